@@ -0,0 +1,55 @@
+//! Compares the serde and the streaming parsers of EntSOE documents:
+//! `cargo bench --bench entsoe_xml --features bench`
+
+use std::{hint::black_box, time::Instant};
+
+use chrono::NaiveDate;
+use emarket::entsoe_xml::{fixture, parse_document, read_document};
+
+/// parses of one case, the best run is reported
+const RUNS: usize = 5;
+
+fn main() {
+    let start = NaiveDate::from_ymd_opt(2023, 12, 31)
+        .unwrap()
+        .and_hms_opt(23, 0, 0)
+        .unwrap();
+    let cases = [
+        ("week, 60 min", fixture::document(start, 7, 60, 1)),
+        ("year, 60 min", fixture::document(start, 365, 60, 1)),
+        ("year, 15 min", fixture::document(start, 365, 15, 1)),
+        (
+            "year, 15 min, 4 series",
+            fixture::document(start, 365, 15, 4),
+        ),
+    ];
+    println!(
+        "{:<24} {:>9} {:>9} {:>12} {:>12} {:>8}",
+        "document", "MB", "points", "serde ms", "stream ms", "speedup"
+    );
+    for (name, txt) in cases.iter() {
+        let points = read_document(txt.as_bytes()).unwrap().points.len();
+        let serde = best(|| parse_document(txt).unwrap().points.len());
+        let stream = best(|| read_document(txt.as_bytes()).unwrap().points.len());
+        println!(
+            "{:<24} {:>9.2} {:>9} {:>12.1} {:>12.1} {:>7.1}x",
+            name,
+            txt.len() as f64 / 1e6,
+            points,
+            serde,
+            stream,
+            serde / stream
+        );
+    }
+}
+
+/// best time of the runs in millis
+fn best(f: impl Fn() -> usize) -> f64 {
+    (0..RUNS)
+        .map(|_| {
+            let now = Instant::now();
+            black_box(f());
+            now.elapsed().as_secs_f64() * 1000.0
+        })
+        .fold(f64::MAX, f64::min)
+}
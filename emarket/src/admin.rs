@@ -0,0 +1,256 @@
+use std::{error::Error, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use emarket::{
+    data::Data,
+    gaps::{GapReport, Gaps},
+    jobs::{JobInfo, JobKind, Jobs},
+    quality::Quality,
+    quarantine::{Entry, Quarantine, Status},
+    utils::to_time,
+};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::{net::TcpListener, sync::mpsc::Sender};
+use tokio_util::sync::CancellationToken;
+
+pub struct AdminService {
+    pub jobs: Jobs,
+    pub key: String,
+    /// set if the gap scanner runs
+    pub gaps: Option<Gaps>,
+    pub quarantine: Quarantine,
+    /// hourly price saver, gets the released points
+    pub saver: Sender<Data>,
+    /// quality flags saver
+    pub quality: Sender<Data>,
+}
+
+#[derive(Debug, Error)]
+pub enum AdminError {
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("unauthorized")]
+    Unauthorized,
+    #[error("not found")]
+    NotFound,
+    #[error("internal error")]
+    Internal(String),
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            AdminError::BadRequest(ref msg) => {
+                tracing::warn!("{}", msg);
+                StatusCode::BAD_REQUEST
+            }
+            AdminError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AdminError::NotFound => StatusCode::NOT_FOUND,
+            AdminError::Internal(ref msg) => {
+                tracing::error!("{}", msg);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+type AdminResult<T> = std::result::Result<T, AdminError>;
+
+#[derive(Deserialize)]
+pub struct ImportParams {
+    from: u64,
+    to: u64,
+}
+
+#[derive(Deserialize)]
+pub struct AggregateParams {
+    at: u64,
+    aggregator: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct QuarantineParams {
+    status: Option<Status>,
+}
+
+pub async fn start(
+    port: u16,
+    srv: AdminService,
+    cancel_token: CancellationToken,
+) -> Result<(), Box<dyn Error>> {
+    let srv = Arc::new(srv);
+    let app = Router::new()
+        .route("/admin/import", post(import_handler))
+        .route("/admin/aggregate", post(aggregate_handler))
+        .route("/admin/jobs", get(jobs_handler))
+        .route("/admin/jobs/:id", get(job_handler))
+        .route("/admin/gaps", get(gaps_handler))
+        .route("/admin/quarantine", get(quarantine_handler))
+        .route("/admin/quarantine/:id/release", post(release_handler))
+        .route("/admin/quarantine/:id/discard", post(discard_handler))
+        .layer(middleware::from_fn_with_state(srv.clone(), auth))
+        .route("/metrics", get(metrics_handler))
+        .with_state(srv);
+
+    tracing::info!(port, "serving admin ...");
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            cancel_token.cancelled().await;
+        })
+        .await?;
+    tracing::info!("exit admin server");
+    Ok(())
+}
+
+async fn auth(
+    State(srv): State<Arc<AdminService>>,
+    request: Request,
+    next: Next,
+) -> AdminResult<Response> {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match token {
+        Some(t) if same_key(t, &srv.key) => Ok(next.run(request).await),
+        _ => Err(AdminError::Unauthorized),
+    }
+}
+
+/// compares in constant time for keys of the same length
+fn same_key(token: &str, key: &str) -> bool {
+    let (token, key) = (token.as_bytes(), key.as_bytes());
+    token.len() == key.len() && token.iter().zip(key).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+async fn import_handler(
+    State(srv): State<Arc<AdminService>>,
+    Json(params): Json<ImportParams>,
+) -> AdminResult<(StatusCode, Json<JobInfo>)> {
+    submit(
+        &srv,
+        JobKind::Import {
+            from: to_time(params.from),
+            to: to_time(params.to),
+        },
+    )
+}
+
+async fn aggregate_handler(
+    State(srv): State<Arc<AdminService>>,
+    Json(params): Json<AggregateParams>,
+) -> AdminResult<(StatusCode, Json<JobInfo>)> {
+    submit(
+        &srv,
+        JobKind::Aggregate {
+            at: to_time(params.at),
+            aggregator: params.aggregator,
+        },
+    )
+}
+
+fn submit(srv: &AdminService, kind: JobKind) -> AdminResult<(StatusCode, Json<JobInfo>)> {
+    let res = srv
+        .jobs
+        .submit(kind)
+        .map_err(|e| AdminError::BadRequest(e.to_string()))?;
+    Ok((StatusCode::ACCEPTED, Json(res)))
+}
+
+async fn jobs_handler(State(srv): State<Arc<AdminService>>) -> AdminResult<Json<Vec<JobInfo>>> {
+    Ok(Json(srv.jobs.list()))
+}
+
+async fn gaps_handler(State(srv): State<Arc<AdminService>>) -> AdminResult<Json<GapReport>> {
+    let gaps = srv.gaps.as_ref().ok_or(AdminError::NotFound)?;
+    Ok(Json(gaps.get()))
+}
+
+async fn quarantine_handler(
+    State(srv): State<Arc<AdminService>>,
+    Query(params): Query<QuarantineParams>,
+) -> AdminResult<Json<Vec<Entry>>> {
+    Ok(Json(srv.quarantine.list(params.status)))
+}
+
+/// saves the point as an original one and recalculates its buckets
+async fn release_handler(
+    State(srv): State<Arc<AdminService>>,
+    Path(id): Path<u64>,
+) -> AdminResult<Json<Entry>> {
+    let entry = review(&srv, id, Status::Released)?;
+    let data = entry.to_data();
+    srv.saver
+        .send(data.clone())
+        .await
+        .map_err(|e| AdminError::Internal(e.to_string()))?;
+    srv.quality
+        .send(Data {
+            at: data.at,
+            price: Quality::Original.code(),
+        })
+        .await
+        .map_err(|e| AdminError::Internal(e.to_string()))?;
+    srv.jobs
+        .submit(JobKind::Aggregate {
+            at: data.at,
+            aggregator: None,
+        })
+        .map_err(|e| AdminError::Internal(e.to_string()))?;
+    Ok(Json(entry))
+}
+
+async fn discard_handler(
+    State(srv): State<Arc<AdminService>>,
+    Path(id): Path<u64>,
+) -> AdminResult<Json<Entry>> {
+    review(&srv, id, Status::Discarded).map(Json)
+}
+
+fn review(srv: &AdminService, id: u64, status: Status) -> AdminResult<Entry> {
+    srv.quarantine
+        .review(id, status)
+        .map_err(|e| AdminError::BadRequest(e.to_string()))?
+        .ok_or(AdminError::NotFound)
+}
+
+/// prometheus metrics, served without the auth
+async fn metrics_handler() -> String {
+    use prometheus::Encoder;
+    let mut buffer = Vec::new();
+    if let Err(e) = prometheus::TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        log::error!("could not encode prometheus metrics: {e}");
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+async fn job_handler(
+    State(srv): State<Arc<AdminService>>,
+    Path(id): Path<u64>,
+) -> AdminResult<Json<JobInfo>> {
+    srv.jobs.get(id).map(Json).ok_or(AdminError::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_keys() {
+        assert!(same_key("secret", "secret"));
+        assert!(!same_key("secreT", "secret"));
+        assert!(!same_key("secret1", "secret"));
+        assert!(!same_key("", "secret"));
+    }
+}
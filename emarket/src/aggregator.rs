@@ -2,12 +2,13 @@ use async_trait::async_trait;
 use chrono::{prelude::*, Duration};
 use chrono::{Datelike, NaiveDate, NaiveDateTime};
 use chrono_tz::Europe::Vilnius;
-use emarket::data::{Aggregator, DBSaver, Data};
+use emarket::data::{Aggregator, DBSaver, Data, Statistic};
 use std::error::Error;
 use std::ops::Add;
 
 #[derive()]
 pub struct AggregatorByDate {
+    name: String,
     db_loader: Box<dyn DBSaver + Sync + Send>,
     db_saver: Box<dyn DBSaver + Sync + Send>,
     last_imported_time: Option<NaiveDateTime>,
@@ -16,17 +17,48 @@ pub struct AggregatorByDate {
 
 impl AggregatorByDate {
     pub async fn new(
+        name: &str,
         db_loader: Box<dyn DBSaver + Sync + Send>,
         db_saver: Box<dyn DBSaver + Sync + Send>,
         time_func: fn(NaiveDateTime) -> (NaiveDateTime, NaiveDateTime),
     ) -> Result<AggregatorByDate, Box<dyn Error>> {
         Ok(AggregatorByDate {
+            name: name.to_string(),
             db_loader,
             db_saver,
             last_imported_time: None,
             time_func,
         })
     }
+
+    /// aggregates one bucket, returns the time of the last loaded item
+    async fn aggregate(
+        &self,
+        t_from: NaiveDateTime,
+        t_to: NaiveDateTime,
+    ) -> Result<Option<NaiveDateTime>, Box<dyn Error>> {
+        log::info!("aggregate {t_from} to {t_to}");
+        let avg = self
+            .db_loader
+            .aggregate(t_from, t_to, Statistic::Avg)
+            .await?;
+        match avg {
+            Some(v) => {
+                log::info!("save {} at {t_from}", v.value);
+                self.db_saver
+                    .save(&Data {
+                        at: t_from,
+                        price: v.value,
+                    })
+                    .await?;
+                Ok(Some(v.last))
+            }
+            None => {
+                log::info!("no data");
+                Ok(None)
+            }
+        }
+    }
 }
 
 #[async_trait]
@@ -42,27 +74,46 @@ impl Aggregator for AggregatorByDate {
         while time <= last_item_time {
             log::info!("aggregate for {time}");
             let (t_from, t_to) = (self.time_func)(time);
-            log::info!("aggregate {t_from} to {t_to}");
-            let data = self.db_loader.load(t_from, t_to).await?;
-            log::info!("loaded range items {}", data.len());
-            if !data.is_empty() {
-                let last_time = data[data.len() - 1].at;
-                let avg = calc_avg(data);
-                if let Some(v) = avg {
-                    log::info!("save {v} at {t_from}");
-                    self.db_saver
-                        .save(&Data {
-                            at: t_from,
-                            price: v,
-                        })
-                        .await?;
-                }
+            if let Some(last_time) = self.aggregate(t_from, t_to).await? {
                 self.last_imported_time = Some(last_time);
             }
             time = t_to;
         }
         Ok(true)
     }
+
+    async fn recompute(
+        &mut self,
+        at: NaiveDateTime,
+        name: Option<&str>,
+    ) -> Result<bool, Box<dyn Error>> {
+        if name.is_some_and(|n| n != self.name) {
+            return Ok(false);
+        }
+        log::info!("{} recompute for {at}", self.name);
+        let (t_from, t_to) = (self.time_func)(at);
+        self.aggregate(t_from, t_to).await?;
+        Ok(true)
+    }
+
+    async fn refresh(
+        &mut self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<bool, Box<dyn Error>> {
+        log::info!("{} refresh {from} - {to}", self.name);
+        let mut time = from;
+        while time < to {
+            let (t_from, t_to) = (self.time_func)(time);
+            if let Some(last_time) = self.aggregate(t_from, t_to).await? {
+                if self.last_imported_time.is_some_and(|t| t < last_time) {
+                    self.last_imported_time = Some(last_time);
+                }
+            }
+            time = t_to;
+        }
+        Ok(true)
+    }
 }
 
 #[derive()]
@@ -78,14 +129,29 @@ impl Aggregator for Aggregators {
         }
         Ok(true)
     }
-}
 
-fn calc_avg(data: Vec<Data>) -> Option<f64> {
-    if data.is_empty() {
-        return None;
+    async fn recompute(
+        &mut self,
+        at: NaiveDateTime,
+        name: Option<&str>,
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut res = false;
+        for a in self.aggregators.iter_mut() {
+            res |= a.recompute(at, name).await?;
+        }
+        Ok(res)
+    }
+
+    async fn refresh(
+        &mut self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<bool, Box<dyn Error>> {
+        for a in self.aggregators.iter_mut() {
+            a.refresh(from, to).await?;
+        }
+        Ok(true)
     }
-    let sum: f64 = data.iter().map(|x| x.price).sum();
-    Some(sum / (data.len() as f64))
 }
 
 fn default_time() -> Option<NaiveDateTime> {
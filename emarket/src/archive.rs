@@ -0,0 +1,243 @@
+use std::{
+    error::Error,
+    io::{BufRead, BufReader, Lines, Read, Write},
+};
+
+use chrono::{naive::serde::ts_milliseconds, NaiveDateTime};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use crate::{data::Data, revisions::Revision, storage::SeriesInfo};
+
+pub const FORMAT: &str = "emarket-archive";
+/// archives of newer versions are refused
+pub const VERSION: u32 = 1;
+
+/// First line of the archive
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    pub format: String,
+    pub version: u32,
+    #[serde(with = "ts_milliseconds")]
+    pub created: NaiveDateTime,
+    /// importer version
+    pub app: String,
+    /// exported series, `first`/`last` of the selected range
+    pub series: Vec<SeriesInfo>,
+}
+
+impl Header {
+    pub fn new(created: NaiveDateTime, app: &str, series: Vec<SeriesInfo>) -> Header {
+        Header {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            created,
+            app: app.to_string(),
+            series,
+        }
+    }
+}
+
+/// One line of the archive after the header
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Record {
+    Point {
+        series: String,
+        #[serde(with = "ts_milliseconds")]
+        at: NaiveDateTime,
+        price: f64,
+    },
+    Revision {
+        series: String,
+        #[serde(flatten)]
+        revision: Revision,
+    },
+}
+
+impl Record {
+    pub fn point(series: &str, d: &Data) -> Record {
+        Record::Point {
+            series: series.to_string(),
+            at: d.at,
+            price: d.price,
+        }
+    }
+
+    pub fn revision(series: &str, r: &Revision) -> Record {
+        Record::Revision {
+            series: series.to_string(),
+            revision: r.clone(),
+        }
+    }
+}
+
+/// Writes a gzipped JSON Lines archive: the header and a record per line
+pub struct ArchiveWriter<W: Write> {
+    out: GzEncoder<W>,
+    records: u64,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(w: W, header: &Header) -> Result<ArchiveWriter<W>, Box<dyn Error>> {
+        let mut res = ArchiveWriter {
+            out: GzEncoder::new(w, Compression::default()),
+            records: 0,
+        };
+        res.line(header)?;
+        Ok(res)
+    }
+
+    pub fn write(&mut self, record: &Record) -> Result<(), Box<dyn Error>> {
+        self.line(record)?;
+        self.records += 1;
+        Ok(())
+    }
+
+    fn line<T: Serialize>(&mut self, value: &T) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer(&mut self.out, value)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// flushes the compressed stream, returns the number of records
+    pub fn finish(self) -> Result<u64, Box<dyn Error>> {
+        self.out.finish()?.flush()?;
+        Ok(self.records)
+    }
+}
+
+/// Reads an archive record by record, the header is checked on open
+pub struct ArchiveReader<R: Read> {
+    pub header: Header,
+    lines: Lines<BufReader<GzDecoder<R>>>,
+}
+
+impl<R: Read> ArchiveReader<R> {
+    pub fn new(r: R) -> Result<ArchiveReader<R>, Box<dyn Error>> {
+        let mut lines = BufReader::new(GzDecoder::new(r)).lines();
+        let line = lines.next().ok_or("empty archive")??;
+        let header: Header =
+            serde_json::from_str(&line).map_err(|e| format!("wrong archive header: {e}"))?;
+        if header.format != FORMAT {
+            return Err(format!("not an {FORMAT}: '{}'", header.format).into());
+        }
+        if header.version > VERSION {
+            return Err(format!(
+                "archive version {} is newer than supported {VERSION}",
+                header.version
+            )
+            .into());
+        }
+        Ok(ArchiveReader { header, lines })
+    }
+}
+
+impl<R: Read> Iterator for ArchiveReader<R> {
+    type Item = Result<Record, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(v) => v,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(serde_json::from_str(&line).map_err(|e| e.into()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{Duration, NaiveDate};
+
+    use super::*;
+
+    fn at(h: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            + Duration::hours(h)
+    }
+
+    fn header() -> Header {
+        Header::new(
+            at(0),
+            "test",
+            vec![SeriesInfo {
+                name: "np_lt".to_string(),
+                labels: BTreeMap::from([("zone".to_string(), "LT".to_string())]),
+                samples: 1,
+                first: Some(1),
+                last: Some(1),
+            }],
+        )
+    }
+
+    #[test]
+    fn write_read() {
+        let records = vec![
+            Record::point(
+                "np_lt",
+                &Data {
+                    at: at(1),
+                    price: 1.5,
+                },
+            ),
+            Record::revision(
+                "np_lt",
+                &Revision {
+                    at: at(1),
+                    price: 1.5,
+                    revision: 2,
+                    published: Some(at(0)),
+                    fetched: at(0),
+                },
+            ),
+        ];
+        let mut buf = vec![];
+        {
+            let mut w = ArchiveWriter::new(&mut buf, &header()).unwrap();
+            for r in records.iter() {
+                w.write(r).unwrap();
+            }
+            assert_eq!(w.finish().unwrap(), 2);
+        }
+        let r = ArchiveReader::new(buf.as_slice()).unwrap();
+        assert_eq!(r.header, header());
+        let res: Vec<Record> = r.map(|r| r.unwrap()).collect();
+        assert_eq!(res, records);
+    }
+
+    #[test]
+    fn refuses_newer() {
+        let mut h = header();
+        h.version = VERSION + 1;
+        let mut buf = vec![];
+        ArchiveWriter::new(&mut buf, &h).unwrap().finish().unwrap();
+        assert!(ArchiveReader::new(buf.as_slice()).is_err());
+        assert!(ArchiveReader::new(&b"not gzip"[..]).is_err());
+    }
+
+    #[test]
+    fn serializes_point() {
+        let s = serde_json::to_string(&Record::point(
+            "np_lt",
+            &Data {
+                at: at(0),
+                price: 2.0,
+            },
+        ))
+        .unwrap();
+        assert_eq!(
+            s,
+            r#"{"type":"point","series":"np_lt","at":1704067200000,"price":2.0}"#
+        );
+    }
+}
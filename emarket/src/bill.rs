@@ -0,0 +1,409 @@
+use std::{collections::BTreeMap, error::Error, path::Path};
+
+use chrono::{
+    naive::serde::ts_milliseconds, DateTime, Duration, LocalResult, NaiveDateTime, TimeZone,
+};
+use chrono_tz::Europe::Vilnius;
+use serde::{Deserialize, Serialize};
+
+use crate::{data::Data, utils::time_month_vilnius};
+
+/// Consumption of the interval starting at `at` (UTC)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub at: NaiveDateTime,
+    pub kwh: f64,
+}
+
+/// local time formats of the smart-meter exports
+const TIME_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y.%m.%d %H:%M",
+    "%Y/%m/%d %H:%M",
+    "%d.%m.%Y %H:%M",
+];
+
+/// parses a smart-meter CSV export: a local (Vilnius) time, or a date and a time column,
+/// and the kWh in the last column, separated by `;`, `,` or a tab, header lines are skipped
+pub fn parse_csv(txt: &str) -> Result<Vec<Usage>, String> {
+    let mut res: Vec<Usage> = vec![];
+    let mut delimiter = None;
+    for (i, line) in txt.lines().enumerate() {
+        let line = line.trim().trim_start_matches('\u{feff}');
+        if line.is_empty() {
+            continue;
+        }
+        let delimiter = *delimiter.get_or_insert_with(|| {
+            [';', '\t', ',']
+                .into_iter()
+                .find(|d| line.contains(*d))
+                .unwrap_or(';')
+        });
+        let fields: Vec<&str> = line
+            .split(delimiter)
+            .map(|f| f.trim().trim_matches('"'))
+            .collect();
+        let (local, rest) = match parse_local(fields[0]) {
+            Some(v) => (v, &fields[1..]),
+            None => match fields
+                .get(1)
+                .and_then(|t| parse_local(&format!("{} {t}", fields[0])))
+            {
+                Some(v) => (v, &fields[2..]),
+                None if res.is_empty() => continue, // header
+                None => return Err(format!("line {}: wrong time '{}'", i + 1, fields[0])),
+            },
+        };
+        let value = rest
+            .iter()
+            .rev()
+            .find(|v| !v.is_empty())
+            .ok_or_else(|| format!("line {}: no value", i + 1))?;
+        let kwh = match delimiter {
+            ',' => value.parse::<f64>(),
+            _ => value.replace(',', ".").parse::<f64>(),
+        }
+        .map_err(|e| format!("line {}: wrong value '{value}': {e}", i + 1))?;
+        let at = to_utc(local, res.last().map(|u| u.at))
+            .ok_or_else(|| format!("line {}: no such local time {local}", i + 1))?;
+        res.push(Usage { at, kwh });
+    }
+    Ok(res)
+}
+
+fn parse_local(v: &str) -> Option<NaiveDateTime> {
+    TIME_FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(v, f).ok())
+}
+
+/// UTC time of the local one, a repeated autumn hour is the later one if `prev` is already past the first
+fn to_utc(local: NaiveDateTime, prev: Option<NaiveDateTime>) -> Option<NaiveDateTime> {
+    match Vilnius.from_local_datetime(&local) {
+        LocalResult::Single(v) => Some(v.naive_utc()),
+        LocalResult::Ambiguous(first, second) => match prev {
+            Some(prev) if prev >= first.naive_utc() => Some(second.naive_utc()),
+            _ => Some(first.naive_utc()),
+        },
+        LocalResult::None => None,
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonUsage {
+    /// millis or RFC 3339 time
+    at: serde_json::Value,
+    #[serde(alias = "value")]
+    kwh: f64,
+}
+
+/// parses `[{"at": 1704060000000, "kwh": 0.4}]`, `at` may be an RFC 3339 time
+pub fn parse_json(txt: &str) -> Result<Vec<Usage>, String> {
+    let list: Vec<JsonUsage> = serde_json::from_str(txt).map_err(|e| e.to_string())?;
+    list.into_iter()
+        .map(|u| {
+            let at = match &u.at {
+                serde_json::Value::Number(n) => n
+                    .as_i64()
+                    .and_then(DateTime::from_timestamp_millis)
+                    .map(|v| v.naive_utc()),
+                serde_json::Value::String(s) => {
+                    DateTime::parse_from_rfc3339(s).ok().map(|v| v.naive_utc())
+                }
+                _ => None,
+            }
+            .ok_or_else(|| format!("wrong time {}", u.at))?;
+            Ok(Usage { at, kwh: u.kwh })
+        })
+        .collect()
+}
+
+/// sorts the consumption and returns its resolution: 15 or 60 minutes
+pub fn prepare(list: &mut [Usage], interval_end: bool) -> Result<Duration, String> {
+    if list.is_empty() {
+        return Err("no consumption".to_string());
+    }
+    list.sort_by_key(|u| u.at);
+    if let Some(u) = list.iter().find(|u| !u.kwh.is_finite() || u.kwh < 0.0) {
+        return Err(format!("wrong consumption {} at {}", u.kwh, u.at));
+    }
+    let step = list
+        .windows(2)
+        .map(|w| w[1].at - w[0].at)
+        .min()
+        .unwrap_or(Duration::hours(1));
+    if step != Duration::minutes(15) && step != Duration::hours(1) {
+        return Err(format!(
+            "unsupported resolution {} min, expected 15 or 60",
+            step.num_minutes()
+        ));
+    }
+    if let Some(u) = list
+        .iter()
+        .find(|u| u.at.and_utc().timestamp() % step.num_seconds() != 0)
+    {
+        return Err(format!(
+            "{} is not aligned to {} min",
+            u.at,
+            step.num_minutes()
+        ));
+    }
+    if interval_end {
+        list.iter_mut().for_each(|u| u.at -= step);
+    }
+    Ok(step)
+}
+
+/// Consumption and its cost in EUR of an interval, day or month
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Cost {
+    #[serde(with = "ts_milliseconds")]
+    pub at: NaiveDateTime,
+    pub kwh: f64,
+    pub cost: f64,
+    /// average price in EUR/kWh
+    pub price: f64,
+}
+
+/// costs of the intervals by the spot `prices` in EUR/kWh, a price applies until the next one
+pub fn spot_costs(usage: &[Usage], prices: &[Data]) -> Result<Vec<Cost>, String> {
+    let prices: BTreeMap<NaiveDateTime, f64> = prices.iter().map(|d| (d.at, d.price)).collect();
+    usage
+        .iter()
+        .map(|u| {
+            let price = prices
+                .range(..=u.at)
+                .next_back()
+                .filter(|(at, _)| u.at - **at < Duration::hours(1))
+                .map(|(_, p)| *p)
+                .ok_or_else(|| format!("no price for {}", u.at))?;
+            Ok(Cost {
+                at: u.at,
+                kwh: u.kwh,
+                cost: u.kwh * price,
+                price,
+            })
+        })
+        .collect()
+}
+
+/// sums the costs by `bucket`
+pub fn sum(costs: &[Cost], bucket: impl Fn(NaiveDateTime) -> NaiveDateTime) -> Vec<Cost> {
+    let mut buckets: BTreeMap<NaiveDateTime, (f64, f64)> = BTreeMap::new();
+    for c in costs {
+        let v = buckets.entry(bucket(c.at)).or_default();
+        v.0 += c.kwh;
+        v.1 += c.cost;
+    }
+    buckets
+        .into_iter()
+        .map(|(at, (kwh, cost))| Cost {
+            at,
+            kwh,
+            cost,
+            price: if kwh > 0.0 { cost / kwh } else { 0.0 },
+        })
+        .collect()
+}
+
+/// Fixed price offer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Offer {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    /// EUR/kWh, compared with the spot price, so both must include or exclude the same fees
+    pub price: f64,
+    /// EUR per month
+    #[serde(default)]
+    pub monthly_fee: f64,
+}
+
+/// loads the offers from a JSON list
+pub fn load_offers(path: &Path) -> Result<Vec<Offer>, Box<dyn Error>> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OfferCost {
+    pub id: String,
+    pub name: String,
+    pub price: f64,
+    pub cost: f64,
+    /// offer cost minus the spot cost, positive if the spot contract was cheaper
+    pub difference: f64,
+    /// fixed price of the offer costing the same as the spot contract
+    pub break_even_price: f64,
+}
+
+/// months of `[from, to)`, a Vilnius month is counted by the covered part of its time,
+/// so the monthly fees of a short upload are prorated
+pub fn covered_months(from: NaiveDateTime, to: NaiveDateTime) -> f64 {
+    let mut res = 0.0;
+    let mut start = time_month_vilnius(from, 0);
+    while start < to {
+        let end = time_month_vilnius(start, 1);
+        let covered = end.min(to) - start.max(from);
+        res += covered.num_seconds() as f64 / (end - start).num_seconds() as f64;
+        start = end;
+    }
+    res
+}
+
+/// costs of the offers for the consumption of `months` months, see [`covered_months`]
+pub fn compare(offers: &[Offer], spot: &Cost, months: f64) -> Vec<OfferCost> {
+    let fees = |o: &Offer| o.monthly_fee * months;
+    offers
+        .iter()
+        .map(|o| {
+            let cost = spot.kwh * o.price + fees(o);
+            OfferCost {
+                id: o.id.clone(),
+                name: o.name.clone(),
+                price: o.price,
+                cost,
+                difference: cost - spot.cost,
+                break_even_price: match spot.kwh > 0.0 {
+                    true => (spot.cost - fees(o)) / spot.kwh,
+                    false => 0.0,
+                },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use chrono::NaiveDate;
+
+    use crate::utils::time_day_vilnius;
+
+    use super::*;
+
+    fn utc(m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_csv() {
+        let eso = "Data;Laikas;Kiekis, kWh\n2024-01-10;00:00;0,25\n2024-01-10;00:15;0,5\n";
+        let res = parse_csv(eso).unwrap();
+        assert_eq!(
+            res,
+            vec![
+                Usage {
+                    at: utc(1, 9, 22, 0),
+                    kwh: 0.25
+                },
+                Usage {
+                    at: utc(1, 9, 22, 15),
+                    kwh: 0.5
+                }
+            ]
+        );
+        let csv = "time,kwh\n\"2024-01-10 02:00\",1.5\n";
+        assert_eq!(parse_csv(csv).unwrap()[0].kwh, 1.5);
+        assert!(parse_csv("2024-01-10 02:00;x\n").is_err());
+        assert!(parse_csv("2024-01-10 02:00;1\nnot a time;1\n").is_err());
+        // spring gap and the repeated autumn hour
+        assert!(parse_csv("2024-03-31 03:00;1\n").is_err());
+        let res = parse_csv("2024-10-27 03:00;1\n2024-10-27 03:00;2\n").unwrap();
+        assert_eq!(res[1].at - res[0].at, Duration::hours(1));
+    }
+
+    #[test]
+    fn parses_json() {
+        let res = parse_json(
+            r#"[{"at": 1704844800000, "kwh": 1}, {"at": "2024-01-10T02:00:00+02:00", "value": 2}]"#,
+        )
+        .unwrap();
+        assert_eq!(res[0].at, utc(1, 10, 0, 0));
+        assert_eq!(res[1].at, utc(1, 10, 0, 0));
+        assert!(parse_json(r#"[{"at": true, "kwh": 1}]"#).is_err());
+    }
+
+    #[test]
+    fn prepares_usage() {
+        let u = |h, min, kwh| Usage {
+            at: utc(1, 10, h, min),
+            kwh,
+        };
+        let mut list = vec![u(1, 0, 1.0), u(0, 0, 1.0)];
+        assert_eq!(prepare(&mut list, true).unwrap(), Duration::hours(1));
+        assert_eq!(list[0].at, utc(1, 9, 23, 0));
+        assert_eq!(
+            prepare(&mut [u(0, 0, 1.0), u(0, 15, 1.0)], false).unwrap(),
+            Duration::minutes(15)
+        );
+        assert!(prepare(&mut [u(0, 0, 1.0), u(0, 30, 1.0)], false).is_err());
+        assert!(prepare(&mut [u(0, 0, -1.0)], false).is_err());
+        assert!(prepare(&mut [], false).is_err());
+    }
+
+    #[test]
+    fn calculates_costs() {
+        let usage: Vec<Usage> = (0..8)
+            .map(|i| Usage {
+                at: utc(1, 31, 21, 0) + Duration::minutes(15 * i),
+                kwh: 1.0,
+            })
+            .collect();
+        let prices = [
+            Data {
+                at: utc(1, 31, 21, 0),
+                price: 0.1,
+            },
+            Data {
+                at: utc(1, 31, 22, 0),
+                price: 0.3,
+            },
+        ];
+        let costs = spot_costs(&usage, &prices).unwrap();
+        assert_relative_eq!(costs[7].cost, 0.3);
+        assert!(spot_costs(&usage, &prices[1..]).is_err());
+        let days = sum(&costs, |at| time_day_vilnius(at, 0));
+        assert_eq!(days.len(), 2);
+        assert_relative_eq!(days[1].cost, 1.2);
+        let total = &sum(&costs, |at| time_month_vilnius(at, 0))[..];
+        assert_eq!(total.len(), 2);
+        let total = sum(&costs, |_| utc(1, 1, 0, 0)).remove(0);
+        assert_relative_eq!(total.price, 0.2);
+        let offers = [Offer {
+            id: "fixed".to_string(),
+            name: String::new(),
+            price: 0.25,
+            monthly_fee: 0.1,
+        }];
+        let res = compare(&offers, &total, 2.0);
+        assert_relative_eq!(res[0].cost, 2.2);
+        assert_relative_eq!(res[0].difference, 0.6);
+        assert_relative_eq!(res[0].break_even_price, 0.175);
+    }
+
+    #[test]
+    fn prorates_months() {
+        // Vilnius midnights
+        let local = |m: u32, d: u32| utc(m, d, 0, 0) - Duration::hours(2);
+        assert_relative_eq!(covered_months(local(1, 1), local(2, 1)), 1.0);
+        // 10 days over two months
+        assert_relative_eq!(
+            covered_months(local(1, 26), local(2, 5)),
+            6.0 / 31.0 + 4.0 / 29.0
+        );
+        // summer time in april
+        assert_relative_eq!(
+            covered_months(
+                local(4, 1) - Duration::hours(1),
+                local(4, 16) - Duration::hours(1)
+            ),
+            0.5
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime, TimeZone};
+use chrono_tz::{Europe::Vilnius, OffsetComponents};
+use emarket::{
+    data::{DBSaver, Data, Statistic},
+    series::{retention_for, Labels, Retention, RES_FIXED_DAY, RES_HOUR, STAT_AVG},
+    TN_1H, TN_FIXED_DAY,
+};
+use std::error::Error;
+
+use crate::redis::CompactionRule;
+
+const EPSILON: f64 = 1e-6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub at: NaiveDateTime,
+    pub expected: f64,
+    pub got: f64,
+}
+
+/// fixed width rollups maintained by redis: hourly and fixed-offset (standard time) days,
+/// calendar days and months with DST shifts stay in `AggregatorByDate`
+pub fn rules(labels: &Labels, retention: &[Retention]) -> Vec<CompactionRule> {
+    vec![
+        CompactionRule {
+            dest: TN_1H.to_string(),
+            labels: labels.with(RES_HOUR, STAT_AVG),
+            stat: Statistic::Avg,
+            bucket: Duration::hours(1),
+            align: Duration::zero(),
+            retention: retention_for(retention, TN_1H),
+        },
+        CompactionRule {
+            dest: TN_FIXED_DAY.to_string(),
+            labels: labels.with(RES_FIXED_DAY, STAT_AVG),
+            stat: Statistic::Avg,
+            bucket: Duration::days(1),
+            align: fixed_day_align(),
+            retention: retention_for(retention, TN_FIXED_DAY),
+        },
+    ]
+}
+
+/// UTC time of the local midnight in standard (winter) time
+fn fixed_day_align() -> Duration {
+    let winter = NaiveDate::from_ymd_opt(2020, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    let offset = Vilnius.offset_from_utc_datetime(&winter).base_utc_offset();
+    let res = Duration::days(1) - offset;
+    if res >= Duration::days(1) {
+        return res - Duration::days(1);
+    }
+    res
+}
+
+/// verifies redis rollups against the raw series and the calendar days of `AggregatorByDate`,
+/// returns the number of mismatches
+pub async fn check(
+    raw: &(dyn DBSaver + Send + Sync),
+    hourly: &(dyn DBSaver + Send + Sync),
+    fixed_days: &(dyn DBSaver + Send + Sync),
+    calendar_days: &(dyn DBSaver + Send + Sync),
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Result<usize, Box<dyn Error>> {
+    log::info!("check compaction {from} - {to}");
+    let raw_data = raw.load(from, to).await?;
+    let mut res = 0;
+    for (name, list) in [
+        (
+            "hourly",
+            compare_fixed(
+                &raw_data,
+                &hourly.load(from, to).await?,
+                Duration::hours(1),
+                Statistic::Avg,
+            ),
+        ),
+        (
+            "fixed day",
+            compare_fixed(
+                &raw_data,
+                &fixed_days.load(from, to).await?,
+                Duration::days(1),
+                Statistic::Avg,
+            ),
+        ),
+        (
+            "calendar day",
+            compare_same(
+                &calendar_days.load(from, to).await?,
+                &fixed_days.load(from, to).await?,
+            ),
+        ),
+    ] {
+        for m in list.iter() {
+            log::warn!(
+                "{name} mismatch at {}: expected {}, got {}",
+                m.at,
+                m.expected,
+                m.got
+            );
+        }
+        res += list.len();
+    }
+    log::info!("compaction check done, mismatches {res}");
+    Ok(res)
+}
+
+/// recalculates each rollup bucket from `raw`, buckets without raw data are skipped
+pub fn compare_fixed(
+    raw: &[Data],
+    compacted: &[Data],
+    bucket: Duration,
+    stat: Statistic,
+) -> Vec<Mismatch> {
+    compacted
+        .iter()
+        .filter_map(|c| {
+            let in_bucket: Vec<Data> = raw
+                .iter()
+                .filter(|r| r.at >= c.at && r.at < c.at + bucket)
+                .cloned()
+                .collect();
+            let expected = stat.calc(&in_bucket)?;
+            mismatch(c.at, expected, c.price)
+        })
+        .collect()
+}
+
+/// compares points with the same timestamps, e.g. calendar days starting at the fixed offset
+pub fn compare_same(expected: &[Data], got: &[Data]) -> Vec<Mismatch> {
+    expected
+        .iter()
+        .filter_map(|e| {
+            let g = got.iter().find(|g| g.at == e.at)?;
+            mismatch(e.at, e.price, g.price)
+        })
+        .collect()
+}
+
+fn mismatch(at: NaiveDateTime, expected: f64, got: f64) -> Option<Mismatch> {
+    if (expected - got).abs() > EPSILON {
+        return Some(Mismatch { at, expected, got });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dt(day: u32, h: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 1, day)
+            .unwrap()
+            .and_hms_opt(h, 0, 0)
+            .unwrap()
+    }
+
+    fn d(at: NaiveDateTime, price: f64) -> Data {
+        Data { at, price }
+    }
+
+    #[test]
+    fn align_winter_midnight() {
+        assert_eq!(fixed_day_align(), Duration::hours(22));
+    }
+
+    #[test]
+    fn compares_fixed() {
+        let raw = vec![d(dt(1, 0), 1.0), d(dt(1, 1), 3.0), d(dt(1, 2), 5.0)];
+        let compacted = vec![d(dt(1, 0), 2.0), d(dt(1, 2), 5.0), d(dt(1, 5), 1.0)];
+        assert!(compare_fixed(&raw, &compacted, Duration::hours(2), Statistic::Avg).is_empty());
+        let compacted = vec![d(dt(1, 0), 2.5)];
+        assert_eq!(
+            compare_fixed(&raw, &compacted, Duration::hours(2), Statistic::Avg),
+            vec![Mismatch {
+                at: dt(1, 0),
+                expected: 2.0,
+                got: 2.5
+            }]
+        );
+    }
+
+    #[test]
+    fn compares_same() {
+        let expected = vec![d(dt(1, 22), 1.0), d(dt(2, 21), 3.0), d(dt(3, 22), 5.0)];
+        let got = vec![d(dt(1, 22), 1.0), d(dt(2, 22), 3.0), d(dt(3, 22), 4.0)];
+        assert_eq!(
+            compare_same(&expected, &got),
+            vec![Mismatch {
+                at: dt(3, 22),
+                expected: 5.0,
+                got: 4.0
+            }]
+        );
+    }
+}
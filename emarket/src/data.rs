@@ -1,6 +1,9 @@
 use async_trait::async_trait;
-use chrono::NaiveDateTime;
-use std::error::Error;
+use chrono::{NaiveDateTime, Utc};
+use std::{collections::BTreeMap, error::Error, sync::Arc};
+use tokio::sync::mpsc::Sender;
+
+use crate::revisions::Revision;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Data {
@@ -14,19 +17,122 @@ impl Data {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Statistic {
+    Avg,
+    Min,
+    Max,
+}
+
+impl Statistic {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Statistic::Avg => "avg",
+            Statistic::Min => "min",
+            Statistic::Max => "max",
+        }
+    }
+
+    pub fn calc(&self, data: &[Data]) -> Option<f64> {
+        if data.is_empty() {
+            return None;
+        }
+        let values = data.iter().map(|x| x.price);
+        let res = match self {
+            Statistic::Avg => values.sum::<f64>() / (data.len() as f64),
+            Statistic::Min => values.fold(f64::INFINITY, f64::min),
+            Statistic::Max => values.fold(f64::NEG_INFINITY, f64::max),
+        };
+        Some(res)
+    }
+}
+
+/// averages points into buckets starting at `bucket(at)`
+pub fn average(data: &[Data], bucket: impl Fn(NaiveDateTime) -> NaiveDateTime) -> Vec<Data> {
+    let mut buckets: BTreeMap<NaiveDateTime, Vec<Data>> = BTreeMap::new();
+    for d in data {
+        buckets.entry(bucket(d.at)).or_default().push(d.clone());
+    }
+    buckets
+        .into_iter()
+        .filter_map(|(at, list)| {
+            Some(Data {
+                at,
+                price: Statistic::Avg.calc(&list)?,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateValue {
+    pub value: f64,
+    /// time of the last point in the range
+    pub last: NaiveDateTime,
+}
+
 #[async_trait]
-pub trait Loader {
+pub trait Loader: Send + Sync {
     async fn live(&self) -> Result<String, Box<dyn Error>>;
     async fn retrieve(
         &self,
         from: NaiveDateTime,
         to: NaiveDateTime,
     ) -> Result<Vec<Data>, Box<dyn Error>>;
+    /// points with their source revision, the default marks them as revision 1 fetched now
+    async fn retrieve_revisions(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Vec<Revision>, Box<dyn Error>> {
+        let fetched = Utc::now().naive_utc();
+        let data = self.retrieve(from, to).await?;
+        Ok(data
+            .into_iter()
+            .map(|d| Revision {
+                at: d.at,
+                price: d.price,
+                revision: 1,
+                published: None,
+                fetched,
+            })
+            .collect())
+    }
+    /// sends the points with their revisions in batches as they are read, e.g. by
+    /// document period, returns the number of points. The default sends them at once
+    async fn stream_revisions(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        sender: Sender<Vec<Revision>>,
+    ) -> Result<usize, Box<dyn Error>> {
+        let res = self.retrieve_revisions(from, to).await?;
+        let len = res.len();
+        if len > 0 {
+            sender
+                .send(res)
+                .await
+                .map_err(|_| "revisions receiver closed")?;
+        }
+        Ok(len)
+    }
 }
 
 #[async_trait]
 pub trait Aggregator {
     async fn work(&mut self, from: NaiveDateTime) -> Result<bool, Box<dyn Error>>;
+    /// recalculates the bucket containing `at`, `name` limits to one aggregator
+    async fn recompute(
+        &mut self,
+        at: NaiveDateTime,
+        name: Option<&str>,
+    ) -> Result<bool, Box<dyn Error>>;
+    /// recalculates all buckets overlapping `[from, to)`
+    async fn refresh(
+        &mut self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<bool, Box<dyn Error>>;
 }
 
 #[async_trait]
@@ -34,11 +140,86 @@ pub trait DBSaver {
     async fn live(&self) -> Result<String, Box<dyn Error>>;
     async fn get_last_time(&self) -> Result<Option<NaiveDateTime>, Box<dyn Error>>;
     async fn save(&self, data: &Data) -> Result<bool, Box<dyn Error>>;
+    /// saves many points, implementations should do it in one round trip
+    async fn save_bulk(&self, data: &[Data]) -> Result<usize, Box<dyn Error>> {
+        for d in data {
+            self.save(d).await?;
+        }
+        Ok(data.len())
+    }
     async fn load(
         &self,
         from: NaiveDateTime,
         to: NaiveDateTime,
     ) -> Result<Vec<Data>, Box<dyn Error>>;
+    /// calculates a statistic over [from, to), the default implementation loads all points
+    async fn aggregate(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        stat: Statistic,
+    ) -> Result<Option<AggregateValue>, Box<dyn Error>> {
+        let data = self.load(from, to).await?;
+        Ok(stat.calc(&data).map(|value| AggregateValue {
+            value,
+            last: data[data.len() - 1].at,
+        }))
+    }
+    /// adds the entries changing the known values to the revision log,
+    /// returns how many were added, the default keeps no history
+    async fn save_revisions(&self, _data: &[Revision]) -> Result<usize, Box<dyn Error>> {
+        Ok(0)
+    }
+    /// revision log entries of points in [from, to)
+    async fn load_revisions(
+        &self,
+        _from: NaiveDateTime,
+        _to: NaiveDateTime,
+    ) -> Result<Vec<Revision>, Box<dyn Error>> {
+        Ok(vec![])
+    }
+}
+
+/// lets one DB be shared by the saver and the aggregators
+#[async_trait]
+impl<T: DBSaver + Send + Sync + ?Sized> DBSaver for Arc<T> {
+    async fn live(&self) -> Result<String, Box<dyn Error>> {
+        self.as_ref().live().await
+    }
+    async fn get_last_time(&self) -> Result<Option<NaiveDateTime>, Box<dyn Error>> {
+        self.as_ref().get_last_time().await
+    }
+    async fn save(&self, data: &Data) -> Result<bool, Box<dyn Error>> {
+        self.as_ref().save(data).await
+    }
+    async fn save_bulk(&self, data: &[Data]) -> Result<usize, Box<dyn Error>> {
+        self.as_ref().save_bulk(data).await
+    }
+    async fn load(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Vec<Data>, Box<dyn Error>> {
+        self.as_ref().load(from, to).await
+    }
+    async fn aggregate(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        stat: Statistic,
+    ) -> Result<Option<AggregateValue>, Box<dyn Error>> {
+        self.as_ref().aggregate(from, to, stat).await
+    }
+    async fn save_revisions(&self, data: &[Revision]) -> Result<usize, Box<dyn Error>> {
+        self.as_ref().save_revisions(data).await
+    }
+    async fn load_revisions(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Vec<Revision>, Box<dyn Error>> {
+        self.as_ref().load_revisions(from, to).await
+    }
 }
 
 #[async_trait]
@@ -48,9 +229,10 @@ pub trait Limiter: Send + Sync {
 
 #[cfg(test)]
 mod tests {
-    use chrono::DateTime;
+    use approx::assert_relative_eq;
+    use chrono::{DateTime, NaiveDate, NaiveDateTime};
 
-    use crate::data::Data;
+    use crate::data::{average, Data, Statistic};
     #[test]
     fn to_string() {
         assert_eq!(
@@ -62,4 +244,57 @@ mod tests {
             "at: 1970-01-01 00:00:00.010, price 1"
         );
     }
+
+    #[test]
+    fn calc_statistic() {
+        let data: Vec<Data> = [1.0, -2.0, 4.0]
+            .iter()
+            .map(|v| Data {
+                at: DateTime::from_timestamp_millis(10).unwrap().naive_utc(),
+                price: *v,
+            })
+            .collect();
+        assert_relative_eq!(Statistic::Avg.calc(&data).unwrap(), 1.0);
+        assert_relative_eq!(Statistic::Min.calc(&data).unwrap(), -2.0);
+        assert_relative_eq!(Statistic::Max.calc(&data).unwrap(), 4.0);
+        assert_eq!(Statistic::Avg.calc(&[]), None);
+    }
+
+    #[test]
+    fn averages() {
+        let at = |day: u32, h: u32| -> NaiveDateTime {
+            NaiveDate::from_ymd_opt(2024, 1, day)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        let data = vec![
+            Data {
+                at: at(1, 0),
+                price: 1.0,
+            },
+            Data {
+                at: at(1, 1),
+                price: 3.0,
+            },
+            Data {
+                at: at(2, 1),
+                price: 5.0,
+            },
+        ];
+        let res = average(&data, |at| at.date().and_hms_opt(0, 0, 0).unwrap());
+        assert_eq!(
+            res,
+            vec![
+                Data {
+                    at: at(1, 0),
+                    price: 2.0
+                },
+                Data {
+                    at: at(2, 0),
+                    price: 5.0
+                },
+            ]
+        );
+    }
 }
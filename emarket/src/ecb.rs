@@ -0,0 +1,136 @@
+use std::{collections::BTreeMap, error::Error};
+
+use emarket::{
+    data::{DBSaver, Data},
+    rates::{self, Rate},
+};
+use serde::Serialize;
+
+use crate::{redis::RetentionOptions, store::Storage};
+
+#[derive(clap::Args, Debug)]
+pub struct RatesArgs {
+    /// ECB reference rates file or URL in the XML or CSV format,
+    /// e.g. https://www.ecb.europa.eu/stats/eurofxref/eurofxref-hist.xml
+    #[arg(long)]
+    pub source: String,
+    /// Currencies to keep, all if not set: PLN,SEK
+    #[arg(long, value_delimiter = ',')]
+    pub currencies: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CurrencyReport {
+    pub currency: String,
+    pub series: String,
+    pub saved: usize,
+    pub first: Option<String>,
+    pub last: Option<String>,
+}
+
+pub async fn load(source: &str) -> Result<String, Box<dyn Error>> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let response = reqwest::get(source).await?.error_for_status()?;
+        return Ok(response.text().await?);
+    }
+    Ok(tokio::fs::read_to_string(source).await?)
+}
+
+/// rates by currency, only `currencies` if not empty
+pub fn group(list: Vec<Rate>, currencies: &[String]) -> BTreeMap<String, Vec<Data>> {
+    let mut res: BTreeMap<String, Vec<Data>> = BTreeMap::new();
+    for r in list {
+        let currency = r.currency.to_uppercase();
+        if currencies.is_empty() || currencies.iter().any(|c| c.eq_ignore_ascii_case(&currency)) {
+            res.entry(currency).or_default().push(r.to_data());
+        }
+    }
+    for data in res.values_mut() {
+        data.sort_by_key(|d| d.at);
+    }
+    res
+}
+
+/// Saves the rates to a daily series per currency, existing dates are overwritten
+pub async fn import(
+    storage: &Storage,
+    args: &RatesArgs,
+    retention: impl Fn(&str) -> RetentionOptions,
+) -> Result<Vec<CurrencyReport>, Box<dyn Error>> {
+    let list = rates::parse(&load(&args.source).await?)?;
+    log::info!("got {} rates", list.len());
+    let mut res = vec![];
+    let by_currency = group(list, &args.currencies);
+    for currency in args.currencies.iter() {
+        if !by_currency.contains_key(&currency.to_uppercase()) {
+            return Err(format!("no {currency} rates in {}", args.source).into());
+        }
+    }
+    for (currency, data) in by_currency {
+        let name = rates::series_name(&currency);
+        let db = storage
+            .series(&name, &rates::labels(&currency), retention(&name))
+            .await?;
+        let saved = db.save_bulk(&data).await?;
+        let date = |d: Option<&Data>| d.map(|d| d.at.date().to_string());
+        log::info!("{currency}: saved {saved} rates to {name}");
+        res.push(CurrencyReport {
+            first: date(data.first()),
+            last: date(data.last()),
+            currency,
+            series: name,
+            saved,
+        });
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use emarket::memory::MemoryStorage;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn imports_rates() {
+        let path = std::env::temp_dir().join(format!("emarket-ecb-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "Date,USD,PLN,SEK,\n2024-01-05,1.0921,4.3580,11.1,\n2024-01-04,1.0953,4.3515,11.2,\n",
+        )
+        .unwrap();
+        let db = MemoryStorage::new();
+        let storage = Storage::Memory(db.clone());
+        let args = |currencies: &[&str]| RatesArgs {
+            source: path.display().to_string(),
+            currencies: currencies.iter().map(|c| c.to_string()).collect(),
+        };
+        let res = import(&storage, &args(&["pln", "SEK"]), |_| {
+            RetentionOptions::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].series, "ecb_pln");
+        assert_eq!(res[0].first.as_deref(), Some("2024-01-04"));
+        let day = |y| {
+            chrono::NaiveDate::from_ymd_opt(y, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        };
+        let data = db
+            .series("ecb_pln")
+            .load(day(2000), day(2100))
+            .await
+            .unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[1].price, 4.358);
+        assert!(
+            import(&storage, &args(&["NOK"]), |_| RetentionOptions::default())
+                .await
+                .is_err()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
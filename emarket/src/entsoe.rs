@@ -1,17 +1,22 @@
 use async_trait::async_trait;
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Utc};
 use emarket::data::{Data, Loader};
+use emarket::revisions::Revision;
 
 use reqwest::{Client, StatusCode};
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::policies::ExponentialBackoff;
 use reqwest_retry::RetryTransientMiddleware;
 
-use serde::{Deserialize, Serialize};
+use emarket::entsoe_xml::{stream_periods, Document, XmlError};
+use emarket::units::{CURRENCY_EUR, UNIT_MWH};
+use futures::TryStreamExt;
 use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
-
-use serde_xml_rs::from_str;
+use tokio::io::AsyncRead;
+use tokio::sync::mpsc::Sender;
+use tokio_util::io::StreamReader;
 
 #[derive(Debug)]
 pub struct EntSOE {
@@ -20,6 +25,23 @@ pub struct EntSOE {
     key: String,
     document: String,
     client: ClientWithMiddleware,
+    units: Units,
+}
+
+/// Expected currency and energy unit of the document prices
+#[derive(Debug, Clone)]
+pub struct Units {
+    pub currency: String,
+    pub unit: String,
+}
+
+impl Default for Units {
+    fn default() -> Self {
+        Units {
+            currency: CURRENCY_EUR.to_string(),
+            unit: UNIT_MWH.to_string(),
+        }
+    }
 }
 
 impl EntSOE {
@@ -49,9 +71,15 @@ impl EntSOE {
             document: document.to_string(),
             domain: domain.to_string(),
             key: key.to_string(),
+            units: Units::default(),
         })
     }
 
+    /// fails the documents with prices in other units
+    pub fn with_units(self, units: Units) -> EntSOE {
+        EntSOE { units, ..self }
+    }
+
     async fn text(
         &self,
         url: &str,
@@ -65,13 +93,74 @@ impl EntSOE {
         let txt = response.text().await?;
         tracing::trace!(txt, status = status.as_u16(), "got");
         if status != want {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("status code: {}, body: {}", status, txt),
-            )));
+            return Err(Box::new(std::io::Error::other(format!(
+                "status code: {}, body: {}",
+                status, txt
+            ))));
         }
         Ok(txt)
     }
+
+    /// response body of the prices in [from, to)
+    async fn body(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> std::result::Result<impl AsyncRead + Send + Unpin + 'static, Box<dyn Error>> {
+        //https://transparency.entsoe.eu/api?securityToken=$(TOKEN)&documentType=A44&in_Domain=10YLT-1001A0008Q&out_Domain=10YLT-1001A0008Q&periodStart=202112312300&periodEnd=202212312300
+        let url = format!(
+            "{}?securityToken={}&documentType={}&in_Domain={}&out_Domain={}&periodStart={}&periodEnd={}",
+            self.url, self.key, self.document, self.domain, self.domain, to_time_str(from), to_time_str(to));
+        tracing::debug!(url, "calling...");
+        let response = self.client.get(&url).send().await?;
+        let status = response.status();
+        if status != StatusCode::OK {
+            let txt = response.text().await?;
+            return Err(Box::new(std::io::Error::other(format!(
+                "status code: {}, body: {}",
+                status, txt
+            ))));
+        }
+        // points are parsed as the body arrives, the body is not kept
+        let body = response.bytes_stream().map_err(std::io::Error::other);
+        Ok(StreamReader::new(Box::pin(body)))
+    }
+
+    async fn document(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> std::result::Result<Document, Box<dyn Error>> {
+        let body = self.body(from, to).await?;
+        let res = read_points(body).await?;
+        res.header.check(&self.units.currency, &self.units.unit)?;
+        tracing::debug!(
+            len = res.points.len(),
+            revision = res.header.revision,
+            "got points"
+        );
+        Ok(res)
+    }
+}
+
+/// collects the points streamed by the parser
+async fn read_points<R: AsyncRead + Send + Unpin + 'static>(
+    reader: R,
+) -> Result<Document, XmlError> {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+    let parser = stream_periods(reader, tx);
+    let collect = async {
+        let mut res = vec![];
+        while let Some(doc) = rx.recv().await {
+            res.extend(doc.points);
+        }
+        res
+    };
+    let (header, points) = tokio::join!(parser, collect);
+    Ok(Document {
+        header: header?,
+        points,
+    })
 }
 
 #[async_trait]
@@ -87,112 +176,136 @@ impl Loader for EntSOE {
         from: NaiveDateTime,
         to: NaiveDateTime,
     ) -> std::result::Result<Vec<Data>, Box<dyn Error>> {
-        //https://transparency.entsoe.eu/api?securityToken=$(TOKEN)&documentType=A44&in_Domain=10YLT-1001A0008Q&out_Domain=10YLT-1001A0008Q&periodStart=202112312300&periodEnd=202212312300
-        let url = format!(
-            "{}?securityToken={}&documentType={}&in_Domain={}&out_Domain={}&periodStart={}&periodEnd={}",
-            self.url, self.key, self.document, self.domain, self.domain, to_time_str(from), to_time_str(to));
-        let txt = self.text(&url, StatusCode::OK).await?;
-        let in_res = from_str::<EntSOEDoc>(txt.as_str())?;
-        tracing::debug!(len = in_res.timeseries.len(), "got timeseries");
-        let res = map_to_data(in_res)?;
-        tracing::debug!(len = res.len(), "extracted points");
-        Ok(res)
+        Ok(self.document(from, to).await?.points)
+    }
+    async fn retrieve_revisions(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> std::result::Result<Vec<Revision>, Box<dyn Error>> {
+        let fetched = Utc::now().naive_utc();
+        Ok(map_to_revisions(self.document(from, to).await?, fetched))
+    }
+    /// sends the points of each document period as soon as it is parsed
+    async fn stream_revisions(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        sender: Sender<Vec<Revision>>,
+    ) -> std::result::Result<usize, Box<dyn Error>> {
+        let body = self.body(from, to).await?;
+        let fetched = Utc::now().naive_utc();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        let parser = stream_periods(body, tx);
+        // the receiver is dropped on an error, so the parser stops
+        let send = async move {
+            let mut len = 0;
+            while let Some(doc) = rx.recv().await {
+                doc.header
+                    .check(&self.units.currency, &self.units.unit)
+                    .map_err(|e| e.to_string())?;
+                len += doc.points.len();
+                sender
+                    .send(map_to_revisions(doc, fetched))
+                    .await
+                    .map_err(|_| "revisions receiver closed".to_string())?;
+            }
+            Ok::<_, String>(len)
+        };
+        let (header, len) = tokio::join!(parser, send);
+        // a failed send stops the parser, its error is the cause
+        let len = len?;
+        let header = header?;
+        tracing::debug!(len, revision = header.revision, "streamed points");
+        Ok(len)
     }
 }
 
-fn to_time_str(t: NaiveDateTime) -> String {
-    t.format("%Y%m%d%H%M").to_string()
-}
-
-fn map_to_data(doc: EntSOEDoc) -> Result<Vec<Data>, Box<dyn Error>> {
-    let res = doc
-        .timeseries
-        .iter()
-        .flat_map(|t| &t.periods)
-        .scan((), |_, p| to_data(p).ok())
-        .flatten()
-        .collect();
-    Ok(res)
+/// Recorded EntSOE responses: an XML document or a directory of them
+#[derive(Debug)]
+pub struct EntSOEFiles {
+    paths: Vec<PathBuf>,
+    units: Units,
 }
 
-fn to_data(p: &EntSOEPeriod) -> Result<Vec<Data>, Box<dyn Error>> {
-    let time = NaiveDateTime::parse_from_str(&p.time_interval.start, "%Y-%m-%dT%H:%MZ")?;
-    // parse <resolution>PT15M</resolution>
-    let resolution = parse_resolution(&p.resolution)?;
-
-    let res = p
-        .points
-        .iter()
-        .map(|p| Data {
-            at: time + (resolution * (i64::from(p.position) - 1) as i32),
-            price: p.price,
+impl EntSOEFiles {
+    pub fn open(path: &Path) -> Result<EntSOEFiles, Box<dyn Error>> {
+        let mut paths = vec![];
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                let p = entry?.path();
+                if p.extension().is_some_and(|e| e == "xml") {
+                    paths.push(p);
+                }
+            }
+            paths.sort();
+        } else if path.is_file() {
+            paths.push(path.to_path_buf());
+        }
+        if paths.is_empty() {
+            return Err(format!("no xml documents in {}", path.display()).into());
+        }
+        Ok(EntSOEFiles {
+            paths,
+            units: Units::default(),
         })
-        .collect();
-    Ok(res)
-}
-
-fn parse_resolution(resolution: &str)   -> Result<chrono::Duration, Box<dyn Error>> {
-    if resolution == "PT5M" {
-        return Ok(chrono::Duration::minutes(5));
-    }
-    if resolution == "PT10M" {
-        return Ok(chrono::Duration::minutes(10));
-    }
-    if resolution == "PT15M" {
-        return Ok(chrono::Duration::minutes(15));
-    }
-    if resolution == "PT30M" {
-        return Ok(chrono::Duration::minutes(30));
     }
-    if resolution == "PT60M" {
-        return Ok(chrono::Duration::hours(1));
-    }
-    Err(Box::new(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        format!("unsupported resolution: {}", resolution),
-    )))
-}
-
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-struct EntSOEDoc {
-    #[serde(rename = "type", default)]
-    pub doc_type: String,
-    #[serde(rename = "TimeSeries", default)]
-    pub timeseries: Vec<EntSOETimeseries>,
-}
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-struct EntSOETimeseries {
-    #[serde(rename = "mRID", default)]
-    pub id: String,
-    #[serde(rename = "Period", default)]
-    pub periods: Vec<EntSOEPeriod>,
+    /// fails the documents with prices in other units
+    pub fn with_units(self, units: Units) -> EntSOEFiles {
+        EntSOEFiles { units, ..self }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-struct EntSOEPeriod {
-    #[serde(rename = "timeInterval")]
-    pub time_interval: EntSOETimeInterval,
-
-    #[serde(rename = "resolution", default)]
-    pub resolution: String,
-
-    #[serde(rename = "Point")]
-    pub points: Vec<EntSOEPoint>,
+#[async_trait]
+impl Loader for EntSOEFiles {
+    async fn live(&self) -> std::result::Result<String, Box<dyn Error>> {
+        Ok(format!("{} documents", self.paths.len()))
+    }
+    /// points of all documents in [from, to), later documents override earlier ones
+    async fn retrieve(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> std::result::Result<Vec<Data>, Box<dyn Error>> {
+        let mut res = std::collections::BTreeMap::new();
+        for path in self.paths.iter() {
+            let file = tokio::fs::File::open(path).await?;
+            let doc = read_points(file)
+                .await
+                .and_then(|doc| {
+                    doc.header
+                        .check(&self.units.currency, &self.units.unit)
+                        .map(|_| doc)
+                })
+                .map_err(|e| format!("{}: {e}", path.display()))?;
+            for d in doc.points {
+                if d.at >= from && d.at < to {
+                    res.insert(d.at, d);
+                }
+            }
+        }
+        Ok(res.into_values().collect())
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-struct EntSOETimeInterval {
-    pub start: String,
-    pub end: String,
+fn to_time_str(t: NaiveDateTime) -> String {
+    t.format("%Y%m%d%H%M").to_string()
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-struct EntSOEPoint {
-    #[serde(rename = "position", default)]
-    pub position: u32,
-    #[serde(rename = "price.amount", default)]
-    pub price: f64,
+/// points with the document revision, a missing revision number counts as the first one
+fn map_to_revisions(doc: Document, fetched: NaiveDateTime) -> Vec<Revision> {
+    let revision = doc.header.revision.max(1);
+    doc.points
+        .into_iter()
+        .map(|d| Revision {
+            at: d.at,
+            price: d.price,
+            revision,
+            published: doc.header.created,
+            fetched,
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -200,8 +313,9 @@ mod tests {
     use approx::assert_relative_eq;
     use chrono::DateTime;
 
-    use crate::entsoe::{map_to_data, to_time_str, EntSOEDoc};
-    use serde_xml_rs::from_str;
+    use crate::entsoe::{map_to_revisions, read_points, to_time_str, EntSOEFiles, Units};
+    use emarket::data::Loader;
+    use emarket::entsoe_xml::{parse_document, read_document};
 
     fn one_sample() -> &'static str {
         r#" <Publication_MarketDocument xmlns="urn:iec62325.351:tc57wg16:451-3:publicationdocument:7:0">
@@ -245,30 +359,22 @@ mod tests {
     }
 
     #[test]
-    fn deseriarelize_entsoe_doc() {
-        let deserialized = from_str::<EntSOEDoc>(one_sample()).unwrap();
-        assert_eq!(deserialized.doc_type, "A44");
-        assert_eq!(deserialized.timeseries.len(), 1);
-        assert_eq!(deserialized.timeseries[0].periods.len(), 1);
-        assert_eq!(
-            deserialized.timeseries[0].periods[0].time_interval.start,
-            "2021-12-31T23:00Z"
-        );
+    fn parses_entsoe_doc() {
+        let res = read_document(one_sample().as_bytes()).unwrap();
+        assert_eq!(res.header.revision, 1);
         assert_eq!(
-            deserialized.timeseries[0].periods[0].time_interval.end,
-            "2022-01-01T23:00Z"
+            res.header.created.unwrap().and_utc().timestamp_millis(),
+            1674454666000
         );
-        assert_eq!(deserialized.timeseries[0].periods[0].points.len(), 2);
-        assert_eq!(deserialized.timeseries[0].periods[0].points[0].position, 1);
-        assert_relative_eq!(deserialized.timeseries[0].periods[0].points[0].price, 50.05);
-        assert_eq!(deserialized.timeseries[0].periods[0].points[1].position, 2);
-        assert_relative_eq!(deserialized.timeseries[0].periods[0].points[1].price, 41.33);
+        assert_eq!(res, parse_document(one_sample()).unwrap());
     }
 
-    #[test]
-    fn maps_data() {
-        let deserialized: EntSOEDoc = from_str(one_sample()).unwrap();
-        let res = map_to_data(deserialized).unwrap();
+    #[tokio::test]
+    async fn maps_data() {
+        let res = read_points(std::io::Cursor::new(one_sample()))
+            .await
+            .unwrap()
+            .points;
         assert_eq!(res.len(), 2);
         assert_relative_eq!(res[0].price, 50.05);
         assert_eq!(res[0].at.and_utc().timestamp_millis(), 1640991600000);
@@ -276,6 +382,57 @@ mod tests {
         assert_eq!(res[1].at.and_utc().timestamp_millis(), 1640995200000);
     }
 
+    #[tokio::test]
+    async fn reads_files() {
+        let dir = std::env::temp_dir().join(format!("emarket-entsoe-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.xml"), one_sample()).unwrap();
+        std::fs::write(dir.join("b.txt"), "skipped").unwrap();
+        let loader = EntSOEFiles::open(&dir).unwrap();
+        let from = DateTime::from_timestamp(1640991600, 0).unwrap().naive_utc();
+        let res = loader
+            .retrieve(from, from + chrono::Duration::days(1))
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[1].price, 41.33);
+        let res = loader
+            .retrieve(
+                from + chrono::Duration::hours(1),
+                from + chrono::Duration::days(1),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        let loader = EntSOEFiles::open(&dir).unwrap().with_units(Units {
+            currency: "PLN".to_string(),
+            unit: "MWh".to_string(),
+        });
+        assert!(loader
+            .retrieve(from, from + chrono::Duration::days(1))
+            .await
+            .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(EntSOEFiles::open(&dir).is_err());
+    }
+
+    #[test]
+    fn maps_revisions() {
+        let doc = read_document(one_sample().as_bytes()).unwrap();
+        let fetched = DateTime::from_timestamp_millis(1674458000000)
+            .unwrap()
+            .naive_utc();
+        let res = map_to_revisions(doc, fetched);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[1].at.and_utc().timestamp_millis(), 1640995200000);
+        assert_eq!(res[1].revision, 1);
+        assert_eq!(res[1].fetched, fetched);
+        assert_eq!(
+            res[1].published.unwrap().and_utc().timestamp_millis(),
+            1674454666000
+        );
+    }
+
     #[test]
     fn formats_time() {
         assert_eq!(
@@ -0,0 +1,523 @@
+use std::io::BufRead;
+
+use chrono::{Duration, NaiveDateTime};
+use quick_xml::{events::Event, Reader};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::{io::AsyncRead, sync::mpsc::Sender};
+use tokio_util::io::SyncIoBridge;
+
+use crate::data::Data;
+
+#[derive(Debug, Error)]
+pub enum XmlError {
+    #[error("xml: {0}")]
+    Xml(#[from] quick_xml::Error),
+    #[error("xml: {0}")]
+    Serde(#[from] serde_xml_rs::Error),
+    #[error("invalid document: {0}")]
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Header {
+    /// 0 if the document has no revision number
+    pub revision: u32,
+    pub created: Option<NaiveDateTime>,
+    /// `currency_Unit.name` of the time series, e.g. EUR
+    pub currency: Option<String>,
+    /// `price_Measure_Unit.name` of the time series, e.g. MWH
+    pub unit: Option<String>,
+}
+
+impl Header {
+    /// fails if the document prices are not in `currency` per `unit`,
+    /// missing values are not checked
+    pub fn check(&self, currency: &str, unit: &str) -> Result<(), XmlError> {
+        for (what, got, want) in [
+            ("currency", &self.currency, currency),
+            ("unit", &self.unit, unit),
+        ] {
+            if let Some(got) = got.as_deref().filter(|v| !v.eq_ignore_ascii_case(want)) {
+                return Err(XmlError::Invalid(format!("{what} {got}, expected {want}")));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parsed EntSOE publication document
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    pub header: Header,
+    pub points: Vec<Data>,
+}
+
+/// parses the whole document with serde, keeps the complete text and tree in memory
+pub fn parse_document(txt: &str) -> Result<Document, XmlError> {
+    let doc: EntSOEDoc = serde_xml_rs::from_str(txt)?;
+    let mut points = vec![];
+    for p in doc.timeseries.iter().flat_map(|t| &t.periods) {
+        let start = parse_start(&p.time_interval.start)?;
+        let resolution = parse_resolution(&p.resolution)?;
+        points.extend(p.points.iter().map(|p| Data {
+            at: at(start, resolution, p.position),
+            price: p.price,
+        }));
+    }
+    let mut header = Header {
+        revision: doc.revision,
+        created: parse_created(&doc.created)?,
+        ..Default::default()
+    };
+    for t in doc.timeseries.iter() {
+        set_once(&mut header.currency, &t.currency, "currency")?;
+        set_once(&mut header.unit, &t.unit, "unit")?;
+    }
+    Ok(Document { header, points })
+}
+
+/// parses the document with the streaming reader
+pub fn read_document<R: BufRead>(reader: R) -> Result<Document, XmlError> {
+    let mut reader = PointReader::new(reader);
+    let points = reader.by_ref().collect::<Result<Vec<_>, _>>()?;
+    Ok(Document {
+        header: reader.header(),
+        points,
+    })
+}
+
+/// Streaming parser of the document points, the points are returned as soon as
+/// they are read, the header is read before the first point
+pub struct PointReader<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    state: State,
+}
+
+impl<R: BufRead> PointReader<R> {
+    pub fn new(reader: R) -> PointReader<R> {
+        PointReader {
+            reader: new_reader(reader),
+            buf: Vec::new(),
+            state: State::default(),
+        }
+    }
+
+    /// header fields read so far
+    pub fn header(&self) -> Header {
+        self.state.header.clone()
+    }
+
+    /// number of the period read last, from 1
+    pub fn period(&self) -> usize {
+        self.state.periods
+    }
+}
+
+impl<R: BufRead> Iterator for PointReader<R> {
+    type Item = Result<Data, XmlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            let res = match self.reader.read_event_into(&mut self.buf) {
+                Ok(Event::Eof) => return self.state.end().err().map(Err),
+                Ok(event) => self.state.event(event),
+                Err(e) => Err(e.into()),
+            };
+            match res {
+                Ok(Some(d)) => return Some(Ok(d)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Parses the points of an async source, e.g. a response body, on a blocking thread
+/// and sends the points of each period with the header as soon as the period is read
+pub async fn stream_periods<R>(reader: R, sender: Sender<Document>) -> Result<Header, XmlError>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let reader = std::io::BufReader::new(SyncIoBridge::new(reader));
+    tokio::task::spawn_blocking(move || {
+        let mut reader = PointReader::new(reader);
+        let send = |points: Vec<Data>, header: Header| {
+            sender
+                .blocking_send(Document { header, points })
+                .map_err(|_| XmlError::Invalid("points receiver closed".to_string()))
+        };
+        let (mut points, mut period) = (vec![], 0);
+        while let Some(d) = reader.next() {
+            let d = d?;
+            if reader.period() != period && !points.is_empty() {
+                send(std::mem::take(&mut points), reader.header())?;
+            }
+            period = reader.period();
+            points.push(d);
+        }
+        if !points.is_empty() {
+            send(points, reader.header())?;
+        }
+        Ok(reader.header())
+    })
+    .await
+    .map_err(|e| XmlError::Invalid(e.to_string()))?
+}
+
+fn new_reader<R>(reader: R) -> Reader<R> {
+    let mut res = Reader::from_reader(reader);
+    res.trim_text(true);
+    res
+}
+
+/// elements of the document the parser cares about
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tag {
+    Revision,
+    Created,
+    TimeSeries,
+    Currency,
+    Unit,
+    Period,
+    Interval,
+    Start,
+    Resolution,
+    Point,
+    Position,
+    Price,
+    Other,
+}
+
+impl Tag {
+    fn from_name(name: &[u8]) -> Tag {
+        match name {
+            b"revisionNumber" => Tag::Revision,
+            b"TimeSeries" => Tag::TimeSeries,
+            b"currency_Unit.name" => Tag::Currency,
+            b"price_Measure_Unit.name" => Tag::Unit,
+            b"createdDateTime" => Tag::Created,
+            b"Period" => Tag::Period,
+            b"timeInterval" => Tag::Interval,
+            b"start" => Tag::Start,
+            b"resolution" => Tag::Resolution,
+            b"Point" => Tag::Point,
+            b"position" => Tag::Position,
+            b"price.amount" => Tag::Price,
+            _ => Tag::Other,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    path: Vec<Tag>,
+    header: Header,
+    /// periods started so far
+    periods: usize,
+    start: Option<NaiveDateTime>,
+    resolution: Option<Duration>,
+    position: Option<u32>,
+    price: Option<f64>,
+}
+
+impl State {
+    fn event(&mut self, event: Event) -> Result<Option<Data>, XmlError> {
+        match event {
+            Event::Start(e) => {
+                let tag = Tag::from_name(e.local_name().as_ref());
+                match tag {
+                    Tag::Period => {
+                        (self.start, self.resolution) = (None, None);
+                        self.periods += 1;
+                    }
+                    Tag::Point => (self.position, self.price) = (None, None),
+                    _ => {}
+                }
+                self.path.push(tag);
+            }
+            Event::End(_) => {
+                let tag = self.path.pop();
+                if tag == Some(Tag::Point) {
+                    return self.point().map(Some);
+                }
+            }
+            Event::Text(e) => {
+                let txt = e.unescape()?;
+                self.text(txt.trim())?;
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn text(&mut self, txt: &str) -> Result<(), XmlError> {
+        let depth = self.path.len();
+        let parent = depth.checked_sub(2).map(|i| self.path[i]);
+        match (self.path.last(), parent) {
+            (Some(Tag::Revision), _) if depth == 2 => {
+                self.header.revision = parse(txt, "revision")?
+            }
+            (Some(Tag::Created), _) if depth == 2 => self.header.created = parse_created(txt)?,
+            (Some(Tag::Currency), Some(Tag::TimeSeries)) => {
+                set_once(&mut self.header.currency, txt, "currency")?
+            }
+            (Some(Tag::Unit), Some(Tag::TimeSeries)) => {
+                set_once(&mut self.header.unit, txt, "unit")?
+            }
+            (Some(Tag::Start), Some(Tag::Interval)) => self.start = Some(parse_start(txt)?),
+            (Some(Tag::Resolution), Some(Tag::Period)) => {
+                self.resolution = Some(parse_resolution(txt)?)
+            }
+            (Some(Tag::Position), Some(Tag::Point)) => {
+                self.position = Some(parse(txt, "position")?)
+            }
+            (Some(Tag::Price), Some(Tag::Point)) => self.price = Some(parse(txt, "price")?),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn end(&self) -> Result<(), XmlError> {
+        match self.path.is_empty() {
+            true => Ok(()),
+            false => Err(XmlError::Invalid("unexpected end of document".to_string())),
+        }
+    }
+
+    fn point(&self) -> Result<Data, XmlError> {
+        let invalid = |what: &str| XmlError::Invalid(format!("point without {what}"));
+        let start = self.start.ok_or_else(|| invalid("period start"))?;
+        let resolution = self.resolution.ok_or_else(|| invalid("resolution"))?;
+        Ok(Data {
+            at: at(
+                start,
+                resolution,
+                self.position.ok_or_else(|| invalid("position"))?,
+            ),
+            price: self.price.ok_or_else(|| invalid("price"))?,
+        })
+    }
+}
+
+/// sets the value of the first time series, all series must have the same one
+fn set_once(value: &mut Option<String>, txt: &str, what: &str) -> Result<(), XmlError> {
+    match value.as_deref() {
+        _ if txt.is_empty() => {}
+        None => *value = Some(txt.to_string()),
+        Some(v) if v == txt => {}
+        Some(v) => {
+            return Err(XmlError::Invalid(format!(
+                "time series with different {what}: {v}, {txt}"
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn at(start: NaiveDateTime, resolution: Duration, position: u32) -> NaiveDateTime {
+    start + resolution * (i64::from(position) - 1) as i32
+}
+
+fn parse<T: std::str::FromStr>(txt: &str, what: &str) -> Result<T, XmlError> {
+    txt.parse()
+        .map_err(|_| XmlError::Invalid(format!("{what}: {txt}")))
+}
+
+fn parse_start(txt: &str) -> Result<NaiveDateTime, XmlError> {
+    NaiveDateTime::parse_from_str(txt, "%Y-%m-%dT%H:%MZ")
+        .map_err(|e| XmlError::Invalid(format!("start {txt}: {e}")))
+}
+
+fn parse_created(txt: &str) -> Result<Option<NaiveDateTime>, XmlError> {
+    match txt {
+        "" => Ok(None),
+        v => NaiveDateTime::parse_from_str(v, "%Y-%m-%dT%H:%M:%SZ")
+            .map(Some)
+            .map_err(|e| XmlError::Invalid(format!("created {v}: {e}"))),
+    }
+}
+
+fn parse_resolution(resolution: &str) -> Result<Duration, XmlError> {
+    match resolution {
+        "PT5M" => Ok(Duration::minutes(5)),
+        "PT10M" => Ok(Duration::minutes(10)),
+        "PT15M" => Ok(Duration::minutes(15)),
+        "PT30M" => Ok(Duration::minutes(30)),
+        "PT60M" => Ok(Duration::hours(1)),
+        _ => Err(XmlError::Invalid(format!(
+            "unsupported resolution: {resolution}"
+        ))),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct EntSOEDoc {
+    #[serde(rename = "revisionNumber", default)]
+    pub revision: u32,
+    #[serde(rename = "createdDateTime", default)]
+    pub created: String,
+    #[serde(rename = "TimeSeries", default)]
+    pub timeseries: Vec<EntSOETimeseries>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct EntSOETimeseries {
+    #[serde(rename = "currency_Unit.name", default)]
+    pub currency: String,
+    #[serde(rename = "price_Measure_Unit.name", default)]
+    pub unit: String,
+    #[serde(rename = "Period", default)]
+    pub periods: Vec<EntSOEPeriod>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct EntSOEPeriod {
+    #[serde(rename = "timeInterval")]
+    pub time_interval: EntSOETimeInterval,
+    #[serde(rename = "resolution", default)]
+    pub resolution: String,
+    #[serde(rename = "Point")]
+    pub points: Vec<EntSOEPoint>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct EntSOETimeInterval {
+    pub start: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct EntSOEPoint {
+    #[serde(rename = "position", default)]
+    pub position: u32,
+    #[serde(rename = "price.amount", default)]
+    pub price: f64,
+}
+
+/// Generated documents for tests and benchmarks
+#[cfg(any(test, feature = "bench"))]
+pub mod fixture {
+    use std::fmt::Write;
+
+    use chrono::{Duration, NaiveDateTime};
+
+    /// document of `series` time series with daily periods of `days` days from `start`,
+    /// `minutes` is the resolution
+    pub fn document(start: NaiveDateTime, days: i64, minutes: i64, series: usize) -> String {
+        let mut res = String::from(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<Publication_MarketDocument xmlns="urn:iec62325.351:tc57wg16:451-3:publicationdocument:7:3">
+  <mRID>fixture</mRID>
+  <revisionNumber>2</revisionNumber>
+  <type>A44</type>
+  <createdDateTime>2024-01-01T12:00:00Z</createdDateTime>
+"#,
+        );
+        let per_day = 24 * 60 / minutes;
+        for s in 0..series {
+            let _ = write!(
+                res,
+                "  <TimeSeries>\n    <mRID>{}</mRID>\n    <currency_Unit.name>EUR</currency_Unit.name>\n    <price_Measure_Unit.name>MWH</price_Measure_Unit.name>\n",
+                s + 1
+            );
+            for day in 0..days {
+                let from = start + Duration::days(day);
+                let _ = write!(
+                    res,
+                    "    <Period>\n      <timeInterval>\n        <start>{}</start>\n        <end>{}</end>\n      </timeInterval>\n      <resolution>PT{}M</resolution>\n",
+                    from.format("%Y-%m-%dT%H:%MZ"),
+                    (from + Duration::days(1)).format("%Y-%m-%dT%H:%MZ"),
+                    minutes
+                );
+                for p in 1..=per_day {
+                    let price = ((day * per_day + p) % 997) as f64 / 10.0 - 20.0;
+                    let _ = write!(
+                        res,
+                        "      <Point>\n        <position>{p}</position>\n        <price.amount>{price}</price.amount>\n      </Point>\n"
+                    );
+                }
+                res.push_str("    </Period>\n");
+            }
+            res.push_str("  </TimeSeries>\n");
+        }
+        res.push_str("</Publication_MarketDocument>\n");
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn start() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 12, 31)
+            .unwrap()
+            .and_hms_opt(23, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn streams_as_serde() {
+        let txt = fixture::document(start(), 3, 15, 2);
+        let expected = parse_document(&txt).unwrap();
+        assert_eq!(expected.points.len(), 2 * 3 * 96);
+        assert_eq!(expected.header.revision, 2);
+        assert_eq!(expected.header.currency.as_deref(), Some("EUR"));
+        assert_eq!(expected.header.unit.as_deref(), Some("MWH"));
+        assert!(expected.header.check("EUR", "MWh").is_ok());
+        assert!(expected.header.check("PLN", "MWh").is_err());
+        assert!(Header::default().check("PLN", "kWh").is_ok());
+        let res = read_document(txt.as_bytes()).unwrap();
+        assert_eq!(res, expected);
+        assert_eq!(res.points[1].at, start() + Duration::minutes(15));
+        assert_eq!(res.points[96].at, start() + Duration::days(1));
+    }
+
+    #[tokio::test]
+    async fn streams_periods() {
+        let txt = fixture::document(start(), 3, 60, 1);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let parser = tokio::spawn(stream_periods(std::io::Cursor::new(txt.clone()), tx));
+        let mut res = vec![];
+        while let Some(doc) = rx.recv().await {
+            assert_eq!(doc.points.len(), 24);
+            assert_eq!(doc.header.currency.as_deref(), Some("EUR"));
+            res.extend(doc.points);
+        }
+        let header = parser.await.unwrap().unwrap();
+        let expected = parse_document(&txt).unwrap();
+        assert_eq!(res, expected.points);
+        assert_eq!(header, expected.header);
+
+        let (tx, rx) = tokio::sync::mpsc::channel(10);
+        drop(rx);
+        assert!(stream_periods(std::io::Cursor::new(txt), tx).await.is_err());
+    }
+
+    #[test]
+    fn fails_on_broken_points() {
+        let txt = fixture::document(start(), 1, 60, 1);
+        let res = read_document(txt.replace("PT60M", "P1D").as_bytes());
+        assert!(matches!(res, Err(XmlError::Invalid(_))));
+        let res = read_document(
+            txt.replacen("<price.amount>", "<x>", 1)
+                .replacen("</price.amount>", "</x>", 1)
+                .as_bytes(),
+        );
+        assert!(res.unwrap_err().to_string().contains("without price"));
+        let res = read_document(txt.replacen("<position>1", "<position>x", 1).as_bytes());
+        assert!(res.is_err());
+        let res = read_document(&txt.as_bytes()[..txt.len() / 2]);
+        assert!(res.is_err());
+        let res = read_document(txt.replacen(">EUR<", ">PLN<", 1).as_bytes());
+        assert_eq!(res.unwrap().header.currency.as_deref(), Some("PLN"));
+        let two = fixture::document(start(), 1, 60, 2).replacen(">EUR<", ">PLN<", 1);
+        let res = read_document(two.as_bytes());
+        assert!(res.unwrap_err().to_string().contains("different currency"));
+        assert!(parse_document(&two).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{naive::serde::ts_milliseconds, naive::serde::ts_milliseconds_option};
+use chrono::{Duration, NaiveDateTime, Utc};
+use prometheus::{IntGauge, Registry};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    data::{DBSaver, Data},
+    jobs::{JobKind, Jobs},
+};
+
+/// Missing interval of a series, `to` is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct Gap {
+    #[serde(with = "ts_milliseconds")]
+    pub from: NaiveDateTime,
+    #[serde(with = "ts_milliseconds")]
+    pub to: NaiveDateTime,
+}
+
+impl Gap {
+    /// missing points of the gap
+    pub fn points(&self, step: Duration) -> i64 {
+        (self.to - self.from).num_milliseconds() / step.num_milliseconds().max(1)
+    }
+}
+
+/// intervals between the points longer than `step`, `data` must be sorted
+pub fn find(data: &[Data], step: Duration) -> Vec<Gap> {
+    data.windows(2)
+        .filter(|w| w[1].at - w[0].at > step)
+        .map(|w| Gap {
+            from: w[0].at + step,
+            to: w[1].at,
+        })
+        .collect()
+}
+
+/// Result of the last gap scan
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GapReport {
+    #[serde(with = "ts_milliseconds_option")]
+    pub scanned: Option<NaiveDateTime>,
+    #[serde(with = "ts_milliseconds_option")]
+    pub from: Option<NaiveDateTime>,
+    #[serde(with = "ts_milliseconds_option")]
+    pub to: Option<NaiveDateTime>,
+    pub gaps: Vec<Gap>,
+    /// missing points in all gaps
+    pub missing: i64,
+}
+
+/// Remaining gaps shared by the scanner with the admin API and metrics
+#[derive(Clone)]
+pub struct Gaps {
+    report: Arc<Mutex<GapReport>>,
+    count: IntGauge,
+    missing: IntGauge,
+}
+
+impl Gaps {
+    pub fn new() -> Result<Gaps, prometheus::Error> {
+        Ok(Gaps {
+            report: Arc::new(Mutex::new(GapReport::default())),
+            count: IntGauge::new("importer_gaps", "Gaps in the stored hourly prices.")?,
+            missing: IntGauge::new(
+                "importer_missing_points",
+                "Missing points in the gaps of the stored hourly prices.",
+            )?,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<(), prometheus::Error> {
+        registry.register(Box::new(self.count.clone()))?;
+        registry.register(Box::new(self.missing.clone()))?;
+        Ok(())
+    }
+
+    pub fn get(&self) -> GapReport {
+        match self.report.lock() {
+            Ok(r) => r.clone(),
+            Err(_) => GapReport::default(),
+        }
+    }
+
+    fn set(&self, report: GapReport) {
+        self.count.set(report.gaps.len() as i64);
+        self.missing.set(report.missing);
+        if let Ok(mut r) = self.report.lock() {
+            *r = report;
+        }
+    }
+}
+
+pub struct ScanOptions {
+    pub interval: std::time::Duration,
+    /// scanned range before the last stored point
+    pub lookback: Duration,
+    /// expected distance between the points
+    pub step: Duration,
+    /// refetches of one gap before giving up
+    pub max_attempts: u32,
+}
+
+/// Scans the series for gaps every `interval` and queues import jobs for them,
+/// the buckets of a closed gap are recalculated with aggregate jobs
+pub async fn scanner_start(
+    db: Box<dyn DBSaver + Send + Sync>,
+    jobs: Jobs,
+    gaps: Gaps,
+    options: ScanOptions,
+    cancel: CancellationToken,
+) -> Result<(), String> {
+    log::info!("start gap scanner loop, every {:?}", options.interval);
+    let mut attempts: HashMap<Gap, u32> = HashMap::new();
+    loop {
+        match scan(db.as_ref(), &options).await {
+            Ok(report) => {
+                log::info!(
+                    "found {} gaps, {} missing points",
+                    report.gaps.len(),
+                    report.missing
+                );
+                refetch(&jobs, &report.gaps, &mut attempts, options.max_attempts);
+                gaps.set(report);
+            }
+            Err(err) => log::error!("gap scan: {err}"),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(options.interval) => {},
+            _ = cancel.cancelled() => break,
+        }
+    }
+    log::info!("exit gap scanner loop");
+    Ok(())
+}
+
+async fn scan(
+    db: &(dyn DBSaver + Send + Sync),
+    options: &ScanOptions,
+) -> Result<GapReport, Box<dyn std::error::Error>> {
+    let Some(last) = db.get_last_time().await? else {
+        return Ok(GapReport::default());
+    };
+    let to = last + options.step;
+    let from = to - options.lookback;
+    let data = db.load(from, to).await?;
+    let gaps = find(&data, options.step);
+    Ok(GapReport {
+        scanned: Some(Utc::now().naive_utc()),
+        from: Some(from),
+        to: Some(to),
+        missing: gaps.iter().map(|g| g.points(options.step)).sum(),
+        gaps,
+    })
+}
+
+/// queues imports of the gaps, aggregates the days of the refetched gaps that are closed
+fn refetch(jobs: &Jobs, gaps: &[Gap], attempts: &mut HashMap<Gap, u32>, max_attempts: u32) {
+    let closed: Vec<Gap> = attempts
+        .keys()
+        .filter(|g| !gaps.contains(g))
+        .copied()
+        .collect();
+    for gap in closed {
+        attempts.remove(&gap);
+        log::info!("gap {} - {} is closed", gap.from, gap.to);
+        // every day of the gap and the day of its last point
+        let last = gap.to - Duration::milliseconds(1);
+        let mut at = gap.from;
+        while at < last {
+            submit(
+                jobs,
+                JobKind::Aggregate {
+                    at,
+                    aggregator: None,
+                },
+            );
+            at += Duration::days(1);
+        }
+        submit(
+            jobs,
+            JobKind::Aggregate {
+                at: last,
+                aggregator: None,
+            },
+        );
+    }
+    for gap in gaps {
+        let n = attempts.entry(*gap).or_default();
+        if *n >= max_attempts {
+            continue;
+        }
+        *n += 1;
+        if *n == max_attempts {
+            log::warn!("gap {} - {}: last refetch attempt", gap.from, gap.to);
+        }
+        submit(
+            jobs,
+            JobKind::Import {
+                from: gap.from,
+                to: gap.to,
+            },
+        );
+    }
+}
+
+fn submit(jobs: &Jobs, kind: JobKind) {
+    if let Err(err) = jobs.submit(kind) {
+        log::warn!("gap job: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn at(h: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            + Duration::hours(h)
+    }
+
+    fn d(h: i64) -> Data {
+        Data {
+            at: at(h),
+            price: 1.0,
+        }
+    }
+
+    fn gap(from: i64, to: i64) -> Gap {
+        Gap {
+            from: at(from),
+            to: at(to),
+        }
+    }
+
+    #[test]
+    fn finds_gaps() {
+        let step = Duration::hours(1);
+        let data = vec![d(1), d(2), d(5), d(6), d(9)];
+        assert_eq!(find(&data, step), vec![gap(3, 5), gap(7, 9)]);
+        assert_eq!(find(&data[..2], step), vec![]);
+        assert_eq!(find(&[], step), vec![]);
+        assert_eq!(gap(3, 5).points(step), 2);
+    }
+
+    #[tokio::test]
+    async fn queues_refetch() {
+        let (tx_i, mut rx_i) = tokio::sync::mpsc::channel(10);
+        let (tx_a, mut rx_a) = tokio::sync::mpsc::channel(10);
+        let jobs = Jobs::new(tx_i, tx_a);
+        let mut attempts = HashMap::new();
+        refetch(&jobs, &[gap(3, 5)], &mut attempts, 2);
+        refetch(&jobs, &[gap(3, 5)], &mut attempts, 2);
+        refetch(&jobs, &[gap(3, 5)], &mut attempts, 2);
+        assert_eq!(
+            rx_i.recv().await.unwrap().kind,
+            JobKind::Import {
+                from: at(3),
+                to: at(5)
+            }
+        );
+        rx_i.recv().await.unwrap();
+        assert!(rx_i.try_recv().is_err());
+
+        refetch(&jobs, &[], &mut attempts, 2);
+        assert_eq!(
+            rx_a.recv().await.unwrap().kind,
+            JobKind::Aggregate {
+                at: at(3),
+                aggregator: None
+            }
+        );
+        assert_eq!(
+            rx_a.recv().await.unwrap().kind,
+            JobKind::Aggregate {
+                at: at(5) - Duration::milliseconds(1),
+                aggregator: None
+            }
+        );
+        assert!(rx_a.try_recv().is_err());
+        assert!(attempts.is_empty());
+    }
+
+    #[test]
+    fn keeps_report() {
+        let gaps = Gaps::new().unwrap();
+        gaps.set(GapReport {
+            gaps: vec![gap(3, 5)],
+            missing: 2,
+            ..Default::default()
+        });
+        assert_eq!(gaps.get().missing, 2);
+        assert_eq!(gaps.missing.get(), 2);
+        assert_eq!(gaps.count.get(), 1);
+        let registry = Registry::new();
+        gaps.register(&registry).unwrap();
+        assert_eq!(registry.gather().len(), 2);
+    }
+}
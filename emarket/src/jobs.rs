@@ -0,0 +1,233 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+const KEEP_FINISHED: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobKind {
+    Import {
+        #[serde(with = "chrono::naive::serde::ts_milliseconds")]
+        from: NaiveDateTime,
+        #[serde(with = "chrono::naive::serde::ts_milliseconds")]
+        to: NaiveDateTime,
+    },
+    Aggregate {
+        #[serde(with = "chrono::naive::serde::ts_milliseconds")]
+        at: NaiveDateTime,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        aggregator: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", content = "error", rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed(String),
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Done | JobStatus::Failed(_))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub kind: JobKind,
+    #[serde(flatten)]
+    pub status: JobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<u64>,
+    #[serde(with = "chrono::naive::serde::ts_milliseconds")]
+    pub created: NaiveDateTime,
+    #[serde(with = "chrono::naive::serde::ts_milliseconds")]
+    pub updated: NaiveDateTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: u64,
+    pub kind: JobKind,
+}
+
+struct JobsInner {
+    next_id: u64,
+    list: VecDeque<JobInfo>,
+}
+
+/// Registry of admin jobs. Import jobs are routed to the import loop,
+/// aggregate jobs to the aggregate loop, both report back their status here.
+#[derive(Clone)]
+pub struct Jobs {
+    inner: Arc<Mutex<JobsInner>>,
+    import_sender: Sender<Job>,
+    aggregate_sender: Sender<Job>,
+}
+
+pub struct JobQueue {
+    pub receiver: Receiver<Job>,
+    pub jobs: Jobs,
+}
+
+impl JobQueue {
+    pub async fn recv(queue: &mut Option<JobQueue>) -> Option<Job> {
+        match queue {
+            Some(q) => q.receiver.recv().await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+impl Jobs {
+    pub fn new(import_sender: Sender<Job>, aggregate_sender: Sender<Job>) -> Jobs {
+        Jobs {
+            inner: Arc::new(Mutex::new(JobsInner {
+                next_id: 1,
+                list: VecDeque::new(),
+            })),
+            import_sender,
+            aggregate_sender,
+        }
+    }
+
+    pub fn submit(&self, kind: JobKind) -> Result<JobInfo, Box<dyn Error + Send + Sync>> {
+        let sender = match kind {
+            JobKind::Import { from, to } => {
+                if from >= to {
+                    return Err(format!("wrong range: {from} >= {to}").into());
+                }
+                &self.import_sender
+            }
+            JobKind::Aggregate { .. } => &self.aggregate_sender,
+        };
+        let mut inner = self.inner.lock().map_err(|e| e.to_string())?;
+        let now = Utc::now().naive_utc();
+        let info = JobInfo {
+            id: inner.next_id,
+            kind: kind.clone(),
+            status: JobStatus::Queued,
+            items: None,
+            created: now,
+            updated: now,
+        };
+        sender
+            .try_send(Job { id: info.id, kind })
+            .map_err(|e| format!("can't enqueue job: {e}"))?;
+        inner.next_id += 1;
+        inner.list.push_back(info.clone());
+        while inner.list.len() > KEEP_FINISHED
+            && inner.list.front().is_some_and(|f| f.status.is_finished())
+        {
+            inner.list.pop_front();
+        }
+        log::info!("job {} queued: {:?}", info.id, info.kind);
+        Ok(info)
+    }
+
+    pub fn set_status(&self, id: u64, status: JobStatus, items: Option<u64>) {
+        log::info!("job {id}: {status:?}");
+        if let Ok(mut inner) = self.inner.lock() {
+            if let Some(info) = inner.list.iter_mut().find(|f| f.id == id) {
+                info.status = status;
+                info.items = items.or(info.items);
+                info.updated = Utc::now().naive_utc();
+            }
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobInfo> {
+        let inner = self.inner.lock().ok()?;
+        inner.list.iter().find(|f| f.id == id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        match self.inner.lock() {
+            Ok(inner) => inner.list.iter().cloned().collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate};
+
+    use super::*;
+
+    fn at() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn routes_jobs() {
+        let (tx_i, mut rx_i) = tokio::sync::mpsc::channel(10);
+        let (tx_a, mut rx_a) = tokio::sync::mpsc::channel(10);
+        let jobs = Jobs::new(tx_i, tx_a);
+        let info = jobs
+            .submit(JobKind::Import {
+                from: at(),
+                to: at() + Duration::days(1),
+            })
+            .unwrap();
+        assert_eq!(info.id, 1);
+        assert_eq!(info.status, JobStatus::Queued);
+        let info = jobs
+            .submit(JobKind::Aggregate {
+                at: at(),
+                aggregator: None,
+            })
+            .unwrap();
+        assert_eq!(info.id, 2);
+        assert_eq!(rx_i.recv().await.unwrap().id, 1);
+        assert_eq!(rx_a.recv().await.unwrap().id, 2);
+    }
+
+    #[test]
+    fn rejects_wrong_range() {
+        let (tx_i, _rx_i) = tokio::sync::mpsc::channel(10);
+        let (tx_a, _rx_a) = tokio::sync::mpsc::channel(10);
+        let jobs = Jobs::new(tx_i, tx_a);
+        assert!(jobs
+            .submit(JobKind::Import {
+                from: at(),
+                to: at(),
+            })
+            .is_err());
+        assert!(jobs.list().is_empty());
+    }
+
+    #[test]
+    fn updates_status() {
+        let (tx_i, _rx_i) = tokio::sync::mpsc::channel(10);
+        let (tx_a, _rx_a) = tokio::sync::mpsc::channel(10);
+        let jobs = Jobs::new(tx_i, tx_a);
+        let info = jobs
+            .submit(JobKind::Aggregate {
+                at: at(),
+                aggregator: Some("day".to_string()),
+            })
+            .unwrap();
+        jobs.set_status(info.id, JobStatus::Running, None);
+        assert_eq!(jobs.get(info.id).unwrap().status, JobStatus::Running);
+        jobs.set_status(info.id, JobStatus::Failed("err".to_string()), Some(2));
+        let res = jobs.get(info.id).unwrap();
+        assert_eq!(res.status, JobStatus::Failed("err".to_string()));
+        assert_eq!(res.items, Some(2));
+        assert!(jobs.get(100).is_none());
+    }
+}
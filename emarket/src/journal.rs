@@ -0,0 +1,128 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::{data::Data, utils::to_time};
+
+/// Write-ahead journal for points not yet saved to the DB.
+/// One point per line: `<millis>\t<price>`.
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub async fn new(path: &Path) -> Result<Journal, Box<dyn Error>> {
+        if let Some(dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).await?;
+        }
+        log::info!("journal {}", path.display());
+        Ok(Journal {
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub async fn append(&self, data: &[Data]) -> Result<(), Box<dyn Error>> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let lines: String = data
+            .iter()
+            .map(|d| format!("{}\t{}\n", d.at.and_utc().timestamp_millis(), d.price))
+            .collect();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(lines.as_bytes()).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+
+    pub async fn load(&self) -> Result<Vec<Data>, Box<dyn Error>> {
+        let content = match fs::read_to_string(&self.path).await {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let res = content
+            .lines()
+            .filter_map(|l| {
+                let res = parse_line(l);
+                if res.is_none() {
+                    log::warn!("skip journal line '{l}'");
+                }
+                res
+            })
+            .collect();
+        Ok(res)
+    }
+
+    pub async fn clear(&self) -> Result<(), Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)
+            .await?;
+        file.sync_all().await?;
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<Data> {
+    let (at, price) = line.split_once('\t')?;
+    Some(Data {
+        at: to_time(at.parse().ok()?),
+        price: price.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn append_load_clear() {
+        let path = std::env::temp_dir().join(format!("emarket-journal-{}", std::process::id()));
+        let journal = Journal::new(&path).await.unwrap();
+        journal.clear().await.unwrap();
+        let base = NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let data = vec![
+            Data {
+                at: base,
+                price: 1.5,
+            },
+            Data {
+                at: base + Duration::hours(1),
+                price: -2.0,
+            },
+        ];
+        journal.append(&data[..1]).await.unwrap();
+        journal.append(&data[1..]).await.unwrap();
+        assert_eq!(journal.load().await.unwrap(), data);
+        journal.clear().await.unwrap();
+        assert!(journal.load().await.unwrap().is_empty());
+        fs::remove_file(&path).await.unwrap();
+        assert!(journal.load().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn skips_broken_lines() {
+        assert_eq!(parse_line("10\t1.5").unwrap().price, 1.5);
+        assert!(parse_line("10\t").is_none());
+        assert!(parse_line("10").is_none());
+        assert!(parse_line("").is_none());
+    }
+}
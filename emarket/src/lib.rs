@@ -1,33 +1,86 @@
+pub mod archive;
+pub mod bill;
 pub mod data;
+pub mod entsoe_xml;
+pub mod gaps;
+pub mod jobs;
+pub mod journal;
+pub mod memory;
+pub mod quality;
+pub mod quarantine;
+pub mod rates;
+pub mod redis_pool;
+pub mod redis_reader;
+pub mod revisions;
+pub mod schedule;
+pub mod series;
+pub mod sqlite;
+pub mod storage;
+pub mod tariff;
+pub mod units;
 pub mod utils;
+pub mod validation;
 
+use backoff::{backoff::Backoff, ExponentialBackoff};
 use chrono::{Duration, NaiveDateTime, Utc};
 use data::{Aggregator, DBSaver, Data, Limiter, Loader};
-use std::error::Error;
+use jobs::{Job, JobKind, JobQueue, JobStatus};
+use journal::Journal;
+use revisions::Revision;
+use std::{
+    collections::{HashSet, VecDeque},
+    error::Error,
+};
 use tokio::{
     sync::{
         mpsc::{Receiver, Sender},
-        Mutex,
+        oneshot, Mutex,
     },
-    time::sleep,
+    time::Instant,
 };
 use tokio_util::sync::CancellationToken;
+use validation::Validator;
 
 use crate::utils::jitter;
 
 pub const TN_HOUR: &str = "np_lt";
 pub const TN_DAY: &str = "np_lt_d";
 pub const TN_MONTH: &str = "np_lt_m";
+/// redis compaction rollups of TN_HOUR
+pub const TN_1H: &str = "np_lt_1h";
+pub const TN_FIXED_DAY: &str = "np_lt_fd";
+/// quality flags of TN_HOUR
+pub const TN_QUALITY: &str = "np_lt_q";
+
+/// message to the aggregate loop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Imported {
+    /// new prices up to the time, the aggregates move forward
+    Till(NaiveDateTime),
+    /// a range was imported again, its buckets are recalculated
+    Again(NaiveDateTime, NaiveDateTime),
+}
 
 type LimiterM = std::sync::Arc<Mutex<Box<dyn Limiter>>>;
-type ResultM = Result<(), Box<dyn Error>>;
+type ResultM = Result<u64, Box<dyn Error>>;
 
 pub struct WorkingData {
     pub start_from: NaiveDateTime,
     pub loader: Box<dyn Loader>,
     pub limiter: LimiterM,
     pub sender: Sender<Data>,
-    pub import_indicator: Sender<NaiveDateTime>,
+    /// revision log writer, revisions are not kept if not set
+    pub revisions: Option<Sender<Vec<Revision>>>,
+    /// quality flags writer, the flags are not kept if not set
+    pub quality: Option<Sender<Data>>,
+    /// checks the points before saving, all points are saved if not set
+    pub validator: Option<Validator>,
+    pub import_indicator: Sender<Imported>,
+    /// the saver is flushed before the import indicator is sent, not waited for if not set
+    pub flush: Option<Sender<Flush>>,
+    pub admin: Option<JobQueue>,
+    /// stop the loop when there is nothing new to import
+    pub once: bool,
 }
 
 pub async fn run_exit_indicator(
@@ -36,21 +89,21 @@ pub async fn run_exit_indicator(
     exit_ind: tokio::sync::mpsc::UnboundedSender<i32>,
 ) -> ResultM {
     match run(w_data, close_token).await {
-        Ok(_) => {
+        Ok(imported) => {
             log::info!("exit run");
+            Ok(imported)
         }
         Err(err) => {
             log::error!("{}", err);
             log::info!("sending exit signal");
             exit_ind.send(1)?;
             log::info!("sent exit signal");
-            return Err(err);
+            Err(err)
         }
     }
-    Ok(())
 }
 
-pub async fn run(w_data: WorkingData, close_token: CancellationToken) -> ResultM {
+pub async fn run(mut w_data: WorkingData, close_token: CancellationToken) -> ResultM {
     log::info!("Importing: from {}", w_data.start_from);
     log::info!("Test EntSOE is live");
     match w_data.loader.live().await {
@@ -63,25 +116,39 @@ pub async fn run(w_data: WorkingData, close_token: CancellationToken) -> ResultM
     }
     let mut from = w_data.start_from;
     let take_dur = Duration::days(7);
+    let mut admin = w_data.admin.take();
+    let mut total = 0;
+    // the last point of the previous import, it is refetched with the next one
+    let mut saved_till = None;
     loop {
         log::info!("loop");
         if close_token.is_cancelled() {
             log::debug!("cancel detected");
             break;
         }
+        if let Some(queue) = admin.as_mut() {
+            while let Ok(job) = queue.receiver.try_recv() {
+                run_import_job(&w_data, queue, job).await;
+            }
+        }
         let to = from + take_dur;
-        let (last_item_time, imported) = import(&w_data, from, to).await?;
+        let (last_item_time, imported) = import(&w_data, from, to, saved_till).await?;
         log::info!(
             "got last item time {}, imported {}",
             last_item_time,
             imported
         );
+        total += imported;
         let now = Utc::now().naive_utc();
         if imported == 0 && to < now {
             from = from + take_dur - Duration::days(1);
             continue;
         } else if imported == 0 {
             log::info!("no new imports");
+            if w_data.once {
+                log::info!("run once - stop");
+                break;
+            }
             let sleep_time = get_sleep(last_item_time, now, jitter);
             log::info!("sleep till {}", now + sleep_time);
             let sleep = tokio::time::sleep(sleep_time.to_std()?);
@@ -89,17 +156,82 @@ pub async fn run(w_data: WorkingData, close_token: CancellationToken) -> ResultM
             tokio::pin!(sleep);
             tokio::select! {
                 _ = &mut sleep => {},
+                Some(job) = JobQueue::recv(&mut admin) => {
+                    if let Some(queue) = admin.as_ref() {
+                        run_import_job(&w_data, queue, job).await;
+                    }
+                    continue;
+                },
                 _ = close_token.cancelled() => {
                     log::debug!("got cancel event");
                     break;
                 }
             }
         }
+        saved(&w_data).await?;
         log::info!("send import indicator to {last_item_time}");
-        w_data.import_indicator.send(last_item_time).await?;
+        w_data
+            .import_indicator
+            .send(Imported::Till(last_item_time))
+            .await?;
         from = last_item_time;
+        saved_till = Some(last_item_time);
+    }
+    log::info!("exit import loop, imported {total}");
+    Ok(total)
+}
+
+async fn run_import_job(w_data: &WorkingData, queue: &JobQueue, job: Job) {
+    let (from, to) = match job.kind {
+        JobKind::Import { from, to } => (from, to),
+        _ => {
+            queue.jobs.set_status(
+                job.id,
+                JobStatus::Failed("not an import job".to_string()),
+                None,
+            );
+            return;
+        }
+    };
+    queue.jobs.set_status(job.id, JobStatus::Running, None);
+    match reimport(w_data, from, to).await {
+        Ok(items) => queue.jobs.set_status(job.id, JobStatus::Done, Some(items)),
+        Err(err) => queue
+            .jobs
+            .set_status(job.id, JobStatus::Failed(err.to_string()), None),
+    }
+}
+
+async fn reimport(
+    w_data: &WorkingData,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Result<u64, Box<dyn Error>> {
+    let take_dur = Duration::days(7);
+    let mut res = 0;
+    let mut at = from;
+    while at < to {
+        let till = std::cmp::min(at + take_dur, to);
+        let (_, imported) = import(w_data, at, till, None).await?;
+        res += imported;
+        at = till;
+    }
+    saved(w_data).await?;
+    log::info!("send import indicator for {from} - {to}");
+    w_data
+        .import_indicator
+        .send(Imported::Again(from, to))
+        .await?;
+    Ok(res)
+}
+
+/// waits for the saver to write the sent points
+async fn saved(w_data: &WorkingData) -> Result<(), Box<dyn Error>> {
+    if let Some(flush) = w_data.flush.as_ref() {
+        let (tx, rx) = oneshot::channel();
+        flush.send(tx).await?;
+        rx.await?;
     }
-    log::info!("exit import loop");
     Ok(())
 }
 
@@ -118,10 +250,13 @@ fn get_sleep(
     sleep + j_f(Duration::minutes(5))
 }
 
+/// imports `[from, to)`, points till `saved_till` are not sent to the savers again.
+/// The points are checked, filled and sent to the savers by the loaded batches
 async fn import(
     w_data: &WorkingData,
     from: NaiveDateTime,
     to: NaiveDateTime,
+    saved_till: Option<NaiveDateTime>,
 ) -> Result<(NaiveDateTime, u64), Box<dyn Error>> {
     {
         log::info!("wait for import");
@@ -131,49 +266,144 @@ async fn import(
     }
     log::info!("loading data from {}", from);
 
-    let data = w_data.loader.retrieve(from, to).await?;
-    data.iter().for_each(|f| {
-        log::trace!("{}", f.to_str());
-        // w_data.saver.save(f).await;
-    });
-    let c = data.len();
-    log::info!("got {} lines", data.len());
-    let data = fix_missing_hours(&data);
-    log::info!("after fixing {} lines", data.len());
+    let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+    let load = async {
+        let res = w_data.loader.stream_revisions(from, to, tx).await;
+        res.map_err(|e| e.to_string())
+    };
+    // the receiver is dropped on an error, so the loader stops
+    let save = async move {
+        let mut batch = Batch::new(from, saved_till);
+        while let Some(revisions) = rx.recv().await {
+            batch
+                .add(w_data, revisions)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok::<_, String>(batch)
+    };
+    let (loaded, batch) = tokio::join!(load, save);
+    let batch = batch?;
+    loaded?;
+    log::info!("got {} lines, {} after fixing", batch.count, batch.sent);
+    if from == batch.last {
+        // nothing new
+        return Ok((batch.last, 0));
+    }
+    Ok((batch.last, batch.count.try_into()?))
+}
 
-    let mut res = from;
+/// state of an import between the loaded batches
+struct Batch {
+    saved_till: Option<NaiveDateTime>,
+    /// jump history of the validator, seeded on the first batch
+    history: Option<VecDeque<f64>>,
+    /// last kept point, the gap to the next batch is forward filled from it
+    prev: Option<Data>,
+    /// held times after `prev`, they stay gaps when a later batch fills up to its points
+    held: HashSet<NaiveDateTime>,
+    /// time of the last sent point
+    last: NaiveDateTime,
+    /// kept points
+    count: usize,
+    /// sent points with the filled ones
+    sent: usize,
+}
 
-    for line in data {
-        if res < line.at {
-            res = line.at;
+impl Batch {
+    fn new(from: NaiveDateTime, saved_till: Option<NaiveDateTime>) -> Batch {
+        Batch {
+            saved_till,
+            history: None,
+            prev: None,
+            held: HashSet::new(),
+            last: from,
+            count: 0,
+            sent: 0,
         }
-        w_data.sender.send(line).await?;
     }
-    log::debug!("send lines to save");
-    if from == res {
-        // nothing new
-        return Ok((res, 0));
+
+    async fn add(
+        &mut self,
+        w_data: &WorkingData,
+        mut revisions: Vec<Revision>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut data: Vec<Data> = revisions.iter().map(Revision::to_data).collect();
+        if let Some(validator) = w_data.validator.as_ref() {
+            let history = match self.history.as_mut() {
+                Some(h) => h,
+                None => self.history.insert(validator.history(&data).await),
+            };
+            data = validator.check(history, data)?;
+            // left out points stay gaps, they are not forward filled
+            let kept: HashSet<NaiveDateTime> = data.iter().map(|d| d.at).collect();
+            revisions.retain(|r| {
+                let keep = kept.contains(&r.at);
+                if !keep {
+                    self.held.insert(r.at);
+                }
+                keep
+            });
+        }
+        data.iter().for_each(|f| log::trace!("{}", f.to_str()));
+        self.count += data.len();
+        let last = data.last().cloned();
+        let prev = self.prev.take();
+        let mut data = match prev.clone() {
+            Some(prev) => {
+                data.insert(0, prev);
+                let mut res = fix_missing_hours(&data);
+                res.remove(0);
+                res
+            }
+            None => fix_missing_hours(&data),
+        };
+        // a batch of held points only keeps the anchor
+        self.prev = last.or(prev);
+        data.retain(|d| !self.held.contains(&d.at));
+        if let Some(prev) = self.prev.as_ref() {
+            self.held.retain(|at| *at > prev.at);
+        }
+        let flags = quality::flags(&data, &revisions);
+        let saved_till = self.saved_till;
+        let new = |d: &Data| saved_till.is_none_or(|t| d.at > t);
+
+        for line in data.into_iter().filter(new) {
+            if self.last < line.at {
+                self.last = line.at;
+            }
+            self.sent += 1;
+            w_data.sender.send(line).await?;
+        }
+        if let Some(sender) = w_data.quality.as_ref() {
+            for f in flags.into_iter().filter(new) {
+                sender.send(f).await?;
+            }
+        }
+        if let Some(sender) = w_data.revisions.as_ref().filter(|_| !revisions.is_empty()) {
+            sender.send(revisions).await?;
+        }
+        Ok(())
     }
-    Ok((res, c.try_into()?))
 }
 
-fn fix_missing_hours(data: &Vec<Data>) -> Vec<Data> {
+/// forward fills the hours missing between the points, as in the saved prices
+pub fn fix_missing_hours(data: &[Data]) -> Vec<Data> {
     let mut res = Vec::with_capacity(data.len());
-    if data.len() == 0 {
+    if data.is_empty() {
         return res;
     }
     let mut from = data[0].at;
     let mut prev_price = data[0].price;
     res.push(data[0].clone());
-    for i in 1..data.len() {
-        let d = &data[i];
-        from = from + Duration::hours(1);
+    for d in data.iter().skip(1) {
+        from += Duration::hours(1);
         while from < d.at {
             res.push(Data {
                 at: from,
                 price: prev_price,
             });
-            from = from + Duration::hours(1);
+            from += Duration::hours(1);
         }
         res.push(d.clone());
         from = d.at;
@@ -182,56 +412,847 @@ fn fix_missing_hours(data: &Vec<Data>) -> Vec<Data> {
     res
 }
 
+/// asks the saver to write the points it got so far, answered once they are saved
+pub type Flush = oneshot::Sender<()>;
+
+pub struct SaverOptions {
+    /// write-ahead journal, without it the saver stops on the first failed save
+    pub journal: Option<Journal>,
+    /// max points in one db write
+    pub batch_size: usize,
+    /// max time to wait for a batch to fill
+    pub batch_latency: std::time::Duration,
+    /// flush requests
+    pub flush: Option<Receiver<Flush>>,
+}
+
+impl Default for SaverOptions {
+    fn default() -> Self {
+        SaverOptions {
+            journal: None,
+            batch_size: 1,
+            batch_latency: std::time::Duration::ZERO,
+            flush: None,
+        }
+    }
+}
+
 pub async fn saver_start(
     db: Box<dyn DBSaver + Send + Sync>,
     receiver: &mut Receiver<Data>,
+    mut options: SaverOptions,
 ) -> Result<(), String> {
-    log::info!("start db saver loop");
+    log::info!(
+        "start db saver loop, batch {}, latency {:?}",
+        options.batch_size,
+        options.batch_latency
+    );
+    let batch_size = options.batch_size.max(1);
+    let mut pending = match &options.journal {
+        Some(journal) => journal
+            .load()
+            .await
+            .map_err(|e| format!("journal load err: {e}"))?,
+        None => vec![],
+    };
+    log::info!("loaded {} points from journal", pending.len());
+    let mut retry = ExponentialBackoff {
+        max_interval: std::time::Duration::from_secs(60),
+        max_elapsed_time: None,
+        ..Default::default()
+    };
+    let mut failing = false;
+    let mut flush_at = (!pending.is_empty()).then(Instant::now);
+    let mut flush_requests = options.flush.take();
+    let mut acks: Vec<Flush> = vec![];
     loop {
-        let line = receiver.recv().await;
-        log::trace!("got line");
-        match line {
-            Some(line) => db
-                .save(&line)
-                .await
-                .map(|_v| ())
-                .map_err(|e| format!("save err: {e}"))?,
-            None => break,
+        let wait = async {
+            match flush_at {
+                Some(at) => tokio::time::sleep_until(at).await,
+                None => std::future::pending().await,
+            }
+        };
+        let flush_request = async {
+            match flush_requests.as_mut() {
+                Some(r) => r.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            line = receiver.recv() => match line {
+                Some(line) => {
+                    log::trace!("got line");
+                    receive(receiver, line, batch_size, &options, &mut pending).await?;
+                    if failing {
+                        continue;
+                    }
+                    if pending.len() < batch_size {
+                        flush_at.get_or_insert(Instant::now() + options.batch_latency);
+                        continue;
+                    }
+                }
+                None => break,
+            },
+            Some(ack) = flush_request => {
+                // the points sent before the request are in the channel already
+                if let Ok(line) = receiver.try_recv() {
+                    receive(receiver, line, usize::MAX, &options, &mut pending).await?;
+                }
+                acks.push(ack);
+                if failing {
+                    continue;
+                }
+            },
+            _ = wait => {}
+        }
+        match flush(db.as_ref(), &options, &mut pending).await {
+            Ok(_) => {
+                retry.reset();
+                failing = false;
+                flush_at = None;
+                acks.drain(..).for_each(|ack| {
+                    let _ = ack.send(());
+                });
+            }
+            Err(err) if options.journal.is_some() => {
+                let wait = retry.next_backoff().unwrap_or(retry.max_interval);
+                log::warn!(
+                    "save err: {err}, {} points in journal, retry in {wait:?}",
+                    pending.len()
+                );
+                failing = true;
+                flush_at = Some(Instant::now() + wait);
+            }
+            Err(err) => return Err(format!("save err: {err}")),
         }
     }
+    flush(db.as_ref(), &options, &mut pending)
+        .await
+        .map_err(|e| format!("save err: {e}, {} points not saved", pending.len()))?;
+    acks.drain(..).for_each(|ack| {
+        let _ = ack.send(());
+    });
     log::info!("exit save loop");
     Ok(())
 }
 
+/// adds `line` and up to `limit` already received points to `pending`, journals them first
+async fn receive(
+    receiver: &mut Receiver<Data>,
+    line: Data,
+    limit: usize,
+    options: &SaverOptions,
+    pending: &mut Vec<Data>,
+) -> Result<(), String> {
+    let mut lines = vec![line];
+    while lines.len() < limit {
+        match receiver.try_recv() {
+            Ok(line) => lines.push(line),
+            Err(_) => break,
+        }
+    }
+    if let Some(journal) = &options.journal {
+        journal
+            .append(&lines)
+            .await
+            .map_err(|e| format!("journal append err: {e}"))?;
+    }
+    pending.extend(lines);
+    Ok(())
+}
+
+/// saves pending points in batches and clears the journal, on failure `pending` keeps unsaved points
+async fn flush(
+    db: &(dyn DBSaver + Send + Sync),
+    options: &SaverOptions,
+    pending: &mut Vec<Data>,
+) -> Result<(), Box<dyn Error>> {
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let mut saved = 0;
+    while !pending.is_empty() {
+        let n = std::cmp::min(options.batch_size.max(1), pending.len());
+        db.save_bulk(&pending[..n]).await?;
+        pending.drain(..n);
+        saved += n;
+    }
+    log::debug!("saved {} points", saved);
+    match &options.journal {
+        Some(journal) => journal.clear().await,
+        None => Ok(()),
+    }
+}
+
+/// aggregates after every import indicator, a failure is caught up with the next indicator,
+/// in the `once` mode the first failure is returned when the indicators end
 pub async fn aggregate_start(
     mut worker: Box<dyn Aggregator + Send + Sync>,
-    receiver: &mut Receiver<NaiveDateTime>,
+    receiver: &mut Receiver<Imported>,
+    mut admin: Option<JobQueue>,
+    once: bool,
 ) -> Result<(), String> {
     log::info!("start db aggregate loop");
+    let mut failed = None;
     loop {
-        let td = receiver.recv().await;
-        log::trace!("got aggregate indicator");
-        match td {
-            Some(td) => {
-                sleep(tokio::time::Duration::from_millis(1000)).await; // problems with selecting new data from redis - wait a bit before aggregation
-                worker
-                    .work(td)
-                    .await
-                    .map(|_v| ())
-                    .map_err(|e| format!("save err: {e}"))?;
+        let td = tokio::select! {
+            td = receiver.recv() => td,
+            Some(job) = JobQueue::recv(&mut admin) => {
+                if let Some(queue) = admin.as_ref() {
+                    run_aggregate_job(worker.as_mut(), queue, job).await;
+                }
+                continue;
             }
+        };
+        log::trace!("got aggregate indicator");
+        let res = match td {
+            Some(Imported::Till(td)) => worker.work(td).await,
+            Some(Imported::Again(from, to)) => worker.refresh(from, to).await,
             None => break,
+        };
+        // the aggregates are caught up with the next indicator
+        if let Err(err) = res {
+            log::error!("aggregate err: {err}");
+            if once && failed.is_none() {
+                failed = Some(format!("save err: {err}"));
+            }
         }
     }
     log::info!("exit aggreagate save loop");
-    Ok(())
+    failed.map_or(Ok(()), Err)
+}
+
+async fn run_aggregate_job(
+    worker: &mut (dyn Aggregator + Send + Sync),
+    queue: &JobQueue,
+    job: Job,
+) {
+    let (at, name) = match job.kind {
+        JobKind::Aggregate { at, aggregator } => (at, aggregator),
+        _ => {
+            queue.jobs.set_status(
+                job.id,
+                JobStatus::Failed("not an aggregate job".to_string()),
+                None,
+            );
+            return;
+        }
+    };
+    queue.jobs.set_status(job.id, JobStatus::Running, None);
+    match worker.recompute(at, name.as_deref()).await {
+        Ok(true) => queue.jobs.set_status(job.id, JobStatus::Done, None),
+        Ok(false) => queue.jobs.set_status(
+            job.id,
+            JobStatus::Failed(format!("no aggregator {}", name.unwrap_or_default())),
+            None,
+        ),
+        Err(err) => queue
+            .jobs
+            .set_status(job.id, JobStatus::Failed(err.to_string()), None),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use chrono::{Duration, NaiveDate, Utc};
+    use std::error::Error;
+
+    use async_trait::async_trait;
+    use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+    use tokio::sync::Mutex;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        aggregate_start,
+        data::{Aggregator, DBSaver, Data, Limiter, Loader},
+        fix_missing_hours, get_sleep,
+        journal::Journal,
+        memory::MemoryStorage,
+        quarantine::Quarantine,
+        reimport, revisions, run, saver_start,
+        storage::SeriesReader,
+        validation::{ValidationArgs, Validator},
+        Imported, SaverOptions, WorkingData, TN_QUALITY,
+    };
+
+    struct TestLoader {
+        data: Vec<Data>,
+    }
+
+    #[async_trait]
+    impl Loader for TestLoader {
+        async fn live(&self) -> Result<String, Box<dyn Error>> {
+            Ok("ok".to_string())
+        }
+        async fn retrieve(
+            &self,
+            from: NaiveDateTime,
+            to: NaiveDateTime,
+        ) -> Result<Vec<Data>, Box<dyn Error>> {
+            Ok(self
+                .data
+                .iter()
+                .filter(|d| d.at >= from && d.at < to)
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct FlakyDB {
+        fails: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        saved: std::sync::Arc<std::sync::Mutex<Vec<Data>>>,
+        batches: std::sync::Arc<std::sync::Mutex<Vec<usize>>>,
+    }
+
+    #[async_trait]
+    impl DBSaver for FlakyDB {
+        async fn live(&self) -> Result<String, Box<dyn Error>> {
+            Ok("ok".to_string())
+        }
+        async fn get_last_time(&self) -> Result<Option<NaiveDateTime>, Box<dyn Error>> {
+            Ok(None)
+        }
+        async fn save(&self, data: &Data) -> Result<bool, Box<dyn Error>> {
+            use std::sync::atomic::Ordering;
+            if self
+                .fails
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| v.checked_sub(1))
+                .is_ok()
+            {
+                return Err("db down".into());
+            }
+            self.saved.lock().unwrap().push(data.clone());
+            Ok(true)
+        }
+        async fn save_bulk(&self, data: &[Data]) -> Result<usize, Box<dyn Error>> {
+            for d in data {
+                self.save(d).await?;
+            }
+            self.batches.lock().unwrap().push(data.len());
+            Ok(data.len())
+        }
+        async fn load(
+            &self,
+            _from: NaiveDateTime,
+            _to: NaiveDateTime,
+        ) -> Result<Vec<Data>, Box<dyn Error>> {
+            Ok(vec![])
+        }
+    }
+
+    struct NoLimiter;
+
+    #[async_trait]
+    impl Limiter for NoLimiter {
+        async fn wait(&self) -> Result<bool, Box<dyn Error>> {
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn run_once_stops() {
+        let base = NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let data = (0..4)
+            .map(|i| Data {
+                at: base + Duration::hours(i),
+                price: i as f64,
+            })
+            .collect();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let (tx_import, mut rx_import) = tokio::sync::mpsc::channel(100);
+        let limiter: Box<dyn Limiter> = Box::new(NoLimiter);
+        let w_data = WorkingData {
+            start_from: base,
+            loader: Box::new(TestLoader { data }),
+            limiter: std::sync::Arc::new(Mutex::new(limiter)),
+            sender: tx,
+            revisions: None,
+            quality: None,
+            validator: None,
+            import_indicator: tx_import,
+            flush: None,
+            admin: None,
+            once: true,
+        };
+        let imported = run(w_data, CancellationToken::new()).await.unwrap();
+        assert_eq!(imported, 4);
+        let mut saved = 0;
+        while rx.recv().await.is_some() {
+            saved += 1;
+        }
+        assert_eq!(saved, 4);
+        assert_eq!(
+            rx_import.recv().await,
+            Some(Imported::Till(base + Duration::hours(3)))
+        );
+        assert_eq!(rx_import.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn reimport_refreshes_aggregates() {
+        let base = NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let data = (0..48)
+            .map(|i| Data {
+                at: base + Duration::hours(i),
+                price: i as f64,
+            })
+            .collect();
+        let (tx, _rx) = tokio::sync::mpsc::channel(100);
+        let (tx_import, mut rx_import) = tokio::sync::mpsc::channel(100);
+        let limiter: Box<dyn Limiter> = Box::new(NoLimiter);
+        let w_data = WorkingData {
+            start_from: base,
+            loader: Box::new(TestLoader { data }),
+            limiter: std::sync::Arc::new(Mutex::new(limiter)),
+            sender: tx,
+            revisions: None,
+            quality: None,
+            validator: None,
+            import_indicator: tx_import,
+            flush: None,
+            admin: None,
+            once: true,
+        };
+        let to = base + Duration::days(2);
+        assert_eq!(reimport(&w_data, base, to).await.unwrap(), 48);
+        assert_eq!(rx_import.try_recv(), Ok(Imported::Again(base, to)));
+    }
 
-    use crate::{data::Data, fix_missing_hours, get_sleep};
+    /// fails the first `fails` calls
+    struct FlakyAggregator {
+        fails: usize,
+        calls: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Aggregator for FlakyAggregator {
+        async fn work(&mut self, _from: NaiveDateTime) -> Result<bool, Box<dyn Error>> {
+            let call = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            match call < self.fails {
+                true => Err("db down".into()),
+                false => Ok(true),
+            }
+        }
+        async fn recompute(
+            &mut self,
+            at: NaiveDateTime,
+            _name: Option<&str>,
+        ) -> Result<bool, Box<dyn Error>> {
+            self.work(at).await
+        }
+        async fn refresh(
+            &mut self,
+            from: NaiveDateTime,
+            _to: NaiveDateTime,
+        ) -> Result<bool, Box<dyn Error>> {
+            self.work(from).await
+        }
+    }
+
+    #[tokio::test]
+    async fn aggregate_fails_once() {
+        let at = NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let aggregate = |once: bool| async move {
+            let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let worker = Box::new(FlakyAggregator {
+                fails: 1,
+                calls: calls.clone(),
+            });
+            let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+            tx.send(Imported::Till(at)).await.unwrap();
+            tx.send(Imported::Till(at)).await.unwrap();
+            drop(tx);
+            let res = aggregate_start(worker, &mut rx, None, once).await;
+            // the loop goes on after the failure
+            assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+            res
+        };
+        assert!(aggregate(true).await.unwrap_err().contains("db down"));
+        assert!(aggregate(false).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn run_saves_to_memory() {
+        let base = NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let data = [0, 1, 3]
+            .into_iter()
+            .map(|i| Data {
+                at: base + Duration::hours(i),
+                price: i as f64,
+            })
+            .collect();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let (tx_import, _rx_import) = tokio::sync::mpsc::channel(100);
+        let (tx_rev, mut rx_rev) = tokio::sync::mpsc::channel(100);
+        let (tx_quality, mut rx_quality) = tokio::sync::mpsc::channel(100);
+        let limiter: Box<dyn Limiter> = Box::new(NoLimiter);
+        let w_data = WorkingData {
+            start_from: base,
+            loader: Box::new(TestLoader { data }),
+            limiter: std::sync::Arc::new(Mutex::new(limiter)),
+            sender: tx,
+            revisions: Some(tx_rev),
+            quality: Some(tx_quality),
+            validator: None,
+            import_indicator: tx_import,
+            flush: None,
+            admin: None,
+            once: true,
+        };
+        let storage = MemoryStorage::new();
+        let db = storage.series("np_lt");
+        let int_db = db.clone();
+        let saver = tokio::spawn(async move {
+            saver_start(Box::new(int_db), &mut rx, SaverOptions::default()).await
+        });
+        let int_db = db.clone();
+        let rev_saver = tokio::spawn(async move {
+            revisions::saver_start(
+                Box::new(int_db),
+                &mut rx_rev,
+                std::time::Duration::from_secs(1),
+            )
+            .await
+        });
+        let db_quality = storage.series(TN_QUALITY);
+        let int_db = db_quality.clone();
+        let quality_saver = tokio::spawn(async move {
+            saver_start(Box::new(int_db), &mut rx_quality, SaverOptions::default()).await
+        });
+        run(w_data, CancellationToken::new()).await.unwrap();
+        saver.await.unwrap().unwrap();
+        rev_saver.await.unwrap().unwrap();
+        quality_saver.await.unwrap().unwrap();
+        assert_eq!(
+            db.get_last_time().await.unwrap(),
+            Some(base + Duration::hours(3))
+        );
+        let res = db.load(base, base + Duration::hours(4)).await.unwrap();
+        assert_eq!(
+            res.iter().map(|d| d.price).collect::<Vec<_>>(),
+            vec![0.0, 1.0, 1.0, 3.0]
+        );
+        let revisions = storage.revisions("np_lt", None, None).await.unwrap();
+        assert_eq!(revisions.len(), 3);
+        assert_eq!(revisions[2].revision, 1);
+        let flags = db_quality
+            .load(base, base + Duration::hours(4))
+            .await
+            .unwrap();
+        assert_eq!(
+            flags.iter().map(|d| d.price).collect::<Vec<_>>(),
+            vec![0.0, 0.0, 1.0, 0.0]
+        );
+    }
+
+    /// sends each point as its own batch
+    struct BatchLoader {
+        data: Vec<Data>,
+    }
+
+    #[async_trait]
+    impl Loader for BatchLoader {
+        async fn live(&self) -> Result<String, Box<dyn Error>> {
+            Ok("ok".to_string())
+        }
+        async fn retrieve(
+            &self,
+            from: NaiveDateTime,
+            to: NaiveDateTime,
+        ) -> Result<Vec<Data>, Box<dyn Error>> {
+            TestLoader {
+                data: self.data.clone(),
+            }
+            .retrieve(from, to)
+            .await
+        }
+        async fn stream_revisions(
+            &self,
+            from: NaiveDateTime,
+            to: NaiveDateTime,
+            sender: tokio::sync::mpsc::Sender<Vec<revisions::Revision>>,
+        ) -> Result<usize, Box<dyn Error>> {
+            let list = self.retrieve_revisions(from, to).await?;
+            let len = list.len();
+            for r in list {
+                sender.send(vec![r]).await?;
+            }
+            Ok(len)
+        }
+    }
+
+    #[tokio::test]
+    async fn imports_by_batches() {
+        let base = NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let data = [0, 1, 4]
+            .into_iter()
+            .map(|i| Data {
+                at: base + Duration::hours(i),
+                price: i as f64,
+            })
+            .collect();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let (tx_import, _rx_import) = tokio::sync::mpsc::channel(100);
+        let (tx_quality, mut rx_quality) = tokio::sync::mpsc::channel(100);
+        let limiter: Box<dyn Limiter> = Box::new(NoLimiter);
+        let w_data = WorkingData {
+            start_from: base,
+            loader: Box::new(BatchLoader { data }),
+            limiter: std::sync::Arc::new(Mutex::new(limiter)),
+            sender: tx,
+            revisions: None,
+            quality: Some(tx_quality),
+            validator: None,
+            import_indicator: tx_import,
+            flush: None,
+            admin: None,
+            once: true,
+        };
+        assert_eq!(run(w_data, CancellationToken::new()).await.unwrap(), 3);
+        let mut saved = vec![];
+        while let Some(d) = rx.recv().await {
+            saved.push(d.price);
+        }
+        // the gap between the batches is filled
+        assert_eq!(saved, vec![0.0, 1.0, 1.0, 1.0, 4.0]);
+        let mut flags = vec![];
+        while let Some(d) = rx_quality.recv().await {
+            flags.push(d.price);
+        }
+        assert_eq!(flags, vec![0.0, 0.0, 1.0, 1.0, 0.0]);
+    }
+
+    #[tokio::test]
+    async fn leaves_held_hours_as_gaps() {
+        let base = NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let data = [(0, 10.0), (1, 9000.0), (2, 12.0)]
+            .into_iter()
+            .map(|(i, price)| Data {
+                at: base + Duration::hours(i),
+                price,
+            })
+            .collect();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let (tx_import, _rx_import) = tokio::sync::mpsc::channel(100);
+        let (tx_quality, mut rx_quality) = tokio::sync::mpsc::channel(100);
+        let limiter: Box<dyn Limiter> = Box::new(NoLimiter);
+        let quarantine = Quarantine::open(None).unwrap();
+        let validator = Validator::new(
+            ValidationArgs::default(),
+            quarantine.clone(),
+            std::sync::Arc::new(FlakyDB::default()),
+        );
+        let w_data = WorkingData {
+            start_from: base,
+            loader: Box::new(TestLoader { data }),
+            limiter: std::sync::Arc::new(Mutex::new(limiter)),
+            sender: tx,
+            revisions: None,
+            quality: Some(tx_quality),
+            validator: Some(validator),
+            import_indicator: tx_import,
+            flush: None,
+            admin: None,
+            once: true,
+        };
+        run(w_data, CancellationToken::new()).await.unwrap();
+        let mut saved = vec![];
+        while let Some(d) = rx.recv().await {
+            saved.push(d.at);
+        }
+        let mut flagged = vec![];
+        while let Some(d) = rx_quality.recv().await {
+            flagged.push(d.at);
+        }
+        let expected = vec![base, base + Duration::hours(2)];
+        assert_eq!(saved, expected);
+        assert_eq!(flagged, expected);
+        assert_eq!(quarantine.list(None).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn fills_after_held_batch() {
+        let base = NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        // one point per batch, the second one is held
+        let data = [(0, 10.0), (1, 9000.0), (3, 12.0)]
+            .into_iter()
+            .map(|(i, price)| Data {
+                at: base + Duration::hours(i),
+                price,
+            })
+            .collect();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let (tx_import, _rx_import) = tokio::sync::mpsc::channel(100);
+        let limiter: Box<dyn Limiter> = Box::new(NoLimiter);
+        let validator = Validator::new(
+            ValidationArgs::default(),
+            Quarantine::open(None).unwrap(),
+            std::sync::Arc::new(FlakyDB::default()),
+        );
+        let w_data = WorkingData {
+            start_from: base,
+            loader: Box::new(BatchLoader { data }),
+            limiter: std::sync::Arc::new(Mutex::new(limiter)),
+            sender: tx,
+            revisions: None,
+            quality: None,
+            validator: Some(validator),
+            import_indicator: tx_import,
+            flush: None,
+            admin: None,
+            once: true,
+        };
+        run(w_data, CancellationToken::new()).await.unwrap();
+        let mut saved = vec![];
+        while let Some(d) = rx.recv().await {
+            saved.push((d.at, d.price));
+        }
+        // the held hour stays a gap, the missing one is filled from the first batch
+        let h = |i| base + Duration::hours(i);
+        assert_eq!(saved, vec![(h(0), 10.0), (h(2), 10.0), (h(3), 12.0)]);
+    }
+
+    #[tokio::test]
+    async fn saver_retries_from_journal() {
+        let path =
+            std::env::temp_dir().join(format!("emarket-saver-journal-{}", std::process::id()));
+        let journal = Journal::new(&path).await.unwrap();
+        journal.clear().await.unwrap();
+        let db = FlakyDB::default();
+        db.fails.store(1, std::sync::atomic::Ordering::SeqCst);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let int_db = db.clone();
+        let options = SaverOptions {
+            journal: Some(journal),
+            ..Default::default()
+        };
+        let saver =
+            tokio::spawn(async move { saver_start(Box::new(int_db), &mut rx, options).await });
+        let at = Utc::now().naive_utc();
+        for i in 0..2 {
+            tx.send(Data {
+                at: at + Duration::hours(i),
+                price: i as f64,
+            })
+            .await
+            .unwrap();
+        }
+        for _ in 0..100 {
+            if db.saved.lock().unwrap().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        drop(tx);
+        saver.await.unwrap().unwrap();
+        assert_eq!(db.saved.lock().unwrap().len(), 2);
+        let journal = Journal::new(&path).await.unwrap();
+        assert!(journal.load().await.unwrap().is_empty());
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn saver_batches() {
+        let db = FlakyDB::default();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let int_db = db.clone();
+        let options = SaverOptions {
+            journal: None,
+            batch_size: 3,
+            batch_latency: std::time::Duration::from_millis(50),
+            flush: None,
+        };
+        let saver =
+            tokio::spawn(async move { saver_start(Box::new(int_db), &mut rx, options).await });
+        let at = Utc::now().naive_utc();
+        for i in 0..7 {
+            tx.send(Data {
+                at: at + Duration::hours(i),
+                price: i as f64,
+            })
+            .await
+            .unwrap();
+        }
+        drop(tx);
+        saver.await.unwrap().unwrap();
+        assert_eq!(db.saved.lock().unwrap().len(), 7);
+        let batches = db.batches.lock().unwrap().clone();
+        assert!(batches.iter().all(|b| *b <= 3), "{batches:?}");
+        assert!(batches.len() < 7, "{batches:?}");
+    }
+
+    #[tokio::test]
+    async fn saver_acks_flush() {
+        let db = FlakyDB::default();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let (tx_flush, rx_flush) = tokio::sync::mpsc::channel(1);
+        let int_db = db.clone();
+        let options = SaverOptions {
+            journal: None,
+            batch_size: 100,
+            batch_latency: std::time::Duration::from_secs(60),
+            flush: Some(rx_flush),
+        };
+        let saver =
+            tokio::spawn(async move { saver_start(Box::new(int_db), &mut rx, options).await });
+        let at = Utc::now().naive_utc();
+        for i in 0..2 {
+            tx.send(Data {
+                at: at + Duration::hours(i),
+                price: i as f64,
+            })
+            .await
+            .unwrap();
+        }
+        let (ack, done) = tokio::sync::oneshot::channel();
+        tx_flush.send(ack).await.unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), done)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(db.saved.lock().unwrap().len(), 2);
+        drop(tx);
+        saver.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn saver_fails_without_journal() {
+        let db = FlakyDB::default();
+        db.fails.store(1, std::sync::atomic::Ordering::SeqCst);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let int_db = db.clone();
+        let saver = tokio::spawn(async move {
+            saver_start(Box::new(int_db), &mut rx, SaverOptions::default()).await
+        });
+        tx.send(Data {
+            at: Utc::now().naive_utc(),
+            price: 1.0,
+        })
+        .await
+        .unwrap();
+        assert!(saver.await.unwrap().is_err());
+    }
 
     #[test]
     fn get_sleep_long() {
@@ -1,20 +1,46 @@
+mod admin;
 mod aggregator;
+mod compaction;
+mod ecb;
 mod entsoe;
 mod limiter;
+mod migrate;
 mod redis;
+mod snapshot;
+mod store;
+mod verify;
 
+use chrono::Duration;
 use chrono::NaiveDate;
 use chrono::NaiveDateTime;
-use clap::Parser;
-use deadpool_redis::Runtime;
+use chrono::Utc;
+use clap::{Parser, Subcommand};
 use emarket::aggregate_start;
 use emarket::data::Aggregator;
 use emarket::data::Data;
 use emarket::data::Limiter;
-use emarket::WorkingData;
-use emarket::TN_HOUR;
-use emarket::{run_exit_indicator, saver_start};
+use emarket::data::Loader;
+use emarket::gaps::{self, Gaps, ScanOptions};
+use emarket::jobs::JobQueue;
+use emarket::jobs::Jobs;
+use emarket::journal::Journal;
+use emarket::memory::MemoryStorage;
+use emarket::quarantine::Quarantine;
+use emarket::redis_pool::{RedisArgs, RedisPool};
+use emarket::revisions;
+use emarket::series::{
+    retention_for, Labels, Retention, RES_DAY, RES_HOUR, RES_MONTH, STAT_AVG, STAT_RAW,
+};
+use emarket::sqlite::SqliteStorage;
+use emarket::storage::{storage_url, StorageUrl};
+use emarket::units::{self, CURRENCY_EUR, UNIT_MWH};
+use emarket::utils::{parse_duration, to_time};
+use emarket::validation::{ValidationArgs, Validator};
+use emarket::{run_exit_indicator, saver_start, SaverOptions};
+use emarket::{Imported, WorkingData};
+use emarket::{TN_1H, TN_DAY, TN_FIXED_DAY, TN_HOUR, TN_MONTH, TN_QUALITY};
 use reqwest::Error;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::sync::Arc;
 use tokio::sync::mpsc::Receiver;
@@ -22,14 +48,17 @@ use tokio::sync::mpsc::Sender;
 use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 
-use entsoe::EntSOE;
+use entsoe::{EntSOE, EntSOEFiles, Units};
 
+use crate::admin::AdminService;
 use crate::aggregator::time_day;
 use crate::aggregator::time_month;
 use crate::aggregator::AggregatorByDate;
 use crate::aggregator::Aggregators;
 use crate::limiter::RateLimiter;
 use crate::redis::RedisClient;
+use crate::redis::RetentionOptions;
+use crate::store::Storage;
 use emarket::data::DBSaver;
 use tokio::signal::unix::{signal, SignalKind};
 use tracing_subscriber::layer::SubscriberExt;
@@ -37,21 +66,99 @@ use tracing_subscriber::util::SubscriberInitExt;
 
 #[derive(Parser, Debug)]
 #[command(version = env!("CARGO_APP_VERSION"), name = "importer", about="Import entsoe day ahead prices to local timeseries DB", author ="Airenas V.<airenass@gmail.com>", long_about = None)]
+#[command(subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// EntSOE query document type
     #[arg(long, short = 'd', env, default_value = "A44")]
     document: String,
     /// EntSOE query domain value
     #[arg(long, short = 'm', env, default_value = "10YLT-1001A0008Q")]
     domain: String,
+    /// Zone label of the imported series
+    #[arg(long, env, default_value = "LT")]
+    zone: String,
     /// EntSOE auth key
     #[arg(long, env, required = true)]
-    key: String,
+    key: Option<String>,
     /// redis url
     #[arg(long, short, env, default_value = "")]
     redis_url: String,
+    /// Storage url: redis://host:port, sqlite:///path/to/file.db or memory://, defaults to the redis url
+    #[arg(long, env)]
+    storage: Option<String>,
+    #[command(flatten)]
+    redis: RedisArgs,
+    /// Admin API port, the API is disabled if not set
+    #[arg(long, env)]
+    admin_port: Option<u16>,
+    /// Admin API bearer token
+    #[arg(long, env)]
+    admin_key: Option<String>,
+    /// Import new data once and exit, exit codes: 0 - imported, 2 - nothing new, 1 - failure
+    #[arg(long, env, default_value_t = false)]
+    once: bool,
+    /// Write-ahead journal file, points are kept there until saved to redis
+    #[arg(long, env)]
+    journal: Option<PathBuf>,
+    /// Max points in one redis write
+    #[arg(long, env, default_value_t = 500)]
+    batch_size: usize,
+    /// Max time to collect a batch before writing it
+    #[arg(long, env, default_value = "1s", value_parser = parse_duration)]
+    batch_latency: std::time::Duration,
+    /// Maintain hourly and fixed-offset day rollups with redis compaction rules
+    #[arg(long, env, default_value_t = false)]
+    compaction: bool,
+    /// Series retention policies: <series>=<duration>, e.g. np_lt=90d,np_lt_1h=0
+    #[arg(long, env, value_delimiter = ',')]
+    retention: Vec<Retention>,
+    /// Allow shortening retention of series without rollups
+    #[arg(long, env, default_value_t = false)]
+    retention_force: bool,
+    /// Scan the stored hourly prices for gaps and refetch them at this interval, disabled if not set
+    #[arg(long, env, value_parser = parse_duration)]
+    gap_scan_interval: Option<std::time::Duration>,
+    /// Range before the last stored point checked by the gap scanner
+    #[arg(long, env, default_value = "30d", value_parser = parse_duration)]
+    gap_scan_lookback: std::time::Duration,
+    /// Currency of the imported prices, documents in other currencies are refused
+    #[arg(long, env, default_value = CURRENCY_EUR)]
+    currency: String,
+    /// Energy unit of the imported prices: MWh or kWh
+    #[arg(long, env, default_value = UNIT_MWH, value_parser = parse_unit)]
+    price_unit: String,
+    #[command(flatten)]
+    validation: ValidationArgs,
+    /// File keeping the quarantined points, they are kept in memory only if not set
+    #[arg(long, env)]
+    quarantine_file: Option<PathBuf>,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Export series to a portable archive
+    Export(snapshot::ExportArgs),
+    /// Restore series from an archive made by export
+    Restore(snapshot::RestoreArgs),
+    /// Move the series keys into the namespace set by --redis-namespace
+    MigrateKeys(migrate::MigrateArgs),
+    /// Compare the stored prices and averages with EntSOE or recorded documents
+    Verify(verify::VerifyArgs),
+    /// Import ECB euro reference rates to a daily series per currency
+    Rates(ecb::RatesArgs),
+}
+
+const EXIT_FAILED: i32 = 1;
+const EXIT_NOTHING_NEW: i32 = 2;
+/// verify found differences and did not repair them
+const EXIT_DIFFERENCES: i32 = 3;
+/// refetches of one gap before the scanner gives up on it
+const GAP_REFETCH_ATTEMPTS: u32 = 3;
+/// how long a revision log write is retried before the batch is dropped
+const REVISION_RETRY: std::time::Duration = std::time::Duration::from_secs(600);
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     tracing_subscriber::registry()
@@ -71,55 +178,62 @@ async fn main_int(args: Args) -> Result<(), Error> {
     tracing::info!(version = env!("CARGO_APP_VERSION"));
     tracing::info!(domain=args.domain);
     tracing::info!(document=args.document);
-    tracing::info!(url=args.redis_url, "redis");
-    if args.key.len() > 4 {
-        tracing::info!(key=format!("{}...{}", &args.key[..2], &args.key[args.key.len() - 2..]));
+    let storage = storage_url(args.storage.as_deref(), &args.redis_url).unwrap_or_else(|err| {
+        log::error!("{err}");
+        process::exit(1)
+    });
+    tracing::info!(storage = ?storage);
+    if let Some(command) = &args.command {
+        if let Err(err) = run_command(&args, command, &storage).await {
+            log::error!("{err}");
+            process::exit(EXIT_FAILED);
+        }
+        return Ok(());
+    }
+    let key = args.key.clone().unwrap_or_default();
+    if key.len() > 4 {
+        tracing::info!(key=format!("{}...{}", &key[..2], &key[key.len() - 2..]));
     }
 
-    let pool = deadpool_redis::Config::from_url(&args.redis_url)
-        .create_pool(Some(Runtime::Tokio1))
-        .unwrap_or_else(|err| {
-            log::error!("redis poll init: {err}");
-            process::exit(1)
-        });
-
-    let db_hours = RedisClient::new(pool.clone(), TN_HOUR)
-        .await
-        .unwrap_or_else(|err| {
-            log::error!("redis client init: {err}");
-            process::exit(1)
-        });
-    let db_days = RedisClient::new(pool.clone(), "np_lt_d")
-        .await
-        .unwrap_or_else(|err| {
-            log::error!("redis client init: {err}");
-            process::exit(1)
-        });
-    let db_months = RedisClient::new(pool, "np_lt_m")
-        .await
-        .unwrap_or_else(|err| {
-            log::error!("redis client init: {err}");
-            process::exit(1)
-        });
-    let boxed_db_days: Box<dyn DBSaver + Send + Sync> = Box::new(db_hours.clone());
-    log::info!("Test Redis is live ...");
-    boxed_db_days.live().await.unwrap();
-    log::info!("Redis OK");
+    let labels = price_labels(&args);
+    let dbs = match storage {
+        StorageUrl::Redis(url) => init_redis(&args, &url, &labels).await,
+        StorageUrl::Sqlite(path) => init_sqlite(&args, &path, &labels).await,
+        StorageUrl::Memory => init_memory(&labels),
+    }
+    .unwrap_or_else(|err| {
+        log::error!("storage init: {err}");
+        process::exit(1)
+    });
+    let db_hours = dbs.hours;
+    log::info!("Test DB is live ...");
+    match db_hours.live().await {
+        Ok(_) => log::info!("DB OK"),
+        Err(err) => log::warn!("DB is not live: {err}"),
+    }
 
-    let aggregator_days =
-        AggregatorByDate::new(Box::new(db_hours.clone()), Box::new(db_days), time_day)
-            .await
-            .unwrap_or_else(|err| {
-                log::error!("aggregator days init: {err}");
-                process::exit(1)
-            });
-    let aggregator_months =
-        AggregatorByDate::new(Box::new(db_hours.clone()), Box::new(db_months), time_month)
-            .await
-            .unwrap_or_else(|err| {
-                log::error!("aggregator months init: {err}");
-                process::exit(1)
-            });
+    let aggregator_days = AggregatorByDate::new(
+        "day",
+        Box::new(db_hours.clone()),
+        Box::new(dbs.days),
+        time_day,
+    )
+    .await
+    .unwrap_or_else(|err| {
+        log::error!("aggregator days init: {err}");
+        process::exit(1)
+    });
+    let aggregator_months = AggregatorByDate::new(
+        "month",
+        Box::new(db_hours.clone()),
+        Box::new(dbs.months),
+        time_month,
+    )
+    .await
+    .unwrap_or_else(|err| {
+        log::error!("aggregator months init: {err}");
+        process::exit(1)
+    });
     let boxed_aggregator: Box<dyn Aggregator + Send + Sync> = Box::new(Aggregators {
         aggregators: vec![Box::new(aggregator_days), Box::new(aggregator_months)],
     });
@@ -130,15 +244,129 @@ async fn main_int(args: Args) -> Result<(), Error> {
 
     let (tx, mut rx) = tokio::sync::mpsc::channel(100);
     let (tx_import, mut rx_import) = tokio::sync::mpsc::channel(100);
+    let (tx_revisions, mut rx_revisions) = tokio::sync::mpsc::channel(100);
+    let (tx_quality, mut rx_quality) = tokio::sync::mpsc::channel(100);
     let cancel_token = CancellationToken::new();
     let (tx_wait_exit, mut rx_wait_exit) = tokio::sync::mpsc::channel(1);
     let (tx_exit_indicator, mut rx_exit_indicator) = tokio::sync::mpsc::unbounded_channel();
 
-    let loader = EntSOE::new(&args.document, &args.domain, &args.key).unwrap();
+    let loader = EntSOE::new(&args.document, &args.domain, &key)
+        .unwrap()
+        .with_units(price_units(&args));
+    let quarantine = Quarantine::open(args.quarantine_file.as_deref()).unwrap_or_else(|err| {
+        log::error!("quarantine init: {err}");
+        process::exit(1)
+    });
+
+    if args.once && (args.admin_port.is_some() || args.gap_scan_interval.is_some()) {
+        log::warn!("admin API and gap scanner are not started in once mode");
+    }
+    let admin_port = args.admin_port.filter(|_| !args.once);
+    let gap_scan = args.gap_scan_interval.filter(|_| !args.once);
+    let gaps = gap_scan.map(|_| {
+        let gaps = Gaps::new().unwrap();
+        gaps.register(prometheus::default_registry()).unwrap();
+        gaps
+    });
+    let (import_admin, aggregate_admin) = if admin_port.is_some() || gap_scan.is_some() {
+        let (tx_import_job, rx_import_job) = tokio::sync::mpsc::channel(100);
+        let (tx_aggregate_job, rx_aggregate_job) = tokio::sync::mpsc::channel(100);
+        let jobs = Jobs::new(tx_import_job, tx_aggregate_job);
+        if let Some(port) = admin_port {
+            let key = args
+                .admin_key
+                .clone()
+                .filter(|k| !k.is_empty())
+                .unwrap_or_else(|| {
+                    log::error!("no admin key provided");
+                    process::exit(1)
+                });
+            let srv = AdminService {
+                jobs: jobs.clone(),
+                key,
+                gaps: gaps.clone(),
+                quarantine: quarantine.clone(),
+                saver: tx.clone(),
+                quality: tx_quality.clone(),
+            };
+            let ct = cancel_token.clone();
+            let int_exit = tx_exit_indicator.clone();
+            tokio::spawn(async move {
+                if let Err(err) = admin::start(port, srv, ct).await {
+                    log::error!("admin server: {err}");
+                    let _ = int_exit.send(1);
+                }
+            });
+        }
+        if let (Some(interval), Some(gaps)) = (gap_scan, gaps) {
+            let options = ScanOptions {
+                interval,
+                lookback: Duration::from_std(args.gap_scan_lookback).unwrap(),
+                step: parse_duration(&labels.resolution)
+                    .ok()
+                    .and_then(|v| Duration::from_std(v).ok())
+                    .unwrap_or_else(|| Duration::hours(1)),
+                max_attempts: GAP_REFETCH_ATTEMPTS,
+            };
+            let int_db = db_hours.clone();
+            let int_jobs = jobs.clone();
+            let ct = cancel_token.clone();
+            tokio::spawn(async move {
+                gaps::scanner_start(Box::new(int_db), int_jobs, gaps, options, ct).await
+            });
+        }
+        (
+            Some(JobQueue {
+                receiver: rx_import_job,
+                jobs: jobs.clone(),
+            }),
+            Some(JobQueue {
+                receiver: rx_aggregate_job,
+                jobs,
+            }),
+        )
+    } else {
+        (None, None)
+    };
 
     //     let interval = config.interval.clone();
     let int_limiter = limiter.clone();
-    let start_from = db_hours.get_last_time().await.unwrap().unwrap_or(
+    let journal = match &args.journal {
+        Some(path) => Some(open_journal(path).await),
+        None => None,
+    };
+    let journal_last = match &journal {
+        Some(journal) => journal
+            .load()
+            .await
+            .unwrap_or_else(|err| {
+                log::error!("journal load: {err}");
+                process::exit(1)
+            })
+            .iter()
+            .map(|d| d.at)
+            .max(),
+        None => None,
+    };
+    let (tx_flush, rx_flush) = tokio::sync::mpsc::channel(1);
+    let saver_options = SaverOptions {
+        journal,
+        batch_size: args.batch_size,
+        batch_latency: args.batch_latency,
+        flush: Some(rx_flush),
+    };
+    let db_last = match db_hours.get_last_time().await {
+        Ok(v) => v,
+        Err(err) if journal_last.is_some() => {
+            log::warn!("DB last time: {err}, continue from the journal");
+            None
+        }
+        Err(err) => {
+            log::error!("DB last time: {err}");
+            process::exit(1)
+        }
+    };
+    let start_from = std::cmp::max(db_last, journal_last).unwrap_or(
         NaiveDate::from_ymd_opt(2020, 1, 1)
             .unwrap()
             .and_hms_opt(0, 0, 0)
@@ -146,26 +374,70 @@ async fn main_int(args: Args) -> Result<(), Error> {
     );
     log::info!("start import from {start_from}");
     log::info!("sending initial aggregate msg");
-    tx_import.send(start_from).await.unwrap_or_else(|err| {
-        log::error!("{err}");
-        process::exit(1);
-    });
+    tx_import
+        .send(Imported::Till(start_from))
+        .await
+        .unwrap_or_else(|err| {
+            log::error!("{err}");
+            process::exit(1);
+        });
     let w_data = WorkingData {
         loader: Box::new(loader),
         start_from,
         sender: tx.clone(),
+        revisions: Some(tx_revisions),
+        quality: Some(tx_quality),
+        validator: Some(Validator::new(
+            args.validation.clone(),
+            quarantine,
+            db_hours.clone(),
+        )),
         limiter: int_limiter,
         import_indicator: tx_import,
+        flush: Some(tx_flush),
+        admin: import_admin,
+        once: args.once,
     };
 
     let importer = run_exit_indicator(w_data, cancel_token.clone(), tx_exit_indicator.clone());
 
     let int_exit = tx_wait_exit.clone();
-    tokio::spawn(async move { start_saver_loop(Box::new(db_hours), &mut rx, int_exit).await });
+    let int_db = db_hours.clone();
+    let saver_job = tokio::spawn(async move {
+        start_saver_loop(Box::new(int_db), &mut rx, saver_options, int_exit).await
+    });
+    let int_exit = tx_wait_exit.clone();
+    let int_db = dbs.quality.clone();
+    let quality_journal = match &args.journal {
+        Some(path) => Some(open_journal(&path.with_extension("quality")).await),
+        None => None,
+    };
+    let quality_options = SaverOptions {
+        journal: quality_journal,
+        batch_size: args.batch_size,
+        batch_latency: args.batch_latency,
+        flush: None,
+    };
+    let quality_job = tokio::spawn(async move {
+        start_saver_loop(Box::new(int_db), &mut rx_quality, quality_options, int_exit).await
+    });
     let int_exit = tx_wait_exit.clone();
-    tokio::spawn(
-        async move { start_aggregate_loop(boxed_aggregator, &mut rx_import, int_exit).await },
-    );
+    let int_db = db_hours.clone();
+    let revision_job = tokio::spawn(async move {
+        let _tx_exit = int_exit;
+        revisions::saver_start(Box::new(int_db), &mut rx_revisions, REVISION_RETRY).await
+    });
+    let int_exit = tx_wait_exit.clone();
+    let aggregate_job = tokio::spawn(async move {
+        start_aggregate_loop(
+            boxed_aggregator,
+            &mut rx_import,
+            aggregate_admin,
+            args.once,
+            int_exit,
+        )
+        .await
+    });
 
     tokio::spawn(async move {
         let mut int_stream = signal(SignalKind::interrupt()).unwrap();
@@ -183,28 +455,351 @@ async fn main_int(args: Args) -> Result<(), Error> {
     drop(tx_wait_exit);
     drop(tx);
 
-    importer.await.unwrap_or_else(|err| {
+    let imported = importer.await.unwrap_or_else(|err| {
         log::error!("{err}");
-        process::exit(1);
+        process::exit(EXIT_FAILED);
     });
 
     log::info!("wait jobs to finish");
     let _ = rx_wait_exit.recv().await;
 
+    if args.once {
+        for (name, job) in [
+            ("saver", saver_job),
+            ("quality", quality_job),
+            ("revision", revision_job),
+            ("aggregate", aggregate_job),
+        ] {
+            if let Err(err) = job.await.map_err(|e| e.to_string()).and_then(|r| r) {
+                log::error!("{name} loop: {err}");
+                process::exit(EXIT_FAILED);
+            }
+        }
+        log::info!("imported {imported}");
+        log::info!("Bye");
+        if imported == 0 {
+            process::exit(EXIT_NOTHING_NEW);
+        }
+        return Ok(());
+    }
+
     log::info!("Bye");
     Ok(())
 }
 
+async fn open_journal(path: &Path) -> Journal {
+    Journal::new(path).await.unwrap_or_else(|err| {
+        log::error!("journal init: {err}");
+        process::exit(1)
+    })
+}
+
+/// labels of the imported hourly prices
+fn price_labels(args: &Args) -> Labels {
+    Labels::price(&args.zone, RES_HOUR, STAT_RAW).with_units(&args.currency, &args.price_unit)
+}
+
+fn parse_unit(s: &str) -> Result<String, String> {
+    units::normalize_unit(s).map(str::to_string)
+}
+
+fn price_units(args: &Args) -> Units {
+    Units {
+        currency: args.currency.clone(),
+        unit: args.price_unit.clone(),
+    }
+}
+
+async fn run_command(
+    args: &Args,
+    command: &Command,
+    storage: &StorageUrl,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let store = Storage::open(storage, &args.redis).await?;
+    let retention = |name: &str| RetentionOptions {
+        keep: retention_for(&args.retention, name),
+        force: args.retention_force,
+    };
+    match command {
+        Command::Export(cmd) => {
+            let reader = store.reader().await?;
+            let keys = if cmd.series.is_empty() {
+                reader.query_index(&[format!("zone={}", args.zone)]).await?
+            } else {
+                cmd.series.iter().map(|name| store.key(name)).collect()
+            };
+            let series: Vec<(String, String)> = keys
+                .into_iter()
+                .map(|key| (store.name(&key).to_string(), key))
+                .collect();
+            let file = std::io::BufWriter::new(std::fs::File::create(&cmd.output)?);
+            let millis = |t: Option<NaiveDateTime>| t.map(|t| t.and_utc().timestamp_millis());
+            let report = snapshot::export(
+                reader.as_ref(),
+                &series,
+                millis(cmd.from),
+                millis(cmd.to),
+                file,
+            )
+            .await?;
+            log::info!(
+                "exported {} records to {}",
+                report.records,
+                cmd.output.display()
+            );
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        Command::Restore(cmd) => {
+            let file = std::io::BufReader::new(std::fs::File::open(&cmd.input)?);
+            // compaction rules are a redis feature
+            let compaction = args.compaction && matches!(store, Storage::Redis { .. });
+            let rules = |labels: &Labels| match compaction {
+                true => compaction::rules(labels, &args.retention),
+                false => vec![],
+            };
+            let mut report =
+                snapshot::restore(&store, file, cmd.overwrite, rules, retention).await?;
+            let hours = report.series.iter().find(|s| s.name == TN_HOUR);
+            if let (true, Some((Some(from), Some(to)))) =
+                (cmd.aggregate, hours.map(|s| (s.first, s.last)))
+            {
+                let reader = store.reader().await?;
+                let labels = Labels::try_from(&reader.info(&store.key(TN_HOUR)).await?.labels)?;
+                report.aggregated = snapshot::aggregate(
+                    &store,
+                    &labels,
+                    to_time(from as u64),
+                    to_time(to as u64) + Duration::milliseconds(1),
+                    retention,
+                )
+                .await?;
+                log::info!("recalculated {} buckets", report.aggregated);
+            }
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        Command::MigrateKeys(cmd) => {
+            let Storage::Redis { pool, .. } = &store else {
+                return Err("key migration works with the redis storage only".into());
+            };
+            let names = [TN_HOUR, TN_DAY, TN_MONTH, TN_1H, TN_FIXED_DAY, TN_QUALITY];
+            let report =
+                migrate::migrate(pool, cmd.from_namespace.clone(), &names, cmd.dry_run).await?;
+            log::info!("{} keys to rename", report.renamed.len());
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        Command::Rates(cmd) => {
+            let report = ecb::import(&store, cmd, retention).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        Command::Verify(cmd) => {
+            let (loader, source): (Box<dyn Loader>, String) = match &cmd.source {
+                Some(path) => (
+                    Box::new(EntSOEFiles::open(path)?.with_units(price_units(args))),
+                    path.display().to_string(),
+                ),
+                None => {
+                    let key = args
+                        .key
+                        .as_deref()
+                        .ok_or("no EntSOE key, set --key or --source")?;
+                    (
+                        Box::new(
+                            EntSOE::new(&args.document, &args.domain, key)?
+                                .with_units(price_units(args)),
+                        ),
+                        "entsoe".to_string(),
+                    )
+                }
+            };
+            let labels = price_labels(args);
+            let series = verify::verify(&store, loader.as_ref(), &labels, cmd, retention).await?;
+            let millis = |t: NaiveDateTime| t.and_utc().timestamp_millis();
+            let report = verify::VerifyReport {
+                from: millis(cmd.from),
+                to: millis(cmd.to),
+                source,
+                ok: series.iter().all(|s| s.diffs.is_empty()),
+                series,
+            };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.ok && !cmd.repair {
+                process::exit(EXIT_DIFFERENCES);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// importer series: raw hourly prices, their quality flags and calendar rollups
+struct Databases {
+    hours: Arc<dyn DBSaver + Send + Sync>,
+    quality: Arc<dyn DBSaver + Send + Sync>,
+    days: Arc<dyn DBSaver + Send + Sync>,
+    months: Arc<dyn DBSaver + Send + Sync>,
+}
+
+async fn init_redis(
+    args: &Args,
+    url: &str,
+    labels: &Labels,
+) -> Result<Databases, Box<dyn std::error::Error>> {
+    let pool = RedisPool::new(url, &args.redis).await?;
+    let key = |name: &str| args.redis.key(name);
+    let mut rules = if args.compaction {
+        compaction::rules(labels, &args.retention)
+    } else {
+        vec![]
+    };
+    for rule in rules.iter_mut() {
+        rule.dest = key(&rule.dest);
+    }
+    let retention = |name: &str| RetentionOptions {
+        keep: retention_for(&args.retention, name),
+        force: args.retention_force,
+    };
+    let db_hours = RedisClient::new(
+        pool.clone(),
+        &key(TN_HOUR),
+        labels,
+        &rules,
+        retention(TN_HOUR),
+    )
+    .await?;
+    let db_quality = RedisClient::new(
+        pool.clone(),
+        &key(TN_QUALITY),
+        &Labels::quality(&labels.zone, &labels.resolution),
+        &[],
+        retention(TN_QUALITY),
+    )
+    .await?;
+    let db_days = RedisClient::new(
+        pool.clone(),
+        &key(TN_DAY),
+        &labels.with(RES_DAY, STAT_AVG),
+        &[],
+        retention(TN_DAY),
+    )
+    .await?;
+    let db_months = RedisClient::new(
+        pool.clone(),
+        &key(TN_MONTH),
+        &labels.with(RES_MONTH, STAT_AVG),
+        &[],
+        retention(TN_MONTH),
+    )
+    .await?;
+    if args.compaction {
+        let db_1h = RedisClient::new(
+            pool.clone(),
+            &rules[0].dest,
+            &rules[0].labels,
+            &[],
+            retention(TN_1H),
+        )
+        .await;
+        let db_fixed_days = RedisClient::new(
+            pool.clone(),
+            &rules[1].dest,
+            &rules[1].labels,
+            &[],
+            retention(TN_FIXED_DAY),
+        )
+        .await;
+        let to = Utc::now().naive_utc();
+        let res = match (db_1h, db_fixed_days) {
+            (Ok(db_1h), Ok(db_fixed_days)) => {
+                compaction::check(
+                    &db_hours,
+                    &db_1h,
+                    &db_fixed_days,
+                    &db_days,
+                    to - Duration::days(30),
+                    to,
+                )
+                .await
+            }
+            (Err(err), _) | (_, Err(err)) => Err(err),
+        };
+        match res {
+            Ok(0) => log::info!("compaction rules are consistent"),
+            Ok(n) => log::warn!("compaction rules have {n} mismatches"),
+            Err(err) => log::error!("compaction check: {err}"),
+        }
+    }
+
+    Ok(Databases {
+        hours: Arc::new(db_hours),
+        quality: Arc::new(db_quality),
+        days: Arc::new(db_days),
+        months: Arc::new(db_months),
+    })
+}
+
+/// compaction rules and retention are redis features, they are ignored here
+async fn init_sqlite(
+    args: &Args,
+    path: &Path,
+    labels: &Labels,
+) -> Result<Databases, Box<dyn std::error::Error>> {
+    if args.compaction || !args.retention.is_empty() {
+        log::warn!("compaction and retention are not supported by sqlite, ignored");
+    }
+    let storage = SqliteStorage::open(path)?;
+    storage.create(TN_HOUR, labels).await?;
+    storage
+        .create(TN_DAY, &labels.with(RES_DAY, STAT_AVG))
+        .await?;
+    storage
+        .create(TN_MONTH, &labels.with(RES_MONTH, STAT_AVG))
+        .await?;
+    storage
+        .create(
+            TN_QUALITY,
+            &Labels::quality(&labels.zone, &labels.resolution),
+        )
+        .await?;
+    Ok(Databases {
+        hours: Arc::new(storage.series(TN_HOUR)),
+        quality: Arc::new(storage.series(TN_QUALITY)),
+        days: Arc::new(storage.series(TN_DAY)),
+        months: Arc::new(storage.series(TN_MONTH)),
+    })
+}
+
+/// nothing is persisted, useful with the admin API for trying imports out
+fn init_memory(labels: &Labels) -> Result<Databases, Box<dyn std::error::Error>> {
+    log::warn!("memory storage, imported data is lost on exit");
+    let storage = MemoryStorage::new();
+    storage.create(TN_HOUR, labels)?;
+    storage.create(TN_DAY, &labels.with(RES_DAY, STAT_AVG))?;
+    storage.create(TN_MONTH, &labels.with(RES_MONTH, STAT_AVG))?;
+    storage.create(
+        TN_QUALITY,
+        &Labels::quality(&labels.zone, &labels.resolution),
+    )?;
+    Ok(Databases {
+        hours: Arc::new(storage.series(TN_HOUR)),
+        quality: Arc::new(storage.series(TN_QUALITY)),
+        days: Arc::new(storage.series(TN_DAY)),
+        months: Arc::new(storage.series(TN_MONTH)),
+    })
+}
+
 async fn start_saver_loop(
     db_saver: Box<dyn DBSaver + Send + Sync>,
     receiver: &mut Receiver<Data>,
+    options: SaverOptions,
     _tx_exit: Sender<()>,
 ) -> Result<(), String> {
     log::info!("Test Redis is live ...");
-    db_saver.live().await.unwrap();
-    log::info!("Redis OK");
+    match db_saver.live().await {
+        Ok(_) => log::info!("Redis OK"),
+        // the saver keeps the points in the journal and retries
+        Err(err) => log::warn!("Redis is not live: {err}"),
+    }
 
-    saver_start(db_saver, receiver).await?;
+    saver_start(db_saver, receiver, options).await?;
 
     log::info!("exit redis loop");
     Ok(())
@@ -212,11 +807,13 @@ async fn start_saver_loop(
 
 async fn start_aggregate_loop(
     db_saver: Box<dyn Aggregator + Send + Sync>,
-    receiver: &mut Receiver<NaiveDateTime>,
+    receiver: &mut Receiver<Imported>,
+    admin: Option<JobQueue>,
+    once: bool,
     _tx_exit: Sender<()>,
 ) -> Result<(), String> {
     log::info!("start aggregate loop");
-    aggregate_start(db_saver, receiver).await?;
+    aggregate_start(db_saver, receiver, admin, once).await?;
     log::info!("exit aggregate loop");
     Ok(())
 }
@@ -0,0 +1,334 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime};
+
+use crate::{
+    data::{DBSaver, Data},
+    revisions::{changed, Revision},
+    series::{matches_filter, Labels},
+    storage::{SeriesInfo, SeriesReader},
+};
+
+#[derive(Default)]
+struct MemorySeriesData {
+    labels: BTreeMap<String, String>,
+    points: BTreeMap<i64, f64>,
+    /// revision log sorted by point and fetch time
+    revisions: Vec<Revision>,
+}
+
+/// Thread-safe in-memory storage for tests and the demo mode, nothing is persisted.
+/// Duplicate points overwrite the old value like the redis `LAST` policy.
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    series: Arc<RwLock<HashMap<String, MemorySeriesData>>>,
+}
+
+/// One series of [`MemoryStorage`]
+#[derive(Clone)]
+pub struct MemorySeries {
+    storage: MemoryStorage,
+    ts_name: String,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    /// creates the series or updates its labels
+    pub fn create(&self, ts_name: &str, labels: &Labels) -> Result<(), Box<dyn Error>> {
+        let mut series = self.series.write().map_err(|e| e.to_string())?;
+        series.entry(ts_name.to_string()).or_default().labels = labels
+            .to_vec()
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Ok(())
+    }
+
+    pub fn series(&self, ts_name: &str) -> MemorySeries {
+        MemorySeries {
+            storage: self.clone(),
+            ts_name: ts_name.to_string(),
+        }
+    }
+
+    pub fn add(&self, ts_name: &str, data: &[Data]) -> Result<usize, Box<dyn Error>> {
+        let mut series = self.series.write().map_err(|e| e.to_string())?;
+        let points = &mut series.entry(ts_name.to_string()).or_default().points;
+        for d in data {
+            points.insert(d.at.and_utc().timestamp_millis(), d.price);
+        }
+        Ok(data.len())
+    }
+
+    /// points in [from, to)
+    fn range(&self, ts_name: &str, from: i64, to: i64) -> Result<Vec<Data>, Box<dyn Error>> {
+        if from >= to {
+            return Ok(vec![]);
+        }
+        let series = self.series.read().map_err(|e| e.to_string())?;
+        let Some(s) = series.get(ts_name) else {
+            return Ok(vec![]);
+        };
+        s.points
+            .range(from..to)
+            .map(|(at, price)| {
+                Ok(Data {
+                    at: to_time(*at)?,
+                    price: *price,
+                })
+            })
+            .collect()
+    }
+
+    /// adds the entries changing the known values to the revision log
+    pub fn add_revisions(&self, ts_name: &str, data: &[Revision]) -> Result<usize, Box<dyn Error>> {
+        let mut series = self.series.write().map_err(|e| e.to_string())?;
+        let revisions = &mut series.entry(ts_name.to_string()).or_default().revisions;
+        let list = changed(revisions, data);
+        revisions.extend(list.iter().cloned());
+        revisions.sort_by_key(|r| (r.at, r.fetched));
+        Ok(list.len())
+    }
+
+    /// revision log entries of points in [from, to)
+    fn revision_range(
+        &self,
+        ts_name: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<Revision>, Box<dyn Error>> {
+        let series = self.series.read().map_err(|e| e.to_string())?;
+        Ok(series
+            .get(ts_name)
+            .map(|s| {
+                s.revisions
+                    .iter()
+                    .filter(|r| (from..to).contains(&r.at.and_utc().timestamp_millis()))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn last(&self, ts_name: &str) -> Result<Option<i64>, Box<dyn Error>> {
+        let series = self.series.read().map_err(|e| e.to_string())?;
+        Ok(series
+            .get(ts_name)
+            .and_then(|s| s.points.last_key_value())
+            .map(|(at, _)| *at))
+    }
+}
+
+fn to_time(millis: i64) -> Result<NaiveDateTime, Box<dyn Error>> {
+    Ok(DateTime::from_timestamp_millis(millis)
+        .ok_or_else(|| format!("wrong time {millis}"))?
+        .naive_utc())
+}
+
+#[async_trait]
+impl SeriesReader for MemoryStorage {
+    async fn live(&self) -> Result<String, Box<dyn Error>> {
+        Ok("ok".to_string())
+    }
+
+    async fn load(
+        &self,
+        ts_name: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<Vec<Data>, Box<dyn Error>> {
+        self.range(ts_name, from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX))
+    }
+
+    async fn query_index(&self, filter: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+        let series = self.series.read().map_err(|e| e.to_string())?;
+        let mut res: Vec<String> = series
+            .iter()
+            .filter(|(_, s)| matches_filter(&s.labels, filter))
+            .map(|(name, _)| name.clone())
+            .collect();
+        res.sort();
+        Ok(res)
+    }
+
+    async fn info(&self, ts_name: &str) -> Result<SeriesInfo, Box<dyn Error>> {
+        let series = self.series.read().map_err(|e| e.to_string())?;
+        let s = series
+            .get(ts_name)
+            .ok_or_else(|| format!("no series {ts_name}"))?;
+        Ok(SeriesInfo {
+            name: ts_name.to_string(),
+            labels: s.labels.clone(),
+            samples: s.points.len() as u64,
+            first: s.points.first_key_value().map(|(at, _)| *at as u64),
+            last: s.points.last_key_value().map(|(at, _)| *at as u64),
+        })
+    }
+
+    async fn revisions(
+        &self,
+        ts_name: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<Vec<Revision>, Box<dyn Error>> {
+        self.revision_range(ts_name, from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX))
+    }
+}
+
+#[async_trait]
+impl DBSaver for MemorySeries {
+    async fn live(&self) -> Result<String, Box<dyn Error>> {
+        Ok("ok".to_string())
+    }
+
+    async fn get_last_time(&self) -> Result<Option<NaiveDateTime>, Box<dyn Error>> {
+        self.storage.last(&self.ts_name)?.map(to_time).transpose()
+    }
+
+    async fn save(&self, data: &Data) -> Result<bool, Box<dyn Error>> {
+        self.storage
+            .add(&self.ts_name, std::slice::from_ref(data))?;
+        Ok(true)
+    }
+
+    async fn save_bulk(&self, data: &[Data]) -> Result<usize, Box<dyn Error>> {
+        self.storage.add(&self.ts_name, data)
+    }
+
+    async fn load(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Vec<Data>, Box<dyn Error>> {
+        self.storage.range(
+            &self.ts_name,
+            from.and_utc().timestamp_millis(),
+            to.and_utc().timestamp_millis(),
+        )
+    }
+
+    async fn save_revisions(&self, data: &[Revision]) -> Result<usize, Box<dyn Error>> {
+        self.storage.add_revisions(&self.ts_name, data)
+    }
+
+    async fn load_revisions(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Vec<Revision>, Box<dyn Error>> {
+        self.storage.revision_range(
+            &self.ts_name,
+            from.and_utc().timestamp_millis(),
+            to.and_utc().timestamp_millis(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate};
+
+    use super::*;
+    use crate::data::Statistic;
+    use crate::series::{price_filter, RES_DAY, RES_HOUR, STAT_AVG, STAT_RAW};
+
+    fn at(h: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            + Duration::hours(h)
+    }
+
+    fn d(h: i64, price: f64) -> Data {
+        Data { at: at(h), price }
+    }
+
+    #[tokio::test]
+    async fn save_load() {
+        let storage = MemoryStorage::new();
+        let db = storage.series("np_lt");
+        assert_eq!(db.get_last_time().await.unwrap(), None);
+        assert_eq!(
+            db.save_bulk(&[d(0, 1.0), d(1, 2.0), d(2, 3.0)])
+                .await
+                .unwrap(),
+            3
+        );
+        assert!(db.save(&d(1, 5.0)).await.unwrap());
+        assert_eq!(db.get_last_time().await.unwrap(), Some(at(2)));
+        assert_eq!(
+            db.load(at(0), at(2)).await.unwrap(),
+            vec![d(0, 1.0), d(1, 5.0)]
+        );
+        assert!(db.load(at(2), at(2)).await.unwrap().is_empty());
+        assert!(storage
+            .series("other")
+            .load(at(0), at(4))
+            .await
+            .unwrap()
+            .is_empty());
+        let res = db
+            .aggregate(at(0), at(3), Statistic::Max)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((res.value, res.last), (5.0, at(2)));
+    }
+
+    #[tokio::test]
+    async fn finds_series() {
+        let storage = MemoryStorage::new();
+        let labels = Labels::price("LT", RES_HOUR, STAT_RAW);
+        storage.create("np_lt", &labels).unwrap();
+        storage
+            .create("np_lt_d", &labels.with(RES_DAY, STAT_AVG))
+            .unwrap();
+        storage.add("np_lt_d", &[d(0, 1.0), d(24, 2.0)]).unwrap();
+        assert_eq!(
+            storage
+                .query_index(&price_filter("LT", RES_DAY, STAT_AVG))
+                .await
+                .unwrap(),
+            vec!["np_lt_d"]
+        );
+        let info = storage.info("np_lt_d").await.unwrap();
+        assert_eq!(info.samples, 2);
+        assert_eq!(info.last, Some(at(24).and_utc().timestamp_millis() as u64));
+        assert_eq!(storage.info("np_lt").await.unwrap().first, None);
+        assert!(storage.info("none").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn keeps_revisions() {
+        let storage = MemoryStorage::new();
+        let db = storage.series("np_lt");
+        let rev = |h: i64, price: f64, revision: u32, fetched: i64| Revision {
+            at: at(h),
+            price,
+            revision,
+            published: None,
+            fetched: at(fetched),
+        };
+        let first = vec![rev(10, 1.0, 1, 0), rev(11, 2.0, 1, 0)];
+        assert_eq!(db.save_revisions(&first).await.unwrap(), 2);
+        let second = vec![rev(10, 1.5, 2, 5), rev(11, 2.0, 1, 5)];
+        assert_eq!(db.save_revisions(&second).await.unwrap(), 1);
+        assert_eq!(
+            storage.revisions("np_lt", None, None).await.unwrap(),
+            vec![rev(10, 1.0, 1, 0), rev(10, 1.5, 2, 5), rev(11, 2.0, 1, 0)]
+        );
+        assert_eq!(
+            db.load_revisions(at(11), at(12)).await.unwrap(),
+            vec![rev(11, 2.0, 1, 0)]
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use std::error::Error;
+
+use emarket::{
+    rates::MEASURE_RATE,
+    redis_pool::{Connection, RedisArgs, RedisPool, LABEL_NAMESPACE},
+    revisions,
+};
+use redis::Value;
+use redis_ts::{AsyncTsCommands, TsInfo};
+use serde::Serialize;
+
+#[derive(clap::Args, Debug)]
+pub struct MigrateArgs {
+    /// Namespace of the existing keys, keys without a prefix if not set
+    #[arg(long)]
+    pub from_namespace: Option<String>,
+    /// List the renames without applying them
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+    /// time series, its namespace label is updated too
+    pub series: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MigrateReport {
+    pub namespace: Option<String>,
+    pub renamed: Vec<Rename>,
+    pub dry_run: bool,
+}
+
+/// renames of the series and their revision logs from the `source` to the `target` namespace
+pub fn plan(source: &RedisArgs, target: &RedisArgs, names: &[&str]) -> Vec<Rename> {
+    let mut res = vec![];
+    for name in names {
+        let (from, to) = (source.key(name), target.key(name));
+        if from == to {
+            continue;
+        }
+        res.push(Rename {
+            from: revisions::key(&from),
+            to: revisions::key(&to),
+            series: false,
+        });
+        res.push(Rename {
+            from,
+            to,
+            series: true,
+        });
+    }
+    res
+}
+
+/// series labels with the namespace label replaced
+pub fn with_namespace(labels: SeriesLabels, namespace: Option<&str>) -> SeriesLabels {
+    let mut res: SeriesLabels = labels
+        .into_iter()
+        .filter(|(k, _)| k != LABEL_NAMESPACE)
+        .collect();
+    if let Some(ns) = namespace {
+        res.push((LABEL_NAMESPACE.to_string(), ns.to_string()));
+    }
+    res
+}
+
+/// Renames the existing keys of `names` into the namespace of the pool in one transaction.
+/// Refuses to run if any target key exists, fails if the keys change meanwhile.
+pub async fn migrate(
+    pool: &RedisPool,
+    from_namespace: Option<String>,
+    names: &[&str],
+    dry_run: bool,
+) -> Result<MigrateReport, Box<dyn Error>> {
+    let target = pool.args();
+    let source = RedisArgs {
+        redis_namespace: from_namespace,
+        ..target.clone()
+    };
+    let mut conn = pool.get().await?;
+    let rates = rate_names(&mut conn, &source).await?;
+    let names: Vec<&str> = names
+        .iter()
+        .copied()
+        .chain(rates.iter().map(String::as_str))
+        .collect();
+    let list = plan(&source, target, &names);
+    if list.is_empty() {
+        return Err("source and target namespaces are the same".into());
+    }
+    let keys: Vec<&String> = list.iter().flat_map(|r| [&r.from, &r.to]).collect();
+    let _: () = redis::cmd("WATCH")
+        .arg(&keys)
+        .query_async(&mut conn)
+        .await?;
+    let report = |renamed| MigrateReport {
+        namespace: target.namespace().map(str::to_string),
+        renamed,
+        dry_run,
+    };
+    let (list, labels) = match prepare(&mut conn, list, target.namespace()).await {
+        Ok(v) => v,
+        Err(err) => {
+            let _: () = redis::cmd("UNWATCH").query_async(&mut conn).await?;
+            return Err(err);
+        }
+    };
+    if dry_run || list.is_empty() {
+        let _: () = redis::cmd("UNWATCH").query_async(&mut conn).await?;
+        return Ok(report(list));
+    }
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for (r, labels) in list.iter().zip(labels.iter()) {
+        pipe.cmd("RENAME").arg(&r.from).arg(&r.to);
+        if let Some(labels) = labels {
+            pipe.cmd("TS.ALTER").arg(&r.to).arg("LABELS").arg(labels);
+        }
+    }
+    let res: Value = pipe.query_async(&mut conn).await?;
+    if res == Value::Nil {
+        return Err("keys changed during the migration, nothing renamed".into());
+    }
+    for r in list.iter() {
+        log::info!("renamed {} -> {}", r.from, r.to);
+    }
+    Ok(report(list))
+}
+
+type SeriesLabels = Vec<(String, String)>;
+
+/// names of the ECB rate series (`ecb_pln`, ...) in the `source` namespace
+async fn rate_names(
+    conn: &mut Connection,
+    source: &RedisArgs,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let keys: Vec<String> = redis::cmd("TS.QUERYINDEX")
+        .arg(format!("measure={MEASURE_RATE}"))
+        .arg(source.namespace_filter())
+        .query_async(conn)
+        .await?;
+    Ok(keys
+        .iter()
+        .filter(|k| source.key(source.name(k)) == **k)
+        .map(|k| source.name(k).to_string())
+        .collect())
+}
+
+/// keeps the renames of existing keys, loads the new labels of the series
+async fn prepare(
+    conn: &mut Connection,
+    list: Vec<Rename>,
+    namespace: Option<&str>,
+) -> Result<(Vec<Rename>, Vec<Option<SeriesLabels>>), Box<dyn Error>> {
+    let mut res = vec![];
+    let mut labels = vec![];
+    for r in list {
+        let exists: bool = redis::cmd("EXISTS").arg(&r.to).query_async(conn).await?;
+        if exists {
+            return Err(format!("target key {} exists", r.to).into());
+        }
+        let exists: bool = redis::cmd("EXISTS").arg(&r.from).query_async(conn).await?;
+        if !exists {
+            continue;
+        }
+        labels.push(match r.series {
+            true => {
+                let info: TsInfo = conn.ts_info(&r.from).await?;
+                Some(with_namespace(info.labels, namespace))
+            }
+            false => None,
+        });
+        res.push(r);
+    }
+    Ok((res, labels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_renames() {
+        let source = RedisArgs::default();
+        let target = RedisArgs {
+            redis_namespace: Some("stage".to_string()),
+            ..Default::default()
+        };
+        let list = plan(&source, &target, &["np_lt"]);
+        assert_eq!(
+            list,
+            vec![
+                Rename {
+                    from: "np_lt:rev".to_string(),
+                    to: "stage:np_lt:rev".to_string(),
+                    series: false,
+                },
+                Rename {
+                    from: "np_lt".to_string(),
+                    to: "stage:np_lt".to_string(),
+                    series: true,
+                },
+            ]
+        );
+        assert!(plan(&target, &target, &["np_lt"]).is_empty());
+        let cluster = |ns: &str| RedisArgs {
+            redis_cluster: true,
+            redis_hash_tag: "lt".to_string(),
+            redis_namespace: Some(ns.to_string()),
+            ..Default::default()
+        };
+        let list = plan(&cluster("stage"), &cluster("prod"), &["np_lt_d"]);
+        assert_eq!(list[1].from, "{lt}stage:np_lt_d");
+        assert_eq!(list[1].to, "{lt}prod:np_lt_d");
+    }
+
+    #[test]
+    fn replaces_namespace_label() {
+        let labels = vec![
+            ("zone".to_string(), "LT".to_string()),
+            ("namespace".to_string(), "stage".to_string()),
+        ];
+        assert_eq!(
+            with_namespace(labels.clone(), Some("prod")),
+            vec![
+                ("zone".to_string(), "LT".to_string()),
+                ("namespace".to_string(), "prod".to_string()),
+            ]
+        );
+        assert_eq!(
+            with_namespace(labels, None),
+            vec![("zone".to_string(), "LT".to_string())]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{naive::serde::ts_milliseconds, Duration, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{data::Data, revisions::Revision};
+
+/// How a stored point was obtained, kept as a code in a parallel series
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+    /// first value published by the source
+    Original,
+    /// missing in the source, copied from the previous point
+    ForwardFilled,
+    /// missing in the source, calculated from the neighbour points
+    Interpolated,
+    /// value of a later source revision
+    Revised,
+}
+
+impl Quality {
+    pub fn code(self) -> f64 {
+        match self {
+            Quality::Original => 0.0,
+            Quality::ForwardFilled => 1.0,
+            Quality::Interpolated => 2.0,
+            Quality::Revised => 3.0,
+        }
+    }
+
+    pub fn from_code(code: f64) -> Option<Quality> {
+        match code as i64 {
+            0 => Some(Quality::Original),
+            1 => Some(Quality::ForwardFilled),
+            2 => Some(Quality::Interpolated),
+            3 => Some(Quality::Revised),
+            _ => None,
+        }
+    }
+
+    /// the value is not from the source
+    pub fn is_filled(self) -> bool {
+        matches!(self, Quality::ForwardFilled | Quality::Interpolated)
+    }
+}
+
+/// flags of the points to save: points missing in `source` were forward filled,
+/// the code is kept as the point price
+pub fn flags(data: &[Data], source: &[Revision]) -> Vec<Data> {
+    let revisions: HashMap<NaiveDateTime, u32> =
+        source.iter().map(|r| (r.at, r.revision)).collect();
+    data.iter()
+        .map(|d| {
+            let q = match revisions.get(&d.at) {
+                None => Quality::ForwardFilled,
+                Some(r) if *r > 1 => Quality::Revised,
+                Some(_) => Quality::Original,
+            };
+            Data {
+                at: d.at,
+                price: q.code(),
+            }
+        })
+        .collect()
+}
+
+/// flags by the point time
+pub fn by_time(flags: &[Data]) -> BTreeMap<NaiveDateTime, Quality> {
+    flags
+        .iter()
+        .filter_map(|d| Some((d.at, Quality::from_code(d.price)?)))
+        .collect()
+}
+
+/// Point counts of one day
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DayQuality {
+    #[serde(with = "ts_milliseconds")]
+    pub at: NaiveDateTime,
+    /// points of a complete day
+    pub expected: i64,
+    pub points: i64,
+    pub original: i64,
+    /// forward filled or interpolated
+    pub filled: i64,
+    pub revised: i64,
+    /// points stored before the flags were kept
+    pub unflagged: i64,
+    pub missing: i64,
+}
+
+/// counts the stored points of every day by their flags, `days` are the day
+/// bounds, `step` is the expected distance between the points
+pub fn summarize(
+    days: &[(NaiveDateTime, NaiveDateTime)],
+    data: &[Data],
+    flags: &[Data],
+    step: Duration,
+) -> Vec<DayQuality> {
+    let flags = by_time(flags);
+    days.iter()
+        .map(|(from, to)| {
+            let mut res = DayQuality {
+                at: *from,
+                expected: (*to - *from).num_milliseconds() / step.num_milliseconds().max(1),
+                ..Default::default()
+            };
+            for d in data.iter().filter(|d| d.at >= *from && d.at < *to) {
+                res.points += 1;
+                match flags.get(&d.at) {
+                    Some(Quality::Original) => res.original += 1,
+                    Some(Quality::Revised) => res.revised += 1,
+                    Some(_) => res.filled += 1,
+                    None => res.unflagged += 1,
+                }
+            }
+            res.missing = (res.expected - res.points).max(0);
+            res
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn at(h: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            + Duration::hours(h)
+    }
+
+    fn d(h: i64, price: f64) -> Data {
+        Data { at: at(h), price }
+    }
+
+    fn rev(h: i64, revision: u32) -> Revision {
+        Revision {
+            at: at(h),
+            price: 1.0,
+            revision,
+            published: None,
+            fetched: at(0),
+        }
+    }
+
+    #[test]
+    fn flags_points() {
+        let data = vec![d(0, 1.0), d(1, 1.0), d(2, 2.0)];
+        let res = flags(&data, &[rev(0, 1), rev(2, 2)]);
+        assert_eq!(
+            res,
+            vec![
+                d(0, Quality::Original.code()),
+                d(1, Quality::ForwardFilled.code()),
+                d(2, Quality::Revised.code()),
+            ]
+        );
+        for q in [
+            Quality::Original,
+            Quality::ForwardFilled,
+            Quality::Interpolated,
+            Quality::Revised,
+        ] {
+            assert_eq!(Quality::from_code(q.code()), Some(q));
+        }
+        assert_eq!(Quality::from_code(10.0), None);
+        assert_eq!(
+            serde_json::to_string(&Quality::ForwardFilled).unwrap(),
+            r#""forward_filled""#
+        );
+    }
+
+    #[test]
+    fn summarizes_days() {
+        let data = vec![d(0, 1.0), d(1, 1.0), d(2, 2.0), d(3, 2.0), d(24, 1.0)];
+        let flags = vec![
+            d(0, Quality::Original.code()),
+            d(1, Quality::ForwardFilled.code()),
+            d(2, Quality::Revised.code()),
+            d(24, Quality::Interpolated.code()),
+        ];
+        let res = summarize(
+            &[(at(0), at(24)), (at(24), at(48))],
+            &data,
+            &flags,
+            Duration::hours(1),
+        );
+        assert_eq!(
+            res[0],
+            DayQuality {
+                at: at(0),
+                expected: 24,
+                points: 4,
+                original: 1,
+                filled: 1,
+                revised: 1,
+                unflagged: 1,
+                missing: 20,
+            }
+        );
+        assert_eq!(res[1].filled, 1);
+        assert_eq!(res[1].missing, 23);
+    }
+}
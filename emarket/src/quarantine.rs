@@ -0,0 +1,174 @@
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{naive::serde::ts_milliseconds, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{data::Data, validation::Issue};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// kept out of the DB until reviewed
+    Pending,
+    /// saved after a review, accepted by later imports
+    Released,
+    /// dropped after a review, dropped by later imports
+    Discarded,
+}
+
+/// Point held back by the validation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: u64,
+    #[serde(with = "ts_milliseconds")]
+    pub at: NaiveDateTime,
+    pub price: f64,
+    pub issues: Vec<Issue>,
+    pub status: Status,
+    #[serde(with = "ts_milliseconds")]
+    pub created: NaiveDateTime,
+    #[serde(with = "ts_milliseconds")]
+    pub updated: NaiveDateTime,
+}
+
+impl Entry {
+    pub fn to_data(&self) -> Data {
+        Data {
+            at: self.at,
+            price: self.price,
+        }
+    }
+}
+
+/// Quarantined points with their review status, kept in a JSON file if a path is set
+#[derive(Clone)]
+pub struct Quarantine {
+    inner: Arc<Mutex<Vec<Entry>>>,
+    path: Option<PathBuf>,
+}
+
+impl Quarantine {
+    pub fn open(path: Option<&Path>) -> Result<Quarantine, Box<dyn Error>> {
+        let list = match path {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(v) => serde_json::from_str(&v)?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+                Err(e) => return Err(e.into()),
+            },
+            None => vec![],
+        };
+        Ok(Quarantine {
+            inner: Arc::new(Mutex::new(list)),
+            path: path.map(Path::to_path_buf),
+        })
+    }
+
+    /// quarantines the point unless the same value was released,
+    /// returns if the point must be kept out of the DB
+    pub fn hold(&self, d: &Data, issues: Vec<Issue>) -> Result<bool, Box<dyn Error>> {
+        let mut list = self.inner.lock().map_err(|e| e.to_string())?;
+        if let Some(e) = list.iter().find(|e| e.at == d.at && e.price == d.price) {
+            return Ok(e.status != Status::Released);
+        }
+        let now = Utc::now().naive_utc();
+        let id = list.iter().map(|e| e.id).max().unwrap_or(0) + 1;
+        log::warn!("quarantine {id}: {} at {}, {issues:?}", d.price, d.at);
+        list.push(Entry {
+            id,
+            at: d.at,
+            price: d.price,
+            issues,
+            status: Status::Pending,
+            created: now,
+            updated: now,
+        });
+        self.save(&list)?;
+        Ok(true)
+    }
+
+    pub fn list(&self, status: Option<Status>) -> Vec<Entry> {
+        match self.inner.lock() {
+            Ok(list) => list
+                .iter()
+                .filter(|e| status.is_none_or(|s| e.status == s))
+                .cloned()
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// marks a pending entry reviewed, `Ok(None)` if there is no such entry
+    pub fn review(&self, id: u64, status: Status) -> Result<Option<Entry>, Box<dyn Error>> {
+        let mut list = self.inner.lock().map_err(|e| e.to_string())?;
+        let Some(entry) = list.iter_mut().find(|e| e.id == id) else {
+            return Ok(None);
+        };
+        if entry.status != Status::Pending {
+            return Err(format!("entry {id} is {:?}", entry.status).into());
+        }
+        entry.status = status;
+        entry.updated = Utc::now().naive_utc();
+        let res = entry.clone();
+        log::info!("quarantine {id}: {status:?}");
+        self.save(&list)?;
+        Ok(Some(res))
+    }
+
+    fn save(&self, list: &[Entry]) -> Result<(), Box<dyn Error>> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(list)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn d(h: u32, price: f64) -> Data {
+        Data {
+            at: NaiveDate::from_ymd_opt(2024, 1, 1)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap(),
+            price,
+        }
+    }
+
+    #[test]
+    fn holds_until_released() {
+        let path = std::env::temp_dir().join(format!("emarket-quarantine-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let q = Quarantine::open(Some(&path)).unwrap();
+        let issues = vec![Issue::AboveMax { limit: 10.0 }];
+        assert!(q.hold(&d(1, 20.0), issues.clone()).unwrap());
+        assert!(q.hold(&d(1, 20.0), issues.clone()).unwrap());
+        assert!(q.hold(&d(2, 30.0), issues.clone()).unwrap());
+        assert_eq!(q.list(Some(Status::Pending)).len(), 2);
+
+        let e = q.review(1, Status::Released).unwrap().unwrap();
+        assert_eq!(e.to_data(), d(1, 20.0));
+        assert!(q.review(1, Status::Discarded).is_err());
+        assert!(q.review(5, Status::Released).unwrap().is_none());
+        q.review(2, Status::Discarded).unwrap();
+        assert!(!q.hold(&d(1, 20.0), issues.clone()).unwrap());
+        assert!(q.hold(&d(2, 30.0), issues.clone()).unwrap());
+        // another value of the released point is held again
+        assert!(q.hold(&d(1, 21.0), issues).unwrap());
+
+        let q = Quarantine::open(Some(&path)).unwrap();
+        assert_eq!(q.list(None).len(), 3);
+        assert_eq!(q.list(Some(Status::Released))[0].id, 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, TimeZone};
+use chrono_tz::Europe::Vilnius;
+use quick_xml::{events::Event, Reader};
+
+use crate::{
+    data::Data,
+    series::{Labels, RES_DAY, STAT_RAW},
+    units::CURRENCY_EUR,
+};
+
+pub const MEASURE_RATE: &str = "rate";
+/// max days a rate is used after its date, the longest ECB closures
+/// (Easter, Christmas) last 4 days
+pub const MAX_RATE_AGE_DAYS: i64 = 5;
+/// zone label of the ECB reference rates
+pub const ZONE_ECB: &str = "ECB";
+
+/// ECB euro reference rate: 1 EUR = `rate` `currency`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rate {
+    pub date: NaiveDate,
+    pub currency: String,
+    pub rate: f64,
+}
+
+impl Rate {
+    /// point of the daily rate series, kept at the UTC midnight of the date
+    pub fn to_data(&self) -> Data {
+        Data {
+            at: self.date.and_hms_opt(0, 0, 0).unwrap_or_default(),
+            price: self.rate,
+        }
+    }
+}
+
+/// series name of the `currency` rates
+pub fn series_name(currency: &str) -> String {
+    format!("ecb_{}", currency.to_lowercase())
+}
+
+/// labels of the `currency` rates series
+pub fn labels(currency: &str) -> Labels {
+    Labels {
+        zone: ZONE_ECB.to_string(),
+        measure: MEASURE_RATE.to_string(),
+        resolution: RES_DAY.to_string(),
+        statistic: STAT_RAW.to_string(),
+        currency: currency.to_uppercase(),
+        unit: CURRENCY_EUR.to_string(),
+    }
+}
+
+/// filter for the `currency` rates series
+pub fn rate_filter(currency: &str) -> Vec<String> {
+    vec![
+        format!("measure={MEASURE_RATE}"),
+        format!("currency={}", currency.to_uppercase()),
+    ]
+}
+
+/// parses the ECB reference rates in the XML (`eurofxref*.xml`) or CSV (`eurofxref*.csv`) format
+pub fn parse(txt: &str) -> Result<Vec<Rate>, String> {
+    match txt.trim_start().starts_with('<') {
+        true => parse_xml(txt),
+        false => parse_csv(txt),
+    }
+}
+
+/// `<Cube time="2024-01-05"><Cube currency="PLN" rate="4.3580"/>...</Cube>`
+pub fn parse_xml(txt: &str) -> Result<Vec<Rate>, String> {
+    let mut reader = Reader::from_str(txt);
+    let mut date = None;
+    let mut res = vec![];
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Eof => break,
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"Cube" => {
+                let mut currency = None;
+                let mut rate = None;
+                for a in e.attributes() {
+                    let a = a.map_err(|e| e.to_string())?;
+                    let value = a.unescape_value().map_err(|e| e.to_string())?;
+                    match a.key.local_name().as_ref() {
+                        b"time" => date = Some(parse_date(&value)?),
+                        b"currency" => currency = Some(value.to_string()),
+                        b"rate" => rate = Some(parse_rate(&value)?),
+                        _ => {}
+                    }
+                }
+                if let (Some(currency), Some(rate)) = (currency, rate) {
+                    let date = date.ok_or_else(|| format!("no date for the {currency} rate"))?;
+                    res.push(Rate {
+                        date,
+                        currency,
+                        rate,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(res)
+}
+
+/// `Date,USD,PLN,` header and a line per date, `N/A` rates are skipped
+pub fn parse_csv(txt: &str) -> Result<Vec<Rate>, String> {
+    let mut lines = txt.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or("empty rates file")?
+        .split(',')
+        .map(str::trim)
+        .collect();
+    if !header[0].eq_ignore_ascii_case("date") {
+        return Err(format!("wrong rates header: {}", header[0]));
+    }
+    let mut res = vec![];
+    for line in lines {
+        let mut values = line.split(',').map(str::trim);
+        let date = parse_date(values.next().unwrap_or_default())?;
+        for (currency, value) in header[1..].iter().zip(values) {
+            if currency.is_empty() || value.is_empty() || value == "N/A" {
+                continue;
+            }
+            res.push(Rate {
+                date,
+                currency: currency.to_string(),
+                rate: parse_rate(value)?,
+            });
+        }
+    }
+    Ok(res)
+}
+
+/// `2024-01-05` or `05 January 2024` as in the daily CSV
+fn parse_date(v: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(v, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(v, "%d %B %Y"))
+        .map_err(|e| format!("wrong date '{v}': {e}"))
+}
+
+fn parse_rate(v: &str) -> Result<f64, String> {
+    match v.parse::<f64>() {
+        Ok(r) if r.is_finite() && r > 0.0 => Ok(r),
+        _ => Err(format!("wrong rate '{v}'")),
+    }
+}
+
+/// Daily rates of one currency
+#[derive(Debug, Clone, Default)]
+pub struct Rates {
+    by_date: BTreeMap<NaiveDate, f64>,
+}
+
+impl Rates {
+    /// rates from the points of a rate series
+    pub fn new(data: &[Data]) -> Rates {
+        Rates {
+            by_date: data.iter().map(|d| (d.at.date(), d.price)).collect(),
+        }
+    }
+
+    /// rate applying on the date: the last one published on or before it,
+    /// ECB publishes no rates on weekends and holidays. Rates older than
+    /// [`MAX_RATE_AGE_DAYS`] do not apply
+    pub fn on(&self, date: NaiveDate) -> Option<f64> {
+        self.by_date
+            .range(..=date)
+            .next_back()
+            .filter(|(d, _)| date - **d <= Duration::days(MAX_RATE_AGE_DAYS))
+            .map(|(_, r)| *r)
+    }
+
+    /// converts the EUR price at `at` with the rate of its Vilnius delivery day
+    pub fn convert(&self, at: NaiveDateTime, price: f64) -> Result<f64, String> {
+        let day = delivery_day(at);
+        self.on(day).map(|r| price * r).ok_or_else(|| {
+            match self.by_date.range(..=day).next_back() {
+                Some((last, _)) => format!("no rate for {day}, the last one is of {last}"),
+                None => format!("no rate for {day}"),
+            }
+        })
+    }
+}
+
+/// Vilnius date of the UTC time
+pub fn delivery_day(at: NaiveDateTime) -> NaiveDate {
+    Vilnius.from_utc_datetime(&at).date_naive()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, d).unwrap()
+    }
+
+    #[test]
+    fn parses_xml() {
+        let txt = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gesmes:Envelope xmlns:gesmes="http://www.gesmes.org/xml/2002-08-01" xmlns="http://www.ecb.int/vocabulary/2002-08-01/eurofxref">
+	<gesmes:subject>Reference rates</gesmes:subject>
+	<Cube>
+		<Cube time="2024-01-05">
+			<Cube currency="USD" rate="1.0921"/>
+			<Cube currency="PLN" rate="4.3580"/>
+		</Cube>
+		<Cube time="2024-01-04">
+			<Cube currency="PLN" rate="4.3515"/>
+		</Cube>
+	</Cube>
+</gesmes:Envelope>"#;
+        let res = parse(txt).unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(
+            res[1],
+            Rate {
+                date: date(5),
+                currency: "PLN".to_string(),
+                rate: 4.358
+            }
+        );
+        assert_eq!(res[2].date, date(4));
+    }
+
+    #[test]
+    fn parses_csv() {
+        let hist =
+            "Date,USD,PLN,SEK,\n2024-01-05,1.0921,4.3580,N/A,\n2024-01-04,1.0953,4.3515,11.2,\n";
+        let res = parse(hist).unwrap();
+        assert_eq!(res.len(), 5);
+        assert_eq!(res[1].currency, "PLN");
+        assert_eq!(res[4].rate, 11.2);
+        let daily = "Date, USD, PLN, \n05 January 2024, 1.0921, 4.3580, \n";
+        let res = parse(daily).unwrap();
+        assert_eq!(res[1].date, date(5));
+        assert!(parse("Date,USD\n2024-01-05,x\n").is_err());
+        assert!(parse("Day,USD\n").is_err());
+    }
+
+    #[test]
+    fn converts_by_delivery_day() {
+        let rate = |d, rate| {
+            Rate {
+                date: date(d),
+                currency: "PLN".to_string(),
+                rate,
+            }
+            .to_data()
+        };
+        // friday and monday, no rates on the weekend
+        let rates = Rates::new(&[rate(5, 4.0), rate(8, 5.0)]);
+        assert_eq!(rates.on(date(4)), None);
+        assert_eq!(rates.on(date(7)), Some(4.0));
+        // 2024-01-07 23:00 UTC is monday in Vilnius
+        let at = date(7).and_hms_opt(23, 0, 0).unwrap();
+        assert_eq!(rates.convert(at, 2.0), Ok(10.0));
+        assert_eq!(rates.convert(at - chrono::Duration::hours(2), 2.0), Ok(8.0));
+        assert!(rates
+            .convert(date(3).and_hms_opt(0, 0, 0).unwrap(), 1.0)
+            .is_err());
+        // stale after a few days without rates
+        assert_eq!(rates.on(date(13)), Some(5.0));
+        assert_eq!(rates.on(date(14)), None);
+        let err = rates
+            .convert(date(20).and_hms_opt(0, 0, 0).unwrap(), 1.0)
+            .unwrap_err();
+        assert!(err.contains("2024-01-08"), "{err}");
+        assert_eq!(series_name("PLN"), "ecb_pln");
+    }
+}
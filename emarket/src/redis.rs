@@ -1,46 +1,215 @@
 use async_trait::async_trait;
-use chrono::NaiveDateTime;
-use deadpool_redis::Pool;
+use chrono::{Duration, NaiveDateTime};
 use emarket::{
-    data::{DBSaver, Data},
+    data::{AggregateValue, DBSaver, Data, Statistic},
+    redis_pool::{Connection, RedisPool, LABEL_NAMESPACE},
+    revisions::{self, changed, range, Revision},
+    series::Labels,
     utils::to_time,
 };
 use redis::RedisError;
-use redis_ts::{AsyncTsCommands, TsOptions, TsRange};
-use std::error::Error;
+use redis_ts::{AsyncTsCommands, TsInfo, TsOptions, TsRange};
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 #[derive(Clone)]
 pub struct RedisClient {
-    pool: Pool,
+    pool: RedisPool,
     ts_name: String,
+    setup: Arc<Setup>,
+}
+
+/// what [`RedisClient::new`] creates in redis
+struct Setup {
+    labels: Labels,
+    compaction: Vec<CompactionRule>,
+    retention: RetentionOptions,
+    done: AtomicBool,
+}
+
+/// Redis side rollup of the series into `dest`
+#[derive(Debug, Clone)]
+pub struct CompactionRule {
+    pub dest: String,
+    pub labels: Labels,
+    pub stat: Statistic,
+    pub bucket: Duration,
+    /// bucket start offset from the epoch
+    pub align: Duration,
+    /// zero keeps data forever
+    pub retention: std::time::Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetentionOptions {
+    /// zero keeps data forever
+    pub keep: std::time::Duration,
+    /// allow shortening retention of a series without rollups
+    pub force: bool,
 }
 
 impl RedisClient {
-    pub async fn new(pool: Pool, ts_name: &str) -> Result<RedisClient, Box<dyn Error>> {
-        let mut conn = pool.get().await?;
-        let r: Result<bool, RedisError> = conn
-            .ts_create(
-                ts_name,
-                TsOptions::default()
-                    .retention_time(0)
-                    .duplicate_policy(redis_ts::TsDuplicatePolicy::Last),
+    /// creates the series and its compaction rules, if redis is not reachable
+    /// that is retried on the first use of the client
+    pub async fn new(
+        pool: RedisPool,
+        ts_name: &str,
+        labels: &Labels,
+        compaction: &[CompactionRule],
+        retention: RetentionOptions,
+    ) -> Result<RedisClient, Box<dyn Error>> {
+        let res = RedisClient {
+            pool,
+            ts_name: ts_name.to_string(),
+            setup: Arc::new(Setup {
+                labels: labels.clone(),
+                compaction: compaction.to_vec(),
+                retention,
+                done: AtomicBool::new(false),
+            }),
+        };
+        match res.pool.get().await {
+            Ok(mut conn) => res.setup(&mut conn).await?,
+            Err(err) => log::warn!("redis: {err}, {ts_name} is set up on the first use"),
+        }
+        Ok(res)
+    }
+
+    /// pooled connection to a set up series
+    async fn conn(&self) -> Result<Connection, Box<dyn Error>> {
+        let mut conn = self.pool.get().await?;
+        if !self.setup.done.load(Ordering::Acquire) {
+            self.setup(&mut conn).await?;
+        }
+        Ok(conn)
+    }
+
+    async fn setup(&self, conn: &mut Connection) -> Result<(), Box<dyn Error>> {
+        let ts_name = self.ts_name.as_str();
+        let Setup {
+            labels,
+            compaction,
+            retention,
+            done,
+        } = self.setup.as_ref();
+        let namespace = self.pool.args().namespace();
+        create(conn, ts_name, labels, namespace).await?;
+        for rule in compaction {
+            create(conn, &rule.dest, &rule.labels, namespace).await?;
+            set_retention(
+                conn,
+                &rule.dest,
+                RetentionOptions {
+                    keep: rule.retention,
+                    force: retention.force,
+                },
             )
-            .await;
-        match r {
-            Ok(_) => {
-                log::info!("DB {} initialized", ts_name);
-            }
-            Err(e) => {
-                if e.detail().unwrap_or("") != "TSDB: key already exists" {
-                    return Err(e).map_err(|e| e.into());
+            .await?;
+            let r: Result<(), RedisError> = redis::cmd("TS.CREATERULE")
+                .arg(ts_name)
+                .arg(&rule.dest)
+                .arg("AGGREGATION")
+                .arg(rule.stat.name())
+                .arg(rule.bucket.num_milliseconds())
+                .arg(rule.align.num_milliseconds())
+                .query_async(conn)
+                .await;
+            match r {
+                Ok(_) => {
+                    log::info!("rule {} -> {} initialized", ts_name, rule.dest);
+                }
+                Err(e) => {
+                    if !e.detail().unwrap_or("").contains("already has") {
+                        return Err(e).map_err(|e| e.into());
+                    }
                 }
             }
         }
-        Ok(RedisClient {
-            pool,
-            ts_name: ts_name.to_string(),
-        })
+        set_retention(conn, ts_name, *retention).await?;
+        done.store(true, Ordering::Release);
+        Ok(())
+    }
+}
+
+async fn create(
+    conn: &mut Connection,
+    ts_name: &str,
+    labels: &Labels,
+    namespace: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let r: Result<bool, RedisError> = conn
+        .ts_create(
+            ts_name,
+            TsOptions::default()
+                .retention_time(0)
+                .duplicate_policy(redis_ts::TsDuplicatePolicy::Last),
+        )
+        .await;
+    match r {
+        Ok(_) => {
+            log::info!("DB {} initialized", ts_name);
+        }
+        Err(e) => {
+            if e.detail().unwrap_or("") != "TSDB: key already exists" {
+                return Err(e).map_err(|e| e.into());
+            }
+        }
+    }
+    // labels are set on existing series too
+    let mut labels = labels.to_vec();
+    if let Some(ns) = namespace {
+        labels.push((LABEL_NAMESPACE, ns));
     }
+    let _: () = redis::cmd("TS.ALTER")
+        .arg(ts_name)
+        .arg("LABELS")
+        .arg(labels)
+        .query_async(conn)
+        .await?;
+    Ok(())
+}
+
+/// applies the retention to an existing series, refuses to drop data
+/// of a series without rollups unless forced
+async fn set_retention(
+    conn: &mut Connection,
+    ts_name: &str,
+    retention: RetentionOptions,
+) -> Result<(), Box<dyn Error>> {
+    let info: TsInfo = conn.ts_info(ts_name).await?;
+    let keep = u64::try_from(retention.keep.as_millis())?;
+    if info.retention_time == keep {
+        return Ok(());
+    }
+    if is_shorter(keep, info.retention_time) && info.rules.is_empty() && !retention.force {
+        return Err(format!(
+            "refuse to shorten retention of {ts_name} without rollups ({} -> {} ms), force it to proceed",
+            info.retention_time, keep
+        )
+        .into());
+    }
+    let _: () = redis::cmd("TS.ALTER")
+        .arg(ts_name)
+        .arg("RETENTION")
+        .arg(keep)
+        .query_async(conn)
+        .await?;
+    log::info!(
+        "{ts_name} retention changed {} -> {} ms",
+        info.retention_time,
+        keep
+    );
+    Ok(())
+}
+
+/// zero means forever
+fn is_shorter(new: u64, old: u64) -> bool {
+    new != 0 && (old == 0 || new < old)
 }
 
 #[async_trait]
@@ -54,7 +223,7 @@ impl DBSaver for RedisClient {
 
     async fn get_last_time(&self) -> Result<Option<NaiveDateTime>, Box<dyn Error>> {
         log::debug!("invoke live");
-        let mut conn = self.pool.get().await?;
+        let mut conn = self.conn().await?;
         let latest: Option<(u64, f64)> = conn.ts_get(self.ts_name.as_str()).await?;
         let res = match latest {
             Some((t, _)) => {
@@ -70,7 +239,7 @@ impl DBSaver for RedisClient {
     }
 
     async fn save(&self, data: &Data) -> Result<bool, Box<dyn Error>> {
-        let mut conn = self.pool.get().await?;
+        let mut conn = self.conn().await?;
         let _: () = conn
             .ts_add(
                 self.ts_name.as_str(),
@@ -81,13 +250,36 @@ impl DBSaver for RedisClient {
         Ok(true)
     }
 
+    async fn save_bulk(&self, data: &[Data]) -> Result<usize, Box<dyn Error>> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let mut conn = self.conn().await?;
+        let values: Vec<(&str, i64, f64)> = data
+            .iter()
+            .map(|d| {
+                (
+                    self.ts_name.as_str(),
+                    d.at.and_utc().timestamp_millis(),
+                    d.price,
+                )
+            })
+            .collect();
+        let res: Vec<redis::Value> = conn.ts_madd(&values).await?;
+        if let Some(v) = res.iter().find(|v| !matches!(v, redis::Value::Int(_))) {
+            return Err(format!("TS.MADD {} failed: {:?}", self.ts_name, v).into());
+        }
+        log::debug!("saved {} points to {}", data.len(), self.ts_name);
+        Ok(data.len())
+    }
+
     async fn load(
         &self,
         from: NaiveDateTime,
         to: NaiveDateTime,
     ) -> Result<Vec<Data>, Box<dyn Error>> {
         log::debug!("invoke load");
-        let mut conn = self.pool.get().await?;
+        let mut conn = self.conn().await?;
         let none_int: Option<u64> = None;
         let list: TsRange<u64, f64> = conn
             .ts_range(
@@ -108,4 +300,109 @@ impl DBSaver for RedisClient {
             .collect();
         Ok(res)
     }
+
+    async fn aggregate(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        stat: Statistic,
+    ) -> Result<Option<AggregateValue>, Box<dyn Error>> {
+        log::debug!("invoke aggregate {}", stat.name());
+        let mut conn = self.conn().await?;
+        let from = from.and_utc().timestamp_millis();
+        let to = to.and_utc().timestamp_millis() - 1; // imitate range [from, to)
+        let (values, last): (Vec<(u64, f64)>, Vec<(u64, f64)>) = redis::pipe()
+            .cmd("TS.RANGE")
+            .arg(self.ts_name.as_str())
+            .arg(from)
+            .arg(to)
+            .arg("ALIGN")
+            .arg("start")
+            .arg("AGGREGATION")
+            .arg(stat.name())
+            .arg(to - from + 1)
+            .cmd("TS.REVRANGE")
+            .arg(self.ts_name.as_str())
+            .arg(from)
+            .arg(to)
+            .arg("COUNT")
+            .arg(1)
+            .query_async(&mut conn)
+            .await?;
+        let res = match (values.first(), last.first()) {
+            (Some(v), Some(l)) => Some(AggregateValue {
+                value: v.1,
+                last: to_time(l.0),
+            }),
+            _ => None,
+        };
+        Ok(res)
+    }
+
+    async fn save_revisions(&self, data: &[Revision]) -> Result<usize, Box<dyn Error>> {
+        let Some((from, to)) = range(data) else {
+            return Ok(0);
+        };
+        let known = self.load_revisions(from, to).await?;
+        let list = changed(&known, data);
+        if list.is_empty() {
+            return Ok(0);
+        }
+        let key = revisions::key(&self.ts_name);
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        let cmd = pipe.cmd("ZADD").arg(&key);
+        for r in list.iter() {
+            cmd.arg(r.at.and_utc().timestamp_millis())
+                .arg(serde_json::to_string(r)?);
+        }
+        let keep = self.setup.retention.keep;
+        if !keep.is_zero() {
+            // as the series retention, relative to the newest point
+            let till = to.and_utc().timestamp_millis() - keep.as_millis() as i64;
+            pipe.cmd("ZREMRANGEBYSCORE")
+                .arg(&key)
+                .arg("-inf")
+                .arg(format!("({till}"))
+                .ignore();
+        }
+        let mut conn = self.conn().await?;
+        let _: () = pipe.query_async(&mut conn).await?;
+        log::debug!("saved {} revisions to {}", list.len(), self.ts_name);
+        Ok(list.len())
+    }
+
+    async fn load_revisions(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Vec<Revision>, Box<dyn Error>> {
+        let mut conn = self.conn().await?;
+        let list: Vec<String> = redis::cmd("ZRANGEBYSCORE")
+            .arg(revisions::key(&self.ts_name))
+            .arg(from.and_utc().timestamp_millis())
+            .arg(format!("({}", to.and_utc().timestamp_millis()))
+            .query_async(&mut conn)
+            .await?;
+        let mut res = list
+            .iter()
+            .map(|s| serde_json::from_str(s))
+            .collect::<Result<Vec<Revision>, _>>()?;
+        res.sort_by_key(|r| (r.at, r.fetched));
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_shorter;
+
+    #[test]
+    fn shorter_retention() {
+        assert!(is_shorter(10, 0));
+        assert!(is_shorter(10, 20));
+        assert!(!is_shorter(0, 20));
+        assert!(!is_shorter(0, 0));
+        assert!(!is_shorter(30, 20));
+    }
 }
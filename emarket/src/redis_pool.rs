@@ -0,0 +1,680 @@
+use std::{
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use clap::Args;
+use deadpool::{
+    managed::{self, BuildError, Hook, HookError, HookErrorCause, Object, PoolError},
+    Runtime,
+};
+use redis::{
+    aio::{AsyncStream, ConnectionLike},
+    ConnectionAddr, ConnectionInfo, IntoConnectionInfo, RedisConnectionInfo, RedisError,
+    RedisResult,
+};
+use thiserror::Error;
+use tokio_native_tls::TlsConnector;
+
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+const CLUSTER_SLOTS: u16 = 16384;
+/// series label with the namespace of its key
+pub const LABEL_NAMESPACE: &str = "namespace";
+
+/// Redis connection options shared by the binaries
+#[derive(Args, Debug, Clone, Default)]
+pub struct RedisArgs {
+    /// Redis ACL user, overrides the one in the url
+    #[arg(long, env)]
+    pub redis_username: Option<String>,
+    /// Redis password, overrides the one in the url
+    #[arg(long, env)]
+    pub redis_password: Option<String>,
+    /// PEM file with CA certificates for rediss:// connections
+    #[arg(long, env)]
+    pub redis_ca_cert: Option<PathBuf>,
+    /// Sentinels host:port, the master is discovered through them
+    #[arg(long, env, value_delimiter = ',')]
+    pub redis_sentinels: Vec<String>,
+    /// Master name monitored by the sentinels
+    #[arg(long, env, default_value = "mymaster")]
+    pub redis_sentinel_master: String,
+    /// Sentinel password
+    #[arg(long, env)]
+    pub redis_sentinel_password: Option<String>,
+    /// Redis Cluster, the url points to any node of the cluster
+    #[arg(long, env, default_value_t = false)]
+    pub redis_cluster: bool,
+    /// Hash tag of the series keys in the cluster, all series share its slot
+    #[arg(long, env, default_value = "emarket")]
+    pub redis_hash_tag: String,
+    /// Key prefix `<namespace>:` and `namespace` label of the series, lets environments share one redis
+    #[arg(long, env)]
+    pub redis_namespace: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Single,
+    Sentinel,
+    Cluster,
+}
+
+impl RedisArgs {
+    fn mode(&self) -> Result<Mode, RedisPoolError> {
+        match (self.redis_sentinels.is_empty(), self.redis_cluster) {
+            (true, false) => Ok(Mode::Single),
+            (false, false) => Ok(Mode::Sentinel),
+            (true, true) => Ok(Mode::Cluster),
+            (false, true) => Err(RedisPoolError::Config(
+                "sentinels and cluster can't be used together".to_string(),
+            )),
+        }
+    }
+
+    /// key of a series prefixed by the namespace, hash tagged in the cluster mode
+    /// so that compaction rules and multi key commands stay in one slot
+    pub fn key(&self, name: &str) -> String {
+        let name = match self.namespace() {
+            Some(ns) => format!("{ns}:{name}"),
+            None => name.to_string(),
+        };
+        if self.redis_cluster {
+            return format!("{{{}}}{name}", self.redis_hash_tag);
+        }
+        name
+    }
+
+    /// series name of the key, the reverse of [`RedisArgs::key`]
+    pub fn name<'a>(&self, key: &'a str) -> &'a str {
+        let mut res = key;
+        if self.redis_cluster {
+            let tag = format!("{{{}}}", self.redis_hash_tag);
+            res = res.strip_prefix(&tag).unwrap_or(res);
+        }
+        if let Some(ns) = self.namespace() {
+            res = res
+                .strip_prefix(ns)
+                .and_then(|v| v.strip_prefix(':'))
+                .unwrap_or(res);
+        }
+        res
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.redis_namespace.as_deref().filter(|ns| !ns.is_empty())
+    }
+
+    /// `TS.QUERYINDEX` filter of the namespace series, series without
+    /// the label belong to the default namespace
+    pub fn namespace_filter(&self) -> String {
+        format!("{LABEL_NAMESPACE}={}", self.namespace().unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RedisPoolError {
+    #[error(transparent)]
+    Redis(#[from] RedisError),
+    #[error(transparent)]
+    Pool(#[from] PoolError<RedisError>),
+    #[error(transparent)]
+    Build(#[from] BuildError<RedisError>),
+    #[error("redis TLS: {0}")]
+    Tls(#[from] native_tls::Error),
+    #[error("redis config: {0}")]
+    Config(String),
+    #[error("no redis master: {0}")]
+    NoMaster(String),
+}
+
+type Pool = managed::Pool<Manager, Connection>;
+
+/// Opens redis connections, TLS ones trust the CAs of `--redis-ca-cert` if set
+pub struct Manager {
+    info: ConnectionInfo,
+    tls: Option<TlsConnector>,
+    ping: AtomicUsize,
+}
+
+#[async_trait]
+impl managed::Manager for Manager {
+    type Type = redis::aio::Connection;
+    type Error = RedisError;
+
+    async fn create(&self) -> Result<redis::aio::Connection, RedisError> {
+        connect(&self.info, self.tls.as_ref()).await
+    }
+
+    async fn recycle(
+        &self,
+        conn: &mut redis::aio::Connection,
+    ) -> managed::RecycleResult<RedisError> {
+        let ping = self.ping.fetch_add(1, Ordering::Relaxed).to_string();
+        let res: String = redis::cmd("PING").arg(&ping).query_async(conn).await?;
+        match res == ping {
+            true => Ok(()),
+            false => Err(managed::RecycleError::StaticMessage("wrong PING response")),
+        }
+    }
+}
+
+/// Pooled connection, returned to the pool on drop
+pub struct Connection(Object<Manager>);
+
+impl From<Object<Manager>> for Connection {
+    fn from(conn: Object<Manager>) -> Self {
+        Connection(conn)
+    }
+}
+
+impl Deref for Connection {
+    type Target = redis::aio::Connection;
+
+    fn deref(&self) -> &redis::aio::Connection {
+        &self.0
+    }
+}
+
+impl DerefMut for Connection {
+    fn deref_mut(&mut self) -> &mut redis::aio::Connection {
+        &mut self.0
+    }
+}
+
+impl ConnectionLike for Connection {
+    fn req_packed_command<'a>(
+        &'a mut self,
+        cmd: &'a redis::Cmd,
+    ) -> redis::RedisFuture<'a, redis::Value> {
+        self.0.req_packed_command(cmd)
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a redis::Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> redis::RedisFuture<'a, Vec<redis::Value>> {
+        self.0.req_packed_commands(cmd, offset, count)
+    }
+
+    fn get_db(&self) -> i64 {
+        self.0.get_db()
+    }
+}
+
+struct Inner {
+    base: ConnectionInfo,
+    args: RedisArgs,
+    tls: Option<TlsConnector>,
+    mode: Mode,
+    /// pool of the current master and its generation, none until the master is found
+    pool: RwLock<(u64, Option<Pool>)>,
+    reconnect: tokio::sync::Mutex<()>,
+}
+
+/// Connection pool following the master. With sentinels or a cluster every connection
+/// is checked to be a master, when none is available the master is looked up again.
+/// `MOVED`/`ASK` redirects are not followed, moving the slot of the series to another
+/// cluster master needs a restart.
+#[derive(Clone)]
+pub struct RedisPool {
+    inner: Arc<Inner>,
+}
+
+impl RedisPool {
+    pub async fn new(url: &str, args: &RedisArgs) -> Result<RedisPool, RedisPoolError> {
+        let mode = args.mode()?;
+        let mut base = url.into_connection_info()?;
+        let tls = match (&args.redis_ca_cert, &base.addr) {
+            (Some(ca), ConnectionAddr::TcpTls { insecure, .. }) => {
+                Some(tls_connector(ca, *insecure)?)
+            }
+            _ => None,
+        };
+        if let Some(username) = &args.redis_username {
+            base.redis.username = Some(username.clone());
+        }
+        if let Some(password) = &args.redis_password {
+            base.redis.password = Some(password.clone());
+        }
+        let pool = match resolve(&base, args, mode, tls.as_ref()).await {
+            Ok(info) => Some(build(info, mode, tls.clone())?),
+            Err(err) => {
+                log::warn!("redis: {err}, the master is looked up again on the first use");
+                None
+            }
+        };
+        Ok(RedisPool {
+            inner: Arc::new(Inner {
+                base,
+                args: args.clone(),
+                tls,
+                mode,
+                pool: RwLock::new((0, pool)),
+                reconnect: tokio::sync::Mutex::new(()),
+            }),
+        })
+    }
+
+    pub async fn get(&self) -> Result<Connection, RedisPoolError> {
+        let (generation, pool) = self.current();
+        if let Some(pool) = pool {
+            match pool.get().await {
+                Ok(conn) => return Ok(conn),
+                Err(e) if self.inner.mode == Mode::Single => return Err(e.into()),
+                Err(e) => log::warn!("redis connection: {e}, looking for the master"),
+            }
+        }
+        let pool = self.reconnect(generation).await?;
+        Ok(pool.get().await?)
+    }
+
+    pub fn args(&self) -> &RedisArgs {
+        &self.inner.args
+    }
+
+    fn current(&self) -> (u64, Option<Pool>) {
+        let res = self.inner.pool.read().unwrap_or_else(|e| e.into_inner());
+        (res.0, res.1.clone())
+    }
+
+    /// replaces the pool of generation `failed`, skipped if already done by another caller
+    async fn reconnect(&self, failed: u64) -> Result<Pool, RedisPoolError> {
+        let _guard = self.inner.reconnect.lock().await;
+        let (generation, pool) = self.current();
+        if let (true, Some(pool)) = (generation != failed, pool) {
+            return Ok(pool);
+        }
+        let inner = self.inner.as_ref();
+        let info = resolve(&inner.base, &inner.args, inner.mode, inner.tls.as_ref()).await?;
+        let pool = build(info, inner.mode, inner.tls.clone())?;
+        *self.inner.pool.write().unwrap_or_else(|e| e.into_inner()) =
+            (generation + 1, Some(pool.clone()));
+        Ok(pool)
+    }
+}
+
+/// TLS connector trusting the certificates of the PEM file `ca`
+fn tls_connector(ca: &Path, insecure: bool) -> Result<TlsConnector, RedisPoolError> {
+    let pem = std::fs::read_to_string(ca)
+        .map_err(|e| RedisPoolError::Config(format!("CA file {}: {e}", ca.display())))?;
+    let certs = pem_certificates(&pem);
+    if certs.is_empty() {
+        return Err(RedisPoolError::Config(format!(
+            "no certificates in {}",
+            ca.display()
+        )));
+    }
+    let mut builder = native_tls::TlsConnector::builder();
+    for cert in certs {
+        builder.add_root_certificate(native_tls::Certificate::from_pem(cert.as_bytes())?);
+    }
+    builder.danger_accept_invalid_certs(insecure);
+    Ok(builder.build()?.into())
+}
+
+/// certificate blocks of a PEM bundle
+fn pem_certificates(pem: &str) -> Vec<&str> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    pem.split_inclusive("-----END CERTIFICATE-----")
+        .filter_map(|c| c.find(BEGIN).map(|i| &c[i..]))
+        .filter(|c| c.ends_with("-----END CERTIFICATE-----"))
+        .collect()
+}
+
+/// opens a connection, TLS ones with `tls` if set, otherwise with the system CAs
+async fn connect(
+    info: &ConnectionInfo,
+    tls: Option<&TlsConnector>,
+) -> RedisResult<redis::aio::Connection> {
+    match (&info.addr, tls) {
+        (ConnectionAddr::TcpTls { host, port, .. }, Some(tls)) => {
+            let tcp = tokio::net::TcpStream::connect((host.as_str(), *port)).await?;
+            let stream = tls
+                .connect(host, tcp)
+                .await
+                .map_err(|e| RedisError::from((redis::ErrorKind::IoError, "TLS", e.to_string())))?;
+            let stream: Pin<Box<dyn AsyncStream + Send + Sync>> = Box::pin(stream);
+            redis::aio::Connection::new(&info.redis, stream).await
+        }
+        _ => {
+            redis::Client::open(info.clone())?
+                .get_async_connection()
+                .await
+        }
+    }
+}
+
+fn build(
+    info: ConnectionInfo,
+    mode: Mode,
+    tls: Option<TlsConnector>,
+) -> Result<Pool, RedisPoolError> {
+    log::info!("redis pool for {}", info.addr);
+    let manager = Manager {
+        info,
+        tls,
+        ping: AtomicUsize::new(0),
+    };
+    let mut builder = Pool::builder(manager).runtime(Runtime::Tokio1);
+    if mode != Mode::Single {
+        builder = builder
+            .post_create(Hook::async_fn(|conn, _| {
+                Box::pin(async move { check_master(conn).await.map_err(HookError::Abort) })
+            }))
+            .post_recycle(Hook::async_fn(|conn, _| {
+                Box::pin(async move {
+                    check_master(conn)
+                        .await
+                        .map_err(|e| HookError::Continue(Some(e)))
+                })
+            }));
+    }
+    Ok(builder.build()?)
+}
+
+/// a demoted master keeps answering pings, writes would fail with READONLY
+async fn check_master(conn: &mut redis::aio::Connection) -> Result<(), HookErrorCause<RedisError>> {
+    let role: Vec<redis::Value> = redis::cmd("ROLE")
+        .query_async(conn)
+        .await
+        .map_err(HookErrorCause::Backend)?;
+    match role.first().map(redis::from_redis_value::<String>) {
+        Some(Ok(r)) if r == "master" => Ok(()),
+        _ => Err(HookErrorCause::StaticMessage("not a master")),
+    }
+}
+
+async fn resolve(
+    base: &ConnectionInfo,
+    args: &RedisArgs,
+    mode: Mode,
+    tls: Option<&TlsConnector>,
+) -> Result<ConnectionInfo, RedisPoolError> {
+    match mode {
+        Mode::Single => Ok(base.clone()),
+        Mode::Sentinel => sentinel_master(base, args, tls).await,
+        Mode::Cluster => cluster_node(base, &args.key(""), tls).await,
+    }
+}
+
+async fn sentinel_master(
+    base: &ConnectionInfo,
+    args: &RedisArgs,
+    tls: Option<&TlsConnector>,
+) -> Result<ConnectionInfo, RedisPoolError> {
+    let mut errors = vec![];
+    for sentinel in args.redis_sentinels.iter() {
+        let (host, port) = parse_addr(sentinel)?;
+        let info = ConnectionInfo {
+            addr: with_addr(&base.addr, host, port),
+            redis: RedisConnectionInfo {
+                db: 0,
+                username: None,
+                password: args.redis_sentinel_password.clone(),
+            },
+        };
+        let res: Result<Option<(String, u16)>, _> = discover(
+            info,
+            redis::cmd("SENTINEL")
+                .arg("get-master-addr-by-name")
+                .arg(&args.redis_sentinel_master),
+            tls,
+        )
+        .await;
+        match res {
+            Ok(Some((host, port))) => {
+                log::info!("sentinel {sentinel}: master {host}:{port}");
+                return Ok(ConnectionInfo {
+                    addr: with_addr(&base.addr, &host, port),
+                    redis: base.redis.clone(),
+                });
+            }
+            Ok(None) => errors.push(format!("{sentinel}: unknown master")),
+            Err(e) => errors.push(format!("{sentinel}: {e}")),
+        }
+    }
+    Err(RedisPoolError::NoMaster(errors.join(", ")))
+}
+
+/// finds the master serving the slot of `key` with `CLUSTER SLOTS`
+async fn cluster_node(
+    base: &ConnectionInfo,
+    key: &str,
+    tls: Option<&TlsConnector>,
+) -> Result<ConnectionInfo, RedisPoolError> {
+    let slots: Vec<Vec<redis::Value>> =
+        discover(base.clone(), redis::cmd("CLUSTER").arg("SLOTS"), tls).await?;
+    let slot = key_slot(key);
+    let (host, port) = slot_master(&slots, slot)?
+        .ok_or_else(|| RedisPoolError::NoMaster(format!("slot {slot} is not served")))?;
+    let host = match host.as_str() {
+        // the node we asked
+        "" => match &base.addr {
+            ConnectionAddr::Tcp(h, _) | ConnectionAddr::TcpTls { host: h, .. } => h.clone(),
+            ConnectionAddr::Unix(_) => {
+                return Err(RedisPoolError::Config("unix socket cluster".to_string()))
+            }
+        },
+        _ => host,
+    };
+    log::info!("cluster slot {slot}: master {host}:{port}");
+    Ok(ConnectionInfo {
+        addr: with_addr(&base.addr, &host, port),
+        redis: base.redis.clone(),
+    })
+}
+
+/// `CLUSTER SLOTS` entries are `[from, to, [host, port, id, ...], replicas...]`
+fn slot_master(
+    slots: &[Vec<redis::Value>],
+    slot: u16,
+) -> Result<Option<(String, u16)>, RedisPoolError> {
+    for entry in slots {
+        let (from, to, master) = match entry.as_slice() {
+            [from, to, master, ..] => (from, to, master),
+            _ => {
+                return Err(RedisPoolError::Config(format!(
+                    "wrong slots entry {entry:?}"
+                )))
+            }
+        };
+        let (from, to): (u16, u16) = (redis::from_redis_value(from)?, redis::from_redis_value(to)?);
+        if from <= slot && slot <= to {
+            let master: Vec<redis::Value> = redis::from_redis_value(master)?;
+            return match master.as_slice() {
+                [host, port, ..] => Ok(Some((
+                    redis::from_redis_value(host)?,
+                    redis::from_redis_value(port)?,
+                ))),
+                _ => Err(RedisPoolError::Config(format!(
+                    "wrong slots node {master:?}"
+                ))),
+            };
+        }
+    }
+    Ok(None)
+}
+
+async fn discover<T: redis::FromRedisValue>(
+    info: ConnectionInfo,
+    cmd: &redis::Cmd,
+    tls: Option<&TlsConnector>,
+) -> Result<T, RedisPoolError> {
+    let res = tokio::time::timeout(DISCOVERY_TIMEOUT, async move {
+        let mut conn = connect(&info, tls).await?;
+        cmd.query_async(&mut conn).await
+    })
+    .await
+    .map_err(|_| RedisPoolError::NoMaster("discovery timeout".to_string()))?;
+    Ok(res?)
+}
+
+fn with_addr(base: &ConnectionAddr, host: &str, port: u16) -> ConnectionAddr {
+    match base {
+        ConnectionAddr::TcpTls { insecure, .. } => ConnectionAddr::TcpTls {
+            host: host.to_string(),
+            port,
+            insecure: *insecure,
+        },
+        _ => ConnectionAddr::Tcp(host.to_string(), port),
+    }
+}
+
+fn parse_addr(addr: &str) -> Result<(&str, u16), RedisPoolError> {
+    let (host, port) = addr.rsplit_once(':').ok_or_else(|| {
+        RedisPoolError::Config(format!("wrong address '{addr}', expected host:port"))
+    })?;
+    let port = port
+        .parse()
+        .map_err(|e| RedisPoolError::Config(format!("wrong port in '{addr}': {e}")))?;
+    Ok((host, port))
+}
+
+/// cluster slot of the key, only the hash tag part `{...}` is hashed if present
+fn key_slot(key: &str) -> u16 {
+    let key = match key.split_once('{') {
+        Some((_, rest)) => match rest.split_once('}') {
+            Some((tag, _)) if !tag.is_empty() => tag,
+            _ => key,
+        },
+        None => key,
+    };
+    crc16(key.as_bytes()) % CLUSTER_SLOTS
+}
+
+/// CRC16-CCITT (XMODEM) used by the cluster key distribution
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for b in data {
+        crc ^= (*b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_slot("foo"), 12182);
+        assert_eq!(key_slot("{user1000}.following"), key_slot("user1000"));
+        assert_eq!(key_slot("foo{}{bar}"), key_slot("foo{}{bar}"));
+        assert_ne!(key_slot("foo{}{bar}"), key_slot("bar"));
+        assert_eq!(key_slot("{emarket}np_lt"), key_slot("{emarket}np_lt_d"));
+    }
+
+    #[test]
+    fn splits_pem() {
+        let cert = |n| format!("-----BEGIN CERTIFICATE-----\n{n}\n-----END CERTIFICATE-----");
+        let pem = format!("# root\n{}\n{}\n", cert("a"), cert("b"));
+        assert_eq!(pem_certificates(&pem), vec![cert("a"), cert("b")]);
+        assert!(pem_certificates("-----BEGIN CERTIFICATE-----\nx").is_empty());
+    }
+
+    #[test]
+    fn keys() {
+        let mut args = RedisArgs {
+            redis_hash_tag: "lt".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(args.key("np_lt"), "np_lt");
+        args.redis_cluster = true;
+        assert_eq!(args.key("np_lt"), "{lt}np_lt");
+        assert_eq!(args.name("{lt}np_lt"), "np_lt");
+        assert_eq!(args.name("np_lt"), "np_lt");
+        args.redis_namespace = Some("stage".to_string());
+        assert_eq!(args.key("np_lt"), "{lt}stage:np_lt");
+        assert_eq!(args.name("{lt}stage:np_lt"), "np_lt");
+        assert_eq!(args.namespace_filter(), "namespace=stage");
+        args.redis_cluster = false;
+        assert_eq!(args.key("np_lt"), "stage:np_lt");
+        assert_eq!(args.name("stage:np_lt"), "np_lt");
+        assert_eq!(args.name("prod:np_lt"), "prod:np_lt");
+        args.redis_namespace = Some(String::new());
+        assert_eq!(args.key("np_lt"), "np_lt");
+        assert_eq!(args.namespace_filter(), "namespace=");
+        args.redis_cluster = true;
+        assert_eq!(args.mode().unwrap(), Mode::Cluster);
+        args.redis_sentinels = vec!["s1:26379".to_string()];
+        assert!(args.mode().is_err());
+        args.redis_cluster = false;
+        assert_eq!(args.mode().unwrap(), Mode::Sentinel);
+    }
+
+    #[test]
+    fn finds_slot_master() {
+        let node = |host: &str, port: i64| {
+            redis::Value::Bulk(vec![
+                redis::Value::Data(host.as_bytes().to_vec()),
+                redis::Value::Int(port),
+                redis::Value::Data(b"id".to_vec()),
+            ])
+        };
+        let slots = vec![
+            vec![
+                redis::Value::Int(0),
+                redis::Value::Int(8191),
+                node("a", 7000),
+                node("a-replica", 7003),
+            ],
+            vec![
+                redis::Value::Int(8192),
+                redis::Value::Int(16383),
+                node("b", 7001),
+            ],
+        ];
+        assert_eq!(
+            slot_master(&slots, 10).unwrap(),
+            Some(("a".to_string(), 7000))
+        );
+        assert_eq!(
+            slot_master(&slots, 16383).unwrap(),
+            Some(("b".to_string(), 7001))
+        );
+        assert_eq!(slot_master(&slots[..1], 9000).unwrap(), None);
+        assert!(slot_master(&[vec![redis::Value::Int(0)]], 0).is_err());
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(parse_addr("s1:26379").unwrap(), ("s1", 26379));
+        assert!(parse_addr("s1").is_err());
+        assert!(parse_addr("s1:x").is_err());
+        let tls = ConnectionAddr::TcpTls {
+            host: "a".to_string(),
+            port: 1,
+            insecure: true,
+        };
+        assert_eq!(
+            with_addr(&tls, "b", 2),
+            ConnectionAddr::TcpTls {
+                host: "b".to_string(),
+                port: 2,
+                insecure: true
+            }
+        );
+        assert_eq!(
+            with_addr(&ConnectionAddr::Tcp("a".to_string(), 1), "b", 2),
+            ConnectionAddr::Tcp("b".to_string(), 2)
+        );
+    }
+}
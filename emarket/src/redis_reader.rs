@@ -0,0 +1,135 @@
+use crate::data::{Data, Statistic};
+use crate::redis_pool::RedisPool;
+use crate::revisions::{self, Revision};
+use crate::storage::{SeriesInfo, SeriesReader};
+use crate::utils::to_time;
+use async_trait::async_trait;
+use redis_ts::{AsyncTsCommands, TsInfo, TsRange};
+use std::error::Error;
+use tracing::instrument;
+
+/// Read access to the RedisTimeSeries series
+#[derive(Clone)]
+pub struct RedisReader {
+    pool: RedisPool,
+}
+
+impl RedisReader {
+    pub async fn new(pool: RedisPool) -> Result<RedisReader, Box<dyn Error>> {
+        Ok(RedisReader { pool })
+    }
+}
+
+#[async_trait]
+impl SeriesReader for RedisReader {
+    async fn live(&self) -> std::result::Result<String, Box<dyn Error>> {
+        log::debug!("invoke live");
+        let mut conn = self.pool.get().await?;
+        let _: () = redis::cmd("PING").query_async(&mut conn).await?;
+        Ok("ok".to_string())
+    }
+
+    #[instrument(skip(self))]
+    async fn load(
+        &self,
+        ts_name: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<Vec<Data>, Box<dyn Error>> {
+        tracing::debug!("invoke load");
+
+        let mut conn = self.pool.get().await?;
+        let none_int: Option<u64> = None;
+        let from_s = match from {
+            Some(v) => v.to_string(),
+            None => "-".to_owned(),
+        };
+        let to_s = match to {
+            Some(v) => (v - 1).to_string(),
+            None => "+".to_owned(),
+        };
+        let list: TsRange<u64, f64> = conn.ts_range(ts_name, from_s, to_s, none_int, None).await?;
+        let res = list
+            .values
+            .iter()
+            .map(|f| Data {
+                at: to_time(f.0),
+                price: f.1,
+            })
+            .collect();
+        Ok(res)
+    }
+
+    /// calculates a statistic over [from, to) in redis
+    #[instrument(skip(self))]
+    async fn aggregate(
+        &self,
+        ts_name: &str,
+        from: i64,
+        to: i64,
+        stat: Statistic,
+    ) -> Result<Option<f64>, Box<dyn Error>> {
+        tracing::debug!("invoke aggregate");
+        let mut conn = self.pool.get().await?;
+        let res: Vec<(u64, f64)> = redis::cmd("TS.RANGE")
+            .arg(ts_name)
+            .arg(from)
+            .arg(to - 1)
+            .arg("ALIGN")
+            .arg("start")
+            .arg("AGGREGATION")
+            .arg(stat.name())
+            .arg(to - from)
+            .query_async(&mut conn)
+            .await?;
+        Ok(res.first().map(|v| v.1))
+    }
+
+    #[instrument(skip(self))]
+    async fn query_index(&self, filter: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut conn = self.pool.get().await?;
+        let mut res: Vec<String> = redis::cmd("TS.QUERYINDEX")
+            .arg(filter)
+            .arg(self.pool.args().namespace_filter())
+            .query_async(&mut conn)
+            .await?;
+        res.sort();
+        Ok(res)
+    }
+
+    #[instrument(skip(self))]
+    async fn info(&self, ts_name: &str) -> Result<SeriesInfo, Box<dyn Error>> {
+        let mut conn = self.pool.get().await?;
+        let info: TsInfo = conn.ts_info(ts_name).await?;
+        let has_data = info.total_samples > 0;
+        Ok(SeriesInfo {
+            name: ts_name.to_string(),
+            labels: info.labels.into_iter().collect(),
+            samples: info.total_samples,
+            first: has_data.then_some(info.first_timestamp),
+            last: has_data.then_some(info.last_timestamp),
+        })
+    }
+
+    #[instrument(skip(self))]
+    async fn revisions(
+        &self,
+        ts_name: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<Vec<Revision>, Box<dyn Error>> {
+        let mut conn = self.pool.get().await?;
+        let list: Vec<String> = redis::cmd("ZRANGEBYSCORE")
+            .arg(revisions::key(ts_name))
+            .arg(from.map_or_else(|| "-inf".to_owned(), |v| v.to_string()))
+            .arg(to.map_or_else(|| "+inf".to_owned(), |v| format!("({v}")))
+            .query_async(&mut conn)
+            .await?;
+        let mut res = list
+            .iter()
+            .map(|s| serde_json::from_str(s))
+            .collect::<Result<Vec<Revision>, _>>()?;
+        res.sort_by_key(|r| (r.at, r.fetched));
+        Ok(res)
+    }
+}
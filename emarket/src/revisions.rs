@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, HashMap};
+
+use backoff::{backoff::Backoff, ExponentialBackoff};
+use chrono::{naive::serde::ts_milliseconds, naive::serde::ts_milliseconds_option, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Receiver;
+
+use crate::data::{DBSaver, Data};
+
+/// One known value of a point: what the source published and when we got it.
+/// The revision log keeps an entry only when the value or revision changes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Revision {
+    #[serde(with = "ts_milliseconds")]
+    pub at: NaiveDateTime,
+    pub price: f64,
+    /// ENTSO-E document revision number
+    pub revision: u32,
+    /// document creation time, if the source has one
+    #[serde(
+        with = "ts_milliseconds_option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub published: Option<NaiveDateTime>,
+    /// when the importer got the value, `as_of` queries use it
+    #[serde(with = "ts_milliseconds")]
+    pub fetched: NaiveDateTime,
+}
+
+impl Revision {
+    pub fn to_data(&self) -> Data {
+        Data {
+            at: self.at,
+            price: self.price,
+        }
+    }
+}
+
+/// redis sorted set with the revision log of the series
+pub fn key(ts_name: &str) -> String {
+    format!("{ts_name}:rev")
+}
+
+/// entries of `new` that change the latest known value of their point,
+/// `known` is the log of the same range
+pub fn changed(known: &[Revision], new: &[Revision]) -> Vec<Revision> {
+    let mut latest: HashMap<NaiveDateTime, &Revision> = HashMap::new();
+    for r in known {
+        match latest.get(&r.at) {
+            Some(l) if l.fetched > r.fetched => {}
+            _ => {
+                latest.insert(r.at, r);
+            }
+        }
+    }
+    let mut res: Vec<Revision> = vec![];
+    for r in new {
+        let same = match res.iter().rev().find(|l| l.at == r.at) {
+            Some(l) => l.price == r.price && l.revision == r.revision,
+            None => latest
+                .get(&r.at)
+                .is_some_and(|l| l.price == r.price && l.revision == r.revision),
+        };
+        if !same {
+            res.push(r.clone());
+        }
+    }
+    res
+}
+
+/// values as known at `as_of`, sorted by time
+pub fn as_of(revisions: &[Revision], as_of: NaiveDateTime) -> Vec<Data> {
+    let mut res: BTreeMap<NaiveDateTime, &Revision> = BTreeMap::new();
+    for r in revisions.iter().filter(|r| r.fetched <= as_of) {
+        match res.get(&r.at) {
+            Some(l) if l.fetched > r.fetched => {}
+            _ => {
+                res.insert(r.at, r);
+            }
+        }
+    }
+    res.values().map(|r| r.to_data()).collect()
+}
+
+/// time range covered by the revisions, `to` is exclusive
+pub fn range(revisions: &[Revision]) -> Option<(NaiveDateTime, NaiveDateTime)> {
+    let from = revisions.iter().map(|r| r.at).min()?;
+    let to = revisions.iter().map(|r| r.at).max()?;
+    Some((from, to + chrono::Duration::milliseconds(1)))
+}
+
+/// writes revision batches to the log, a batch is dropped after retrying for `max_retry`
+pub async fn saver_start(
+    db: Box<dyn DBSaver + Send + Sync>,
+    receiver: &mut Receiver<Vec<Revision>>,
+    max_retry: std::time::Duration,
+) -> Result<(), String> {
+    log::info!("start revision saver loop");
+    while let Some(list) = receiver.recv().await {
+        let mut retry = ExponentialBackoff {
+            max_interval: std::time::Duration::from_secs(60),
+            max_elapsed_time: Some(max_retry),
+            ..Default::default()
+        };
+        loop {
+            let err = match db.save_revisions(&list).await {
+                Ok(n) => {
+                    log::debug!("saved {n} revisions of {}", list.len());
+                    break;
+                }
+                Err(err) => err.to_string(),
+            };
+            match retry.next_backoff() {
+                Some(wait) => {
+                    log::warn!("revision save err: {err}, retry in {wait:?}");
+                    tokio::time::sleep(wait).await;
+                }
+                None => {
+                    log::error!("revision save err: {err}, {} dropped", list.len());
+                    break;
+                }
+            }
+        }
+    }
+    log::info!("exit revision saver loop");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate};
+
+    use super::*;
+
+    fn at(h: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            + Duration::hours(h)
+    }
+
+    fn rev(h: i64, price: f64, revision: u32, fetched: i64) -> Revision {
+        Revision {
+            at: at(h),
+            price,
+            revision,
+            published: None,
+            fetched: at(fetched),
+        }
+    }
+
+    #[test]
+    fn keeps_changes_only() {
+        let known = vec![rev(10, 1.0, 1, 0), rev(10, 2.0, 2, 1), rev(11, 3.0, 1, 0)];
+        let new = vec![
+            rev(10, 2.0, 2, 5),
+            rev(11, 3.0, 2, 5),
+            rev(12, 4.0, 1, 5),
+            rev(12, 4.0, 1, 5),
+        ];
+        assert_eq!(
+            changed(&known, &new),
+            vec![rev(11, 3.0, 2, 5), rev(12, 4.0, 1, 5)]
+        );
+        assert_eq!(changed(&[], &new[..1]), vec![rev(10, 2.0, 2, 5)]);
+    }
+
+    #[test]
+    fn selects_as_of() {
+        let list = vec![
+            rev(10, 1.0, 1, 0),
+            rev(11, 3.0, 1, 0),
+            rev(10, 2.0, 2, 5),
+            rev(12, 4.0, 1, 6),
+        ];
+        let data = |v: &[(i64, f64)]| {
+            v.iter()
+                .map(|(h, price)| Data {
+                    at: at(*h),
+                    price: *price,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(as_of(&list, at(4)), data(&[(10, 1.0), (11, 3.0)]));
+        assert_eq!(as_of(&list, at(5)), data(&[(10, 2.0), (11, 3.0)]));
+        assert_eq!(
+            as_of(&list, at(10)),
+            data(&[(10, 2.0), (11, 3.0), (12, 4.0)])
+        );
+        assert!(as_of(&list, at(-1)).is_empty());
+    }
+
+    #[test]
+    fn serializes() {
+        let mut r = rev(1, 1.5, 2, 0);
+        let s = serde_json::to_string(&r).unwrap();
+        assert_eq!(
+            s,
+            r#"{"at":1704070800000,"price":1.5,"revision":2,"fetched":1704067200000}"#
+        );
+        r.published = Some(at(0));
+        let s = serde_json::to_string(&r).unwrap();
+        assert_eq!(serde_json::from_str::<Revision>(&s).unwrap(), r);
+    }
+}
@@ -0,0 +1,566 @@
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDateTime, Timelike};
+use serde::Serialize;
+
+use crate::{data::Data, utils::time_day_vilnius};
+
+/// minutes of a scheduling slot
+pub const SLOT_MINUTES: i64 = 15;
+
+pub fn slot() -> Duration {
+    Duration::minutes(SLOT_MINUTES)
+}
+
+/// start of the slot containing `at`
+pub fn slot_start(at: NaiveDateTime) -> NaiveDateTime {
+    let at = at
+        .with_second(0)
+        .unwrap_or(at)
+        .with_nanosecond(0)
+        .unwrap_or(at);
+    at - Duration::minutes(at.minute() as i64 % SLOT_MINUTES)
+}
+
+/// Price of a slot in EUR/kWh
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slot {
+    pub at: NaiveDateTime,
+    pub price: f64,
+}
+
+/// slots of [from, to) by the `prices`, a price applies until the next one but at most an hour,
+/// the list ends at the first slot without a price
+pub fn slots(prices: &[Data], from: NaiveDateTime, to: NaiveDateTime) -> Vec<Slot> {
+    let prices: BTreeMap<NaiveDateTime, f64> = prices.iter().map(|d| (d.at, d.price)).collect();
+    let mut res = vec![];
+    let mut at = slot_start(from);
+    while at + slot() <= to {
+        let price = prices
+            .range(..=at)
+            .next_back()
+            .filter(|(p, _)| at - **p < Duration::hours(1));
+        let Some((_, price)) = price else {
+            break;
+        };
+        res.push(Slot { at, price: *price });
+        at += slot();
+    }
+    res
+}
+
+/// Run of consecutive slots
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Window {
+    #[serde(with = "chrono::naive::serde::ts_milliseconds")]
+    pub start: NaiveDateTime,
+    #[serde(with = "chrono::naive::serde::ts_milliseconds")]
+    pub end: NaiveDateTime,
+    pub kwh: f64,
+    /// EUR
+    pub cost: f64,
+    /// average EUR/kWh
+    pub price: f64,
+}
+
+impl Window {
+    fn new(slots: &[Slot], profile: &[f64]) -> Window {
+        let kwh_of = |kw: f64| kw * SLOT_MINUTES as f64 / 60.0;
+        let kwh = profile.iter().map(|kw| kwh_of(*kw)).sum::<f64>();
+        let cost = slots
+            .iter()
+            .zip(profile)
+            .map(|(s, kw)| s.price * kwh_of(*kw))
+            .sum::<f64>();
+        Window {
+            start: slots[0].at,
+            end: slots[slots.len() - 1].at + slot(),
+            kwh,
+            cost,
+            price: if kwh > 0.0 { cost / kwh } else { 0.0 },
+        }
+    }
+}
+
+/// the first and the cheapest start of a run consuming `profile` kW per slot, the earliest of equal ones,
+/// none if the slots are shorter than the profile
+pub fn cheapest_window(slots: &[Slot], profile: &[f64]) -> Option<(Window, Window)> {
+    if profile.is_empty() || slots.len() < profile.len() {
+        return None;
+    }
+    let mut list = slots
+        .windows(profile.len())
+        .map(|w| Window::new(w, profile));
+    let first = list.next()?;
+    let best = list.fold(first.clone(), |best, w| match w.cost < best.cost {
+        true => w,
+        false => best,
+    });
+    Some((first, best))
+}
+
+/// max slots × DP states of one [`cheapest_slots`] search
+pub const MAX_CELLS: usize = 10_000_000;
+
+/// Constraints of a slot selection, all in slots
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Constraints {
+    /// slots to select
+    pub count: usize,
+    /// min length of a run
+    pub min_block: usize,
+    /// max off slots between two runs
+    pub max_gap: Option<usize>,
+    /// min slots of every local day the window fully covers, the partial days at its edges
+    /// are not constrained, so a window from 23:30 does not force the slots before midnight
+    pub min_per_day: usize,
+}
+
+/// DP state dimensions and the gap codes
+struct Dims {
+    count: usize,
+    day: usize,
+    run: usize,
+    gap: Option<usize>,
+}
+
+impl Dims {
+    /// gap code before the first run
+    fn none(&self) -> usize {
+        self.gap.map_or(0, |g| g + 1)
+    }
+
+    /// gap code after a too long gap, no more runs, never set without a max gap
+    fn exceeded(&self) -> usize {
+        self.gap.map_or(usize::MAX, |g| g + 2)
+    }
+
+    fn gaps(&self) -> usize {
+        self.gap.map_or(1, |g| g + 3)
+    }
+
+    /// states count, none on an overflow
+    fn len(&self) -> Option<usize> {
+        [self.day + 1, self.run + 1, self.gaps()]
+            .into_iter()
+            .try_fold(self.count + 1, |v, d| v.checked_mul(d))
+    }
+
+    fn index(&self, n: usize, d: usize, r: usize, g: usize) -> usize {
+        ((n * (self.day + 1) + d) * (self.run + 1) + r) * self.gaps() + g
+    }
+
+    fn state(&self, i: usize) -> (usize, usize, usize, usize) {
+        let g = i % self.gaps();
+        let i = i / self.gaps();
+        let r = i % (self.run + 1);
+        let i = i / (self.run + 1);
+        (i / (self.day + 1), i % (self.day + 1), r, g)
+    }
+
+    /// gap code after an off slot
+    fn off(&self, r: usize, g: usize) -> usize {
+        match self.gap {
+            None => 0,
+            Some(max) if r > 0 => match max {
+                0 => self.exceeded(),
+                _ => 1,
+            },
+            Some(_) if g == self.none() || g == self.exceeded() => g,
+            Some(max) if g < max => g + 1,
+            Some(_) => self.exceeded(),
+        }
+    }
+}
+
+/// the cheapest selection of `c.count` slots meeting the constraints, true for a selected slot
+pub fn cheapest_slots(slots: &[Slot], c: &Constraints) -> Result<Vec<bool>, String> {
+    if c.count > slots.len() {
+        return Err(format!("{} slots asked, {} known", c.count, slots.len()));
+    }
+    if c.count > 0 && c.min_block > slots.len() {
+        return Err(format!(
+            "{} slots min block, {} known",
+            c.min_block,
+            slots.len()
+        ));
+    }
+    let mut per_day: BTreeMap<NaiveDateTime, usize> = BTreeMap::new();
+    slots
+        .iter()
+        .for_each(|s| *per_day.entry(time_day_vilnius(s.at, 0)).or_default() += 1);
+    let required = per_day
+        .into_iter()
+        .map(|(day, len)| {
+            let day_len = (time_day_vilnius(day, 1) - day).num_minutes() / SLOT_MINUTES;
+            let full = len as i64 == day_len;
+            (day, if full { c.min_per_day.min(len) } else { 0 })
+        })
+        .collect();
+    select(slots, c, &required)
+}
+
+/// the cheapest selection with at least `required` slots of every local day
+fn select(
+    slots: &[Slot],
+    c: &Constraints,
+    required: &BTreeMap<NaiveDateTime, usize>,
+) -> Result<Vec<bool>, String> {
+    // longer constraints than the slots change nothing: a gap has at most all the slots
+    let dims = Dims {
+        count: c.count,
+        day: required.values().max().copied().unwrap_or(0),
+        run: c.min_block.clamp(1, slots.len().max(1)),
+        gap: c.max_gap.map(|g| g.min(slots.len())),
+    };
+    let len = dims
+        .len()
+        .filter(|len| len.checked_mul(slots.len()).is_some_and(|v| v <= MAX_CELLS))
+        .ok_or("too many combinations, narrow the window or the constraints")?;
+    let days: Vec<NaiveDateTime> = slots.iter().map(|s| time_day_vilnius(s.at, 0)).collect();
+    let required = |day: &NaiveDateTime| required.get(day).copied().unwrap_or(0);
+
+    let mut cost = vec![f64::INFINITY; len];
+    cost[dims.index(0, 0, 0, dims.none())] = 0.0;
+    // previous state * 2 + 1 if the slot is on
+    let mut parents = vec![vec![u32::MAX; len]; slots.len()];
+    for (i, s) in slots.iter().enumerate() {
+        let new_day = i > 0 && days[i] != days[i - 1];
+        let mut next = vec![f64::INFINITY; len];
+        for (from, v) in cost.iter().enumerate().filter(|(_, v)| v.is_finite()) {
+            let (n, d, r, g) = dims.state(from);
+            let d = match new_day {
+                true if d < required(&days[i - 1]) => continue,
+                true => 0,
+                false => d,
+            };
+            let mut set = |to: usize, v: f64, on: bool| {
+                if v < next[to] {
+                    next[to] = v;
+                    parents[i][to] = (from * 2 + on as usize) as u32;
+                }
+            };
+            if n < dims.count && g != dims.exceeded() {
+                let to = dims.index(n + 1, (d + 1).min(dims.day), (r + 1).min(dims.run), 0);
+                set(to, v + s.price, true);
+            }
+            if r == 0 || r >= dims.run {
+                set(dims.index(n, d, 0, dims.off(r, g)), *v, false);
+            }
+        }
+        cost = next;
+    }
+
+    let last = days.last().map_or(0, required);
+    let end = cost
+        .iter()
+        .enumerate()
+        .filter(|(i, v)| {
+            let (n, d, r, _) = dims.state(*i);
+            v.is_finite() && n == dims.count && d >= last && (r == 0 || r >= dims.run)
+        })
+        .min_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, _)| i)
+        .ok_or("no selection meets the constraints")?;
+    let mut res = vec![false; slots.len()];
+    let mut state = end;
+    for i in (0..slots.len()).rev() {
+        let parent = parents[i][state] as usize;
+        res[i] = parent % 2 == 1;
+        state = parent / 2;
+    }
+    Ok(res)
+}
+
+/// [start, end) of the selected runs
+pub fn runs(slots: &[Slot], selected: &[bool]) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+    let mut res: Vec<(NaiveDateTime, NaiveDateTime)> = vec![];
+    for (s, _) in slots.iter().zip(selected).filter(|(_, on)| **on) {
+        match res.last_mut() {
+            Some(last) if last.1 == s.at => last.1 = s.at + slot(),
+            _ => res.push((s.at, s.at + slot())),
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn at(m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+    }
+
+    fn hours(start: NaiveDateTime, prices: &[f64]) -> Vec<Data> {
+        prices
+            .iter()
+            .enumerate()
+            .map(|(i, p)| Data {
+                at: start + Duration::hours(i as i64),
+                price: *p,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn makes_slots() {
+        assert_eq!(slot_start(at(1, 1, 10, 44)), at(1, 1, 10, 30));
+        let prices = hours(at(1, 1, 0, 0), &[1.0, 2.0]);
+        let res = slots(&prices, at(1, 1, 0, 40), at(1, 1, 5, 0));
+        assert_eq!(res.len(), 6);
+        assert_eq!(res[0].at, at(1, 1, 0, 30));
+        assert_eq!(res[2].price, 2.0);
+        // a gap ends the slots
+        let mut prices = prices;
+        prices.push(Data {
+            at: at(1, 1, 3, 0),
+            price: 1.0,
+        });
+        assert_eq!(slots(&prices, at(1, 1, 0, 0), at(1, 1, 5, 0)).len(), 8);
+    }
+
+    #[test]
+    fn finds_window() {
+        let prices = hours(at(1, 1, 0, 0), &[5.0, 1.0, 2.0, 9.0]);
+        let slots = slots(&prices, at(1, 1, 0, 0), at(1, 1, 4, 0));
+        let (first, best) = cheapest_window(&slots, &[2.0; 6]).unwrap();
+        assert_eq!(best.start, at(1, 1, 1, 0));
+        assert_eq!(best.end, at(1, 1, 2, 30));
+        assert_relative_eq!(best.kwh, 3.0);
+        assert_relative_eq!(best.cost, 4.0);
+        assert_relative_eq!(first.cost, 2.0 * 5.0 + 1.0 * 1.0);
+        // high first slot of the profile fits the cheapest slots
+        let (_, best) = cheapest_window(&slots, &[4.0, 0.0, 0.0, 0.0, 0.0]).unwrap();
+        assert_eq!(best.start, at(1, 1, 1, 0));
+        assert!(cheapest_window(&slots, &[1.0; 17]).is_none());
+    }
+
+    #[test]
+    fn handles_dst() {
+        // 2024-03-31 has 23 hours in Vilnius, 2024-10-27 has 25
+        for (day, len) in [(at(3, 30, 22, 0), 23), (at(10, 26, 21, 0), 25)] {
+            let mut prices = vec![9.0; len];
+            prices[len - 1] = 1.0;
+            let prices = hours(day, &prices);
+            let slots = slots(&prices, day, day + Duration::days(2));
+            assert_eq!(slots.len(), len * 4);
+            let (_, best) = cheapest_window(&slots, &[1.0; 4]).unwrap();
+            assert_eq!(best.start, day + Duration::hours(len as i64 - 1));
+        }
+    }
+
+    fn selection(prices: &[f64], c: Constraints) -> Result<String, String> {
+        let slots: Vec<Slot> = prices
+            .iter()
+            .enumerate()
+            .map(|(i, p)| Slot {
+                at: at(1, 1, 0, 0) + slot() * i as i32,
+                price: *p,
+            })
+            .collect();
+        let res = cheapest_slots(&slots, &c)?;
+        assert_eq!(res.iter().filter(|v| **v).count(), c.count);
+        Ok(res.iter().map(|v| if *v { '1' } else { '0' }).collect())
+    }
+
+    #[test]
+    fn selects_slots() {
+        let prices = [5.0, 1.0, 9.0, 2.0, 8.0, 1.0, 7.0, 3.5];
+        let c = |count, min_block, max_gap| Constraints {
+            count,
+            min_block,
+            max_gap,
+            min_per_day: 0,
+        };
+        assert_eq!(selection(&prices, c(3, 0, None)).unwrap(), "01010100");
+        assert_eq!(selection(&prices, c(4, 2, None)).unwrap(), "11000110");
+        assert_eq!(selection(&prices, c(3, 0, Some(1))).unwrap(), "01010100");
+        assert_eq!(selection(&prices, c(2, 0, Some(0))).unwrap(), "11000000");
+        assert_eq!(selection(&prices, c(3, 3, None)).unwrap(), "00011100");
+        assert_eq!(selection(&prices, c(0, 2, Some(0))).unwrap(), "00000000");
+        assert!(selection(&prices, c(9, 0, None)).is_err());
+        assert!(selection(&prices, c(2, 3, None)).is_err());
+        assert!(selection(&prices, c(2, 9, None)).is_err());
+    }
+
+    #[test]
+    fn clamps_huge_constraints() {
+        let prices = [5.0, 1.0, 9.0, 2.0, 8.0, 1.0, 7.0, 3.5];
+        let huge = 400_000;
+        let c = |count, min_block, min_per_day| Constraints {
+            count,
+            min_block,
+            max_gap: Some(huge),
+            min_per_day,
+        };
+        assert_eq!(selection(&prices, c(3, 0, 0)).unwrap(), "01010100");
+        // a partial day is not constrained
+        assert_eq!(selection(&prices, c(3, 0, huge)).unwrap(), "01010100");
+        assert!(selection(&prices, c(8, huge, huge)).is_err());
+        let c = Constraints {
+            count: 3,
+            min_block: usize::MAX,
+            max_gap: Some(usize::MAX),
+            min_per_day: usize::MAX,
+        };
+        assert!(selection(&prices, c).is_err());
+    }
+
+    #[test]
+    fn selects_per_day() {
+        // 21:00 - 23:00 UTC, the day changes in Vilnius after 4 slots
+        let prices = [9.0, 9.0, 8.0, 9.0, 1.0, 1.0, 1.0, 1.0];
+        let slots: Vec<Slot> = prices
+            .iter()
+            .enumerate()
+            .map(|(i, p)| Slot {
+                at: at(1, 1, 21, 0) + slot() * i as i32,
+                price: *p,
+            })
+            .collect();
+        let c = Constraints {
+            count: 3,
+            min_per_day: 1,
+            ..Default::default()
+        };
+        // both days are partial, so not constrained
+        let res = cheapest_slots(&slots, &c).unwrap();
+        assert_eq!(
+            res,
+            vec![false, false, false, false, true, true, true, false]
+        );
+        let required = BTreeMap::from([
+            (at(1, 1, 22, 0) - Duration::days(1), 1),
+            (at(1, 1, 22, 0), 1),
+        ]);
+        let res = select(&slots, &c, &required).unwrap();
+        assert_eq!(
+            res,
+            vec![false, false, true, false, true, true, false, false]
+        );
+        let runs = runs(&slots, &res);
+        assert_eq!(
+            runs,
+            vec![
+                (at(1, 1, 21, 30), at(1, 1, 21, 45)),
+                (at(1, 1, 22, 0), at(1, 1, 22, 30))
+            ]
+        );
+
+        // a whole local day from 22:00 UTC with an hour around it
+        let slots: Vec<Slot> = (0..104)
+            .map(|i| Slot {
+                at: at(1, 1, 21, 0) + slot() * i,
+                price: match i {
+                    0..4 => 9.0,
+                    4..100 if i == 50 => 8.0,
+                    4..100 => 20.0,
+                    _ => 1.0,
+                },
+            })
+            .collect();
+        let selected = |c: &Constraints| {
+            cheapest_slots(&slots, c).map(|res| {
+                res.iter()
+                    .enumerate()
+                    .filter(|(_, on)| **on)
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>()
+            })
+        };
+        let c = |count, min_per_day| Constraints {
+            count,
+            min_per_day,
+            ..Default::default()
+        };
+        assert_eq!(selected(&c(3, 0)).unwrap(), vec![100, 101, 102]);
+        // the whole day gets its slot, the partial day before it none
+        assert_eq!(selected(&c(3, 1)).unwrap(), vec![50, 100, 101]);
+        assert_eq!(selected(&c(2, 2)).unwrap().len(), 2);
+        assert!(selected(&c(1, 2)).is_err());
+        // capped at the slots of the day
+        assert_eq!(
+            selected(&c(96, usize::MAX)).unwrap(),
+            (4..100).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let prices = [4.0, 1.5, 9.0, 2.0, 8.0, 1.0, 7.0, 3.5, 2.5, 6.0];
+        let slots: Vec<Slot> = prices
+            .iter()
+            .enumerate()
+            .map(|(i, p)| Slot {
+                at: at(1, 1, 21, 0) + slot() * i as i32,
+                price: *p,
+            })
+            .collect();
+        let valid = |mask: u32, c: &Constraints| {
+            let on: Vec<bool> = (0..prices.len()).map(|i| mask & (1 << i) != 0).collect();
+            let runs = runs(&slots, &on);
+            let blocks = runs
+                .iter()
+                .all(|(s, e)| (*e - *s).num_minutes() >= c.min_block as i64 * SLOT_MINUTES);
+            let gaps = runs.windows(2).all(|w| {
+                c.max_gap
+                    .is_none_or(|g| (w[1].0 - w[0].1).num_minutes() <= g as i64 * SLOT_MINUTES)
+            });
+            // the day changes after 4 slots
+            let day = |r: std::ops::Range<usize>| on[r.clone()].iter().filter(|v| **v).count();
+            let days = day(0..4) >= c.min_per_day.min(4) && day(4..10) >= c.min_per_day.min(6);
+            blocks && gaps && days && mask.count_ones() as usize == c.count
+        };
+        // the DP with the partial days constrained as whole ones
+        let day = time_day_vilnius(slots[0].at, 0);
+        let required = |min_per_day: usize| {
+            BTreeMap::from([
+                (day, min_per_day.min(4)),
+                (time_day_vilnius(day, 1), min_per_day.min(6)),
+            ])
+        };
+        let cost = |on: &[bool]| {
+            on.iter()
+                .zip(prices)
+                .filter(|(v, _)| **v)
+                .map(|(_, p)| p)
+                .sum::<f64>()
+        };
+        for count in 0..=6 {
+            for min_block in 0..=3 {
+                for max_gap in [None, Some(0), Some(1), Some(3)] {
+                    for min_per_day in 0..=2 {
+                        let c = Constraints {
+                            count,
+                            min_block,
+                            max_gap,
+                            min_per_day,
+                        };
+                        let best = (0..1u32 << prices.len())
+                            .filter(|m| valid(*m, &c))
+                            .map(|m| {
+                                cost(
+                                    &(0..prices.len())
+                                        .map(|i| m & (1 << i) != 0)
+                                        .collect::<Vec<_>>(),
+                                )
+                            })
+                            .min_by(f64::total_cmp);
+                        let res = select(&slots, &c, &required(min_per_day));
+                        match best {
+                            Some(best) => assert_relative_eq!(cost(&res.unwrap()), best),
+                            None => assert!(res.is_err(), "{c:?}"),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use std::{collections::BTreeMap, str::FromStr, time::Duration};
+
+use serde::Serialize;
+
+use crate::{
+    units::{CURRENCY_EUR, UNIT_MWH},
+    utils::parse_duration,
+};
+
+pub const MEASURE_PRICE: &str = "price";
+/// flags of the price points, see [`crate::quality::Quality`]
+pub const MEASURE_QUALITY: &str = "quality";
+
+pub const RES_HOUR: &str = "1h";
+pub const RES_DAY: &str = "1d";
+/// days aligned to the local standard time midnight
+pub const RES_FIXED_DAY: &str = "1d_std";
+pub const RES_MONTH: &str = "1M";
+
+pub const STAT_RAW: &str = "raw";
+pub const STAT_AVG: &str = "avg";
+
+/// Labels attached to every series, used to discover series by filters
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Labels {
+    pub zone: String,
+    pub measure: String,
+    pub resolution: String,
+    pub statistic: String,
+    pub currency: String,
+    pub unit: String,
+}
+
+impl Labels {
+    pub fn price(zone: &str, resolution: &str, statistic: &str) -> Labels {
+        Labels {
+            zone: zone.to_string(),
+            measure: MEASURE_PRICE.to_string(),
+            resolution: resolution.to_string(),
+            statistic: statistic.to_string(),
+            currency: CURRENCY_EUR.to_string(),
+            unit: UNIT_MWH.to_string(),
+        }
+    }
+
+    /// labels of the quality flags of the `zone` prices
+    pub fn quality(zone: &str, resolution: &str) -> Labels {
+        Labels {
+            zone: zone.to_string(),
+            measure: MEASURE_QUALITY.to_string(),
+            resolution: resolution.to_string(),
+            statistic: STAT_RAW.to_string(),
+            currency: "none".to_string(),
+            unit: "flag".to_string(),
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<(&'static str, &str)> {
+        vec![
+            ("zone", &self.zone),
+            ("measure", &self.measure),
+            ("resolution", &self.resolution),
+            ("statistic", &self.statistic),
+            ("currency", &self.currency),
+            ("unit", &self.unit),
+        ]
+    }
+
+    /// labels of the prices in `currency` per `unit`
+    pub fn with_units(&self, currency: &str, unit: &str) -> Labels {
+        Labels {
+            currency: currency.to_string(),
+            unit: unit.to_string(),
+            ..self.clone()
+        }
+    }
+
+    pub fn with(&self, resolution: &str, statistic: &str) -> Labels {
+        Labels {
+            resolution: resolution.to_string(),
+            statistic: statistic.to_string(),
+            ..self.clone()
+        }
+    }
+}
+
+impl TryFrom<&BTreeMap<String, String>> for Labels {
+    type Error = String;
+
+    fn try_from(labels: &BTreeMap<String, String>) -> Result<Self, Self::Error> {
+        let get = |key: &str| {
+            labels
+                .get(key)
+                .cloned()
+                .ok_or_else(|| format!("no label '{key}'"))
+        };
+        Ok(Labels {
+            zone: get("zone")?,
+            measure: get("measure")?,
+            resolution: get("resolution")?,
+            statistic: get("statistic")?,
+            currency: get("currency")?,
+            unit: get("unit")?,
+        })
+    }
+}
+
+/// longest bucket of a resolution, months are taken as 31 days
+pub fn bucket(resolution: &str) -> Option<Duration> {
+    match resolution {
+        RES_MONTH => Some(Duration::from_secs(31 * 24 * 3600)),
+        RES_FIXED_DAY => bucket(RES_DAY),
+        _ => parse_duration(resolution).ok(),
+    }
+}
+
+/// `TS.QUERYINDEX`/`TS.MRANGE` filter for the series of a zone
+pub fn price_filter(zone: &str, resolution: &str, statistic: &str) -> Vec<String> {
+    vec![
+        format!("zone={zone}"),
+        format!("measure={MEASURE_PRICE}"),
+        format!("resolution={resolution}"),
+        format!("statistic={statistic}"),
+    ]
+}
+
+/// filter for the quality flags series of a zone
+pub fn quality_filter(zone: &str, resolution: &str) -> Vec<String> {
+    vec![
+        format!("zone={zone}"),
+        format!("measure={MEASURE_QUALITY}"),
+        format!("resolution={resolution}"),
+    ]
+}
+
+/// matches labels with a `TS.QUERYINDEX` like filter: `label=value`, `label!=value`,
+/// an empty value stands for a missing label
+pub fn matches_filter(labels: &BTreeMap<String, String>, filter: &[String]) -> bool {
+    filter.iter().all(|f| {
+        let (key, value, equal) = match f.split_once("!=") {
+            Some((k, v)) => (k, v, false),
+            None => match f.split_once('=') {
+                Some((k, v)) => (k, v, true),
+                None => return false,
+            },
+        };
+        let got = labels.get(key).map_or("", |v| v.as_str());
+        (got == value) == equal
+    })
+}
+
+/// Retention policy of a series, zero keeps data forever
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retention {
+    pub series: String,
+    pub keep: Duration,
+}
+
+impl FromStr for Retention {
+    type Err = String;
+
+    /// parses `<series>=<duration>`, e.g. `np_lt=90d` or `np_lt_1h=0`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (series, keep) = s
+            .split_once('=')
+            .ok_or_else(|| format!("wrong retention '{s}', expected <series>=<duration>"))?;
+        let keep = match keep.trim() {
+            "0" | "forever" => Duration::ZERO,
+            v => parse_duration(v)?,
+        };
+        Ok(Retention {
+            series: series.trim().to_string(),
+            keep,
+        })
+    }
+}
+
+pub fn retention_for(policies: &[Retention], series: &str) -> Duration {
+    policies
+        .iter()
+        .find(|p| p.series == series)
+        .map_or(Duration::ZERO, |p| p.keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels() {
+        let l = Labels::price("LT", RES_HOUR, STAT_RAW);
+        assert_eq!(
+            l.to_vec(),
+            vec![
+                ("zone", "LT"),
+                ("measure", "price"),
+                ("resolution", "1h"),
+                ("statistic", "raw"),
+                ("currency", "EUR"),
+                ("unit", "MWh"),
+            ]
+        );
+        let d = l.with(RES_DAY, STAT_AVG);
+        assert_eq!(d.resolution, "1d");
+        assert_eq!(d.statistic, "avg");
+        assert_eq!(d.zone, "LT");
+    }
+
+    #[test]
+    fn filter() {
+        assert_eq!(
+            price_filter("LT", RES_MONTH, STAT_AVG),
+            vec!["zone=LT", "measure=price", "resolution=1M", "statistic=avg"]
+        );
+    }
+
+    #[test]
+    fn matches() {
+        let labels: BTreeMap<String, String> = Labels::price("LT", RES_HOUR, STAT_RAW)
+            .to_vec()
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert!(matches_filter(
+            &labels,
+            &price_filter("LT", RES_HOUR, STAT_RAW)
+        ));
+        assert!(!matches_filter(
+            &labels,
+            &price_filter("LV", RES_HOUR, STAT_RAW)
+        ));
+        assert!(matches_filter(&labels, &["zone!=LV".to_string()]));
+        assert!(!matches_filter(&labels, &["zone!=LT".to_string()]));
+        assert!(matches_filter(&labels, &["other=".to_string()]));
+        assert!(!matches_filter(&labels, &["zone=".to_string()]));
+        assert!(!matches_filter(&labels, &["zone".to_string()]));
+        assert!(matches_filter(&labels, &[]));
+    }
+
+    #[test]
+    fn buckets() {
+        assert_eq!(bucket(RES_HOUR), Some(Duration::from_secs(3600)));
+        assert_eq!(bucket(RES_FIXED_DAY), Some(Duration::from_secs(86400)));
+        assert_eq!(bucket(RES_MONTH), Some(Duration::from_secs(31 * 86400)));
+        assert_eq!(bucket("wrong"), None);
+    }
+
+    #[test]
+    fn parse_retention() {
+        assert_eq!(
+            Retention::from_str("np_lt=90d").unwrap(),
+            Retention {
+                series: "np_lt".to_string(),
+                keep: Duration::from_secs(90 * 24 * 3600)
+            }
+        );
+        assert_eq!(
+            Retention::from_str("np_lt_1h=0").unwrap().keep,
+            Duration::ZERO
+        );
+        assert_eq!(
+            Retention::from_str("np_lt_1h=forever").unwrap().keep,
+            Duration::ZERO
+        );
+        assert!(Retention::from_str("np_lt").is_err());
+        assert!(Retention::from_str("np_lt=xx").is_err());
+    }
+
+    #[test]
+    fn finds_retention() {
+        let policies = vec![Retention::from_str("np_lt=1h").unwrap()];
+        assert_eq!(retention_for(&policies, "np_lt"), Duration::from_secs(3600));
+        assert_eq!(retention_for(&policies, "np_lt_d"), Duration::ZERO);
+    }
+}
@@ -0,0 +1,470 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use emarket::{
+    archive::{ArchiveReader, ArchiveWriter, Header, Record},
+    data::{Aggregator, Data},
+    revisions::Revision,
+    series::{Labels, RES_DAY, RES_MONTH, STAT_AVG},
+    storage::{SeriesInfo, SeriesReader},
+    utils::parse_time,
+    TN_DAY, TN_HOUR, TN_MONTH,
+};
+use serde::Serialize;
+
+use crate::{
+    aggregator::{time_day, time_month, AggregatorByDate},
+    redis::{CompactionRule, RetentionOptions},
+    store::Storage,
+};
+
+/// points in one restore write
+const RESTORE_BATCH: usize = 500;
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    /// Archive file, gzipped JSON Lines
+    #[arg(long, short)]
+    pub output: PathBuf,
+    /// Series to export, all series of the zone if empty
+    #[arg(long, value_delimiter = ',')]
+    pub series: Vec<String>,
+    /// Export points from: 2024-01-01, RFC 3339 time or unix millis
+    #[arg(long, value_parser = parse_time)]
+    pub from: Option<NaiveDateTime>,
+    /// Export points before this time
+    #[arg(long, value_parser = parse_time)]
+    pub to: Option<NaiveDateTime>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RestoreArgs {
+    /// Archive file made by export
+    #[arg(long, short)]
+    pub input: PathBuf,
+    /// Replace existing points having other values, they are kept by default
+    #[arg(long, default_value_t = false)]
+    pub overwrite: bool,
+    /// Recalculate the day and month averages of the restored hourly prices
+    #[arg(long, default_value_t = false)]
+    pub aggregate: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExportReport {
+    pub series: Vec<SeriesInfo>,
+    pub records: u64,
+}
+
+/// existing point having another value than the archive
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Conflict {
+    pub at: i64,
+    pub existing: f64,
+    pub archived: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SeriesReport {
+    pub name: String,
+    pub points: usize,
+    /// time range of the archived points
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last: Option<i64>,
+    /// points written, conflicts are included if overwritten
+    pub saved: usize,
+    pub unchanged: usize,
+    pub conflicts: Vec<Conflict>,
+    pub revisions: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RestoreReport {
+    pub series: Vec<SeriesReport>,
+    pub overwritten: bool,
+    /// day and month buckets recalculated
+    pub aggregated: usize,
+}
+
+/// writes the series with their points and revisions in [from, to),
+/// `series` are pairs of the archive name and the storage key.
+/// The header needs the point counts, so the series are counted first and
+/// written one by one, only one series is kept in memory
+pub async fn export<W: Write>(
+    db: &dyn SeriesReader,
+    series: &[(String, String)],
+    from: Option<i64>,
+    to: Option<i64>,
+    w: W,
+) -> Result<ExportReport, Box<dyn Error>> {
+    let mut infos = Vec::with_capacity(series.len());
+    for (name, key) in series {
+        let info = db.info(key).await?;
+        let data = db.load(key, from, to).await?;
+        infos.push(SeriesInfo {
+            name: name.clone(),
+            labels: info.labels,
+            samples: data.len() as u64,
+            first: data
+                .first()
+                .map(|d| d.at.and_utc().timestamp_millis() as u64),
+            last: data
+                .last()
+                .map(|d| d.at.and_utc().timestamp_millis() as u64),
+        });
+    }
+    let header = Header::new(
+        Utc::now().naive_utc(),
+        env!("CARGO_APP_VERSION"),
+        infos.clone(),
+    );
+    let mut writer = ArchiveWriter::new(w, &header)?;
+    for ((name, key), info) in series.iter().zip(infos.iter()) {
+        let data = db.load(key, from, to).await?;
+        if data.len() as u64 != info.samples {
+            log::warn!(
+                "{name} changed during the export: {} points, {} in the header",
+                data.len(),
+                info.samples
+            );
+        }
+        for d in data.iter() {
+            writer.write(&Record::point(name, d))?;
+        }
+        drop(data);
+        let revisions = db.revisions(key, from, to).await?;
+        for r in revisions.iter() {
+            writer.write(&Record::revision(name, r))?;
+        }
+        log::info!(
+            "export {name}: {} points, {} revisions",
+            info.samples,
+            revisions.len()
+        );
+    }
+    Ok(ExportReport {
+        series: infos,
+        records: writer.finish()?,
+    })
+}
+
+/// loads the archive through `DBSaver`, existing points with other values are
+/// reported as conflicts and replaced only with `overwrite`.
+/// The hourly prices are created with the compaction `rules` of their labels before
+/// the points are loaded, the archived rule destinations are left to the rules
+pub async fn restore<R: Read>(
+    storage: &Storage,
+    r: R,
+    overwrite: bool,
+    rules: impl Fn(&Labels) -> Vec<CompactionRule>,
+    retention: impl Fn(&str) -> RetentionOptions,
+) -> Result<RestoreReport, Box<dyn Error>> {
+    let reader = ArchiveReader::new(r)?;
+    let header = reader.header.clone();
+    log::info!(
+        "archive v{} of {} created {} by {}",
+        header.version,
+        header.series.len(),
+        header.created,
+        header.app
+    );
+    let mut records: HashMap<String, (Vec<Data>, Vec<Revision>)> = HashMap::new();
+    for record in reader {
+        match record? {
+            Record::Point { series, at, price } => records
+                .entry(series)
+                .or_default()
+                .0
+                .push(Data { at, price }),
+            Record::Revision { series, revision } => {
+                records.entry(series).or_default().1.push(revision)
+            }
+        }
+    }
+    let hour_rules = match header.series.iter().find(|s| s.name == TN_HOUR) {
+        Some(info) => {
+            rules(&Labels::try_from(&info.labels).map_err(|e| format!("series {TN_HOUR}: {e}"))?)
+        }
+        None => vec![],
+    };
+    let mut res = vec![];
+    for info in header.series.iter() {
+        if hour_rules.iter().any(|r| r.dest == info.name) {
+            log::info!(
+                "{} is calculated by its compaction rule, skipped",
+                info.name
+            );
+            records.remove(&info.name);
+            continue;
+        }
+        let labels =
+            Labels::try_from(&info.labels).map_err(|e| format!("series {}: {e}", info.name))?;
+        let rules = match info.name == TN_HOUR {
+            true => hour_rules.as_slice(),
+            false => &[],
+        };
+        let db = storage
+            .series_with_rules(&info.name, &labels, rules, retention(&info.name))
+            .await?;
+        let (data, revisions) = records.remove(&info.name).unwrap_or_default();
+        let range = range(&data);
+        let mut report = SeriesReport {
+            name: info.name.clone(),
+            points: data.len(),
+            first: range.map(|(from, _)| from.and_utc().timestamp_millis()),
+            last: data.iter().map(|d| d.at.and_utc().timestamp_millis()).max(),
+            ..Default::default()
+        };
+        let existing: BTreeMap<NaiveDateTime, f64> = match range {
+            Some((from, to)) => db
+                .load(from, to)
+                .await?
+                .into_iter()
+                .map(|d| (d.at, d.price))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        let mut to_save = vec![];
+        for d in data {
+            match existing.get(&d.at) {
+                Some(v) if *v == d.price => report.unchanged += 1,
+                Some(v) => {
+                    report.conflicts.push(Conflict {
+                        at: d.at.and_utc().timestamp_millis(),
+                        existing: *v,
+                        archived: d.price,
+                    });
+                    if overwrite {
+                        to_save.push(d);
+                    }
+                }
+                None => to_save.push(d),
+            }
+        }
+        for chunk in to_save.chunks(RESTORE_BATCH) {
+            report.saved += db.save_bulk(chunk).await?;
+        }
+        report.revisions = db.save_revisions(&revisions).await?;
+        log::info!(
+            "restored {}: {} saved, {} unchanged, {} conflicts, {} revisions",
+            report.name,
+            report.saved,
+            report.unchanged,
+            report.conflicts.len(),
+            report.revisions
+        );
+        res.push(report);
+    }
+    for name in records.keys() {
+        log::warn!("series {name} is not in the archive header, skipped");
+    }
+    Ok(RestoreReport {
+        series: res,
+        overwritten: overwrite,
+        aggregated: 0,
+    })
+}
+
+/// recalculates the day and month averages of the hourly prices in [from, to),
+/// returns the number of recalculated buckets
+pub async fn aggregate(
+    storage: &Storage,
+    labels: &Labels,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    retention: impl Fn(&str) -> RetentionOptions,
+) -> Result<usize, Box<dyn Error>> {
+    let hours = storage.series(TN_HOUR, labels, retention(TN_HOUR)).await?;
+    let mut res = 0;
+    for (name, ts_name, resolution, time_func) in [
+        ("day", TN_DAY, RES_DAY, time_day as fn(_) -> _),
+        ("month", TN_MONTH, RES_MONTH, time_month),
+    ] {
+        let dest = storage
+            .series(
+                ts_name,
+                &labels.with(resolution, STAT_AVG),
+                retention(ts_name),
+            )
+            .await?;
+        let mut aggregator =
+            AggregatorByDate::new(name, Box::new(hours.clone()), Box::new(dest), time_func).await?;
+        let mut at = from;
+        while at < to {
+            aggregator.recompute(at, None).await?;
+            res += 1;
+            at = time_func(at).1;
+        }
+    }
+    Ok(res)
+}
+
+/// time range of the points, `to` is exclusive
+fn range(data: &[Data]) -> Option<(NaiveDateTime, NaiveDateTime)> {
+    let from = data.iter().map(|d| d.at).min()?;
+    let to = data.iter().map(|d| d.at).max()?;
+    Some((from, to + Duration::milliseconds(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use emarket::{
+        memory::MemoryStorage,
+        series::{RES_HOUR, STAT_RAW},
+        TN_1H,
+    };
+
+    use super::*;
+
+    fn at(h: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            + Duration::hours(h)
+    }
+
+    fn d(h: i64, price: f64) -> Data {
+        Data { at: at(h), price }
+    }
+
+    fn source() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        storage
+            .create(TN_HOUR, &Labels::price("LT", RES_HOUR, STAT_RAW))
+            .unwrap();
+        storage
+            .add(TN_HOUR, &[d(0, 1.0), d(1, 2.0), d(2, 3.0), d(30, 4.0)])
+            .unwrap();
+        storage
+            .add_revisions(
+                TN_HOUR,
+                &[Revision {
+                    at: at(1),
+                    price: 2.0,
+                    revision: 1,
+                    published: None,
+                    fetched: at(-10),
+                }],
+            )
+            .unwrap();
+        storage
+    }
+
+    fn no_rules(_: &Labels) -> Vec<CompactionRule> {
+        vec![]
+    }
+
+    #[tokio::test]
+    async fn export_restore() {
+        let series = vec![(TN_HOUR.to_string(), TN_HOUR.to_string())];
+        let mut buf = vec![];
+        let report = export(
+            &source(),
+            &series,
+            None,
+            Some(at(24).and_utc().timestamp_millis()),
+            &mut buf,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.records, 4);
+        assert_eq!(report.series[0].samples, 3);
+
+        let target = MemoryStorage::new();
+        target.add(TN_HOUR, &[d(0, 1.0), d(1, 5.0)]).unwrap();
+        let storage = Storage::Memory(target.clone());
+        let res = restore(&storage, buf.as_slice(), false, no_rules, |_| {
+            RetentionOptions::default()
+        })
+        .await
+        .unwrap();
+        let s = &res.series[0];
+        assert_eq!((s.points, s.saved, s.unchanged), (3, 1, 1));
+        assert_eq!(s.last, Some(at(2).and_utc().timestamp_millis()));
+        assert_eq!(s.revisions, 1);
+        assert_eq!(
+            s.conflicts,
+            vec![Conflict {
+                at: at(1).and_utc().timestamp_millis(),
+                existing: 5.0,
+                archived: 2.0
+            }]
+        );
+        let loaded = target.load(TN_HOUR, None, None).await.unwrap();
+        assert_eq!(loaded, vec![d(0, 1.0), d(1, 5.0), d(2, 3.0)]);
+
+        let res = restore(&storage, buf.as_slice(), true, no_rules, |_| {
+            RetentionOptions::default()
+        })
+        .await
+        .unwrap();
+        assert_eq!(res.series[0].saved, 1);
+        assert_eq!(res.series[0].revisions, 0);
+        let loaded = target.load(TN_HOUR, None, None).await.unwrap();
+        assert_eq!(loaded[1], d(1, 2.0));
+        assert_eq!(
+            target.info(TN_HOUR).await.unwrap().labels.get("zone"),
+            Some(&"LT".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn restores_with_rules() {
+        let db = source();
+        let labels = Labels::price("LT", RES_HOUR, STAT_RAW);
+        db.create(TN_1H, &labels.with(RES_HOUR, STAT_AVG)).unwrap();
+        db.add(TN_1H, &[d(0, 1.0)]).unwrap();
+        let series: Vec<(String, String)> = [TN_HOUR, TN_1H]
+            .iter()
+            .map(|s| (s.to_string(), s.to_string()))
+            .collect();
+        let mut buf = vec![];
+        export(&db, &series, None, None, &mut buf).await.unwrap();
+
+        let target = MemoryStorage::new();
+        let storage = Storage::Memory(target.clone());
+        let res = restore(
+            &storage,
+            buf.as_slice(),
+            false,
+            |labels| crate::compaction::rules(labels, &[]),
+            |_| RetentionOptions::default(),
+        )
+        .await
+        .unwrap();
+        let names: Vec<&str> = res.series.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec![TN_HOUR]);
+        assert_eq!(res.series[0].saved, 4);
+        assert!(target.info(TN_1H).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn aggregates_restored() {
+        let storage = Storage::Memory(source());
+        let n = aggregate(
+            &storage,
+            &Labels::price("LT", RES_HOUR, STAT_RAW),
+            at(0),
+            at(31),
+            |_| RetentionOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert!(n >= 3);
+        let Storage::Memory(db) = storage else {
+            unreachable!()
+        };
+        let days = db.load(TN_DAY, None, None).await.unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!(db.load(TN_MONTH, None, None).await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,605 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    data::{AggregateValue, DBSaver, Data, Statistic},
+    revisions::{changed, range, Revision},
+    series::{matches_filter, Labels},
+    storage::{SeriesInfo, SeriesReader},
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS series (
+        name TEXT PRIMARY KEY,
+        labels TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS points (
+        series TEXT NOT NULL,
+        at INTEGER NOT NULL,
+        price REAL NOT NULL,
+        PRIMARY KEY (series, at)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS revisions (
+        series TEXT NOT NULL,
+        at INTEGER NOT NULL,
+        fetched INTEGER NOT NULL,
+        revision INTEGER NOT NULL,
+        published INTEGER,
+        price REAL NOT NULL,
+        PRIMARY KEY (series, at, fetched)
+    ) WITHOUT ROWID;";
+
+/// Embedded SQLite storage, keeps all series in one file.
+/// Duplicate points overwrite the old value like the redis `LAST` policy.
+#[derive(Clone)]
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+/// One series of [`SqliteStorage`]
+#[derive(Clone)]
+pub struct SqliteSeries {
+    storage: SqliteStorage,
+    ts_name: String,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<SqliteStorage, Box<dyn Error>> {
+        if let Some(dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        log::info!("sqlite {}", path.display());
+        Ok(SqliteStorage {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// creates the series or updates its labels
+    pub async fn create(&self, ts_name: &str, labels: &Labels) -> Result<(), Box<dyn Error>> {
+        let labels: BTreeMap<&str, &str> = labels.to_vec().into_iter().collect();
+        let labels = serde_json::to_string(&labels)?;
+        let ts_name = ts_name.to_string();
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO series (name, labels) VALUES (?1, ?2)
+                 ON CONFLICT (name) DO UPDATE SET labels = excluded.labels",
+                params![ts_name, labels],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub fn series(&self, ts_name: &str) -> SqliteSeries {
+        SqliteSeries {
+            storage: self.clone(),
+            ts_name: ts_name.to_string(),
+        }
+    }
+
+    /// runs the query on the blocking pool
+    async fn call<T, F>(&self, f: F) -> Result<T, Box<dyn Error>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().map_err(|e| e.to_string())?;
+            f(&mut conn).map_err(|e| e.to_string())
+        })
+        .await?;
+        Ok(res?)
+    }
+
+    async fn range(&self, ts_name: &str, from: i64, to: i64) -> Result<Vec<Data>, Box<dyn Error>> {
+        let ts_name = ts_name.to_string();
+        let res = self
+            .call(move |conn| {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT at, price FROM points WHERE series = ?1 AND at >= ?2 AND at < ?3 ORDER BY at",
+                )?;
+                let rows = stmt.query_map(params![ts_name, from, to], |r| {
+                    Ok((r.get::<_, i64>(0)?, r.get::<_, f64>(1)?))
+                })?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .await?;
+        res.into_iter()
+            .map(|(at, price)| {
+                Ok(Data {
+                    at: to_time(at)?,
+                    price,
+                })
+            })
+            .collect()
+    }
+
+    /// statistic and the last point time over [from, to)
+    async fn calc(
+        &self,
+        ts_name: &str,
+        from: i64,
+        to: i64,
+        stat: Statistic,
+    ) -> Result<Option<(f64, i64)>, Box<dyn Error>> {
+        let sql = format!(
+            "SELECT {}(price), MAX(at) FROM points WHERE series = ?1 AND at >= ?2 AND at < ?3",
+            stat.name()
+        );
+        let ts_name = ts_name.to_string();
+        self.call(move |conn| {
+            let (value, last): (Option<f64>, Option<i64>) =
+                conn.query_row(&sql, params![ts_name, from, to], |r| {
+                    Ok((r.get(0)?, r.get(1)?))
+                })?;
+            Ok(value.zip(last))
+        })
+        .await
+    }
+
+    async fn add(&self, ts_name: &str, data: &[Data]) -> Result<usize, Box<dyn Error>> {
+        let ts_name = ts_name.to_string();
+        let values: Vec<(i64, f64)> = data
+            .iter()
+            .map(|d| (d.at.and_utc().timestamp_millis(), d.price))
+            .collect();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT INTO points (series, at, price) VALUES (?1, ?2, ?3)
+                     ON CONFLICT (series, at) DO UPDATE SET price = excluded.price",
+                )?;
+                for (at, price) in values.iter() {
+                    stmt.execute(params![ts_name, at, price])?;
+                }
+            }
+            tx.commit()?;
+            Ok(values.len())
+        })
+        .await
+    }
+
+    /// adds the entries changing the known values to the revision log in one transaction
+    async fn add_revisions(
+        &self,
+        ts_name: &str,
+        data: &[Revision],
+    ) -> Result<usize, Box<dyn Error>> {
+        let Some((from, to)) = range(data) else {
+            return Ok(0);
+        };
+        let (from, to) = (
+            from.and_utc().timestamp_millis(),
+            to.and_utc().timestamp_millis(),
+        );
+        let ts_name = ts_name.to_string();
+        let data = data.to_vec();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let known = revision_rows(&tx, &ts_name, from, to)?;
+            let list = changed(&known, &data);
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT INTO revisions (series, at, fetched, revision, published, price)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                     ON CONFLICT (series, at, fetched) DO UPDATE SET
+                        revision = excluded.revision, published = excluded.published, price = excluded.price",
+                )?;
+                for r in list.iter() {
+                    stmt.execute(params![
+                        ts_name,
+                        r.at.and_utc().timestamp_millis(),
+                        r.fetched.and_utc().timestamp_millis(),
+                        r.revision,
+                        r.published.map(|v| v.and_utc().timestamp_millis()),
+                        r.price
+                    ])?;
+                }
+            }
+            tx.commit()?;
+            Ok(list.len())
+        })
+        .await
+    }
+
+    async fn revision_range(
+        &self,
+        ts_name: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<Revision>, Box<dyn Error>> {
+        let ts_name = ts_name.to_string();
+        self.call(move |conn| revision_rows(conn, &ts_name, from, to))
+            .await
+    }
+}
+
+/// revision log entries of points in [from, to), sorted by point and fetch time
+fn revision_rows(
+    conn: &Connection,
+    ts_name: &str,
+    from: i64,
+    to: i64,
+) -> rusqlite::Result<Vec<Revision>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT at, fetched, revision, published, price FROM revisions
+         WHERE series = ?1 AND at >= ?2 AND at < ?3 ORDER BY at, fetched",
+    )?;
+    let rows = stmt.query_map(params![ts_name, from, to], |r| {
+        let time = |i: usize| -> rusqlite::Result<NaiveDateTime> {
+            let v: i64 = r.get(i)?;
+            to_time(v).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    i,
+                    rusqlite::types::Type::Integer,
+                    e.to_string().into(),
+                )
+            })
+        };
+        let published: Option<i64> = r.get(3)?;
+        Ok(Revision {
+            at: time(0)?,
+            fetched: time(1)?,
+            revision: r.get(2)?,
+            published: match published {
+                Some(_) => Some(time(3)?),
+                None => None,
+            },
+            price: r.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+fn to_time(millis: i64) -> Result<NaiveDateTime, Box<dyn Error>> {
+    Ok(DateTime::from_timestamp_millis(millis)
+        .ok_or_else(|| format!("wrong time {millis}"))?
+        .naive_utc())
+}
+
+#[async_trait]
+impl SeriesReader for SqliteStorage {
+    async fn live(&self) -> Result<String, Box<dyn Error>> {
+        self.call(|conn| conn.query_row("SELECT 1", [], |_| Ok(())))
+            .await?;
+        Ok("ok".to_string())
+    }
+
+    async fn load(
+        &self,
+        ts_name: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<Vec<Data>, Box<dyn Error>> {
+        self.range(ts_name, from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX))
+            .await
+    }
+
+    async fn aggregate(
+        &self,
+        ts_name: &str,
+        from: i64,
+        to: i64,
+        stat: Statistic,
+    ) -> Result<Option<f64>, Box<dyn Error>> {
+        Ok(self.calc(ts_name, from, to, stat).await?.map(|(v, _)| v))
+    }
+
+    async fn query_index(&self, filter: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+        let list = self
+            .call(|conn| {
+                let mut stmt =
+                    conn.prepare_cached("SELECT name, labels FROM series ORDER BY name")?;
+                let rows =
+                    stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .await?;
+        let mut res = vec![];
+        for (name, labels) in list {
+            let labels: BTreeMap<String, String> = serde_json::from_str(&labels)?;
+            if matches_filter(&labels, filter) {
+                res.push(name);
+            }
+        }
+        Ok(res)
+    }
+
+    async fn info(&self, ts_name: &str) -> Result<SeriesInfo, Box<dyn Error>> {
+        let name = ts_name.to_string();
+        let (labels, samples, first, last) = self
+            .call(move |conn| {
+                let labels: Option<String> = conn
+                    .query_row(
+                        "SELECT labels FROM series WHERE name = ?1",
+                        params![name],
+                        |r| r.get(0),
+                    )
+                    .optional()?;
+                let (samples, first, last): (u64, Option<i64>, Option<i64>) = conn.query_row(
+                    "SELECT COUNT(*), MIN(at), MAX(at) FROM points WHERE series = ?1",
+                    params![name],
+                    |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+                )?;
+                Ok((labels, samples, first, last))
+            })
+            .await?;
+        let labels = labels.ok_or_else(|| format!("no series {ts_name}"))?;
+        Ok(SeriesInfo {
+            name: ts_name.to_string(),
+            labels: serde_json::from_str(&labels)?,
+            samples,
+            first: first.map(|v| v as u64),
+            last: last.map(|v| v as u64),
+        })
+    }
+
+    async fn revisions(
+        &self,
+        ts_name: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<Vec<Revision>, Box<dyn Error>> {
+        self.revision_range(ts_name, from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX))
+            .await
+    }
+}
+
+#[async_trait]
+impl DBSaver for SqliteSeries {
+    async fn live(&self) -> Result<String, Box<dyn Error>> {
+        SeriesReader::live(&self.storage).await
+    }
+
+    async fn get_last_time(&self) -> Result<Option<NaiveDateTime>, Box<dyn Error>> {
+        let ts_name = self.ts_name.clone();
+        let last: Option<i64> = self
+            .storage
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT MAX(at) FROM points WHERE series = ?1",
+                    params![ts_name],
+                    |r| r.get(0),
+                )
+            })
+            .await?;
+        last.map(to_time).transpose()
+    }
+
+    async fn save(&self, data: &Data) -> Result<bool, Box<dyn Error>> {
+        self.storage
+            .add(&self.ts_name, std::slice::from_ref(data))
+            .await?;
+        Ok(true)
+    }
+
+    async fn save_bulk(&self, data: &[Data]) -> Result<usize, Box<dyn Error>> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let res = self.storage.add(&self.ts_name, data).await?;
+        log::debug!("saved {} points to {}", res, self.ts_name);
+        Ok(res)
+    }
+
+    async fn load(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Vec<Data>, Box<dyn Error>> {
+        self.storage
+            .range(
+                &self.ts_name,
+                from.and_utc().timestamp_millis(),
+                to.and_utc().timestamp_millis(),
+            )
+            .await
+    }
+
+    async fn aggregate(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        stat: Statistic,
+    ) -> Result<Option<AggregateValue>, Box<dyn Error>> {
+        let res = self
+            .storage
+            .calc(
+                &self.ts_name,
+                from.and_utc().timestamp_millis(),
+                to.and_utc().timestamp_millis(),
+                stat,
+            )
+            .await?;
+        match res {
+            Some((value, last)) => Ok(Some(AggregateValue {
+                value,
+                last: to_time(last)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    async fn save_revisions(&self, data: &[Revision]) -> Result<usize, Box<dyn Error>> {
+        self.storage.add_revisions(&self.ts_name, data).await
+    }
+
+    async fn load_revisions(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Vec<Revision>, Box<dyn Error>> {
+        self.storage
+            .revision_range(
+                &self.ts_name,
+                from.and_utc().timestamp_millis(),
+                to.and_utc().timestamp_millis(),
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use chrono::{Duration, NaiveDate};
+
+    use super::*;
+    use crate::series::{price_filter, RES_DAY, RES_HOUR, STAT_AVG, STAT_RAW};
+
+    fn at(h: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            + Duration::hours(h)
+    }
+
+    fn d(h: i64, price: f64) -> Data {
+        Data { at: at(h), price }
+    }
+
+    fn open(name: &str) -> (SqliteStorage, std::path::PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("emarket-sqlite-{name}-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        (SqliteStorage::open(&path).unwrap(), path)
+    }
+
+    #[tokio::test]
+    async fn save_load() {
+        let (storage, path) = open("save");
+        storage
+            .create("np_lt", &Labels::price("LT", RES_HOUR, STAT_RAW))
+            .await
+            .unwrap();
+        let db = storage.series("np_lt");
+        assert_eq!(db.get_last_time().await.unwrap(), None);
+        assert_eq!(
+            db.save_bulk(&[d(0, 1.0), d(1, 2.0), d(2, 3.0)])
+                .await
+                .unwrap(),
+            3
+        );
+        assert!(db.save(&d(1, 5.0)).await.unwrap());
+        assert_eq!(db.get_last_time().await.unwrap(), Some(at(2)));
+        assert_eq!(
+            db.load(at(0), at(2)).await.unwrap(),
+            vec![d(0, 1.0), d(1, 5.0)]
+        );
+        assert!(db.load(at(3), at(4)).await.unwrap().is_empty());
+        assert!(storage
+            .series("other")
+            .load(at(0), at(4))
+            .await
+            .unwrap()
+            .is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn aggregates() {
+        let (storage, path) = open("aggregate");
+        let db = storage.series("np_lt");
+        db.save_bulk(&[d(0, 1.0), d(1, -2.0), d(2, 4.0), d(3, 10.0)])
+            .await
+            .unwrap();
+        let res = db
+            .aggregate(at(0), at(3), Statistic::Avg)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_relative_eq!(res.value, 1.0);
+        assert_eq!(res.last, at(2));
+        let res = db
+            .aggregate(at(0), at(3), Statistic::Min)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_relative_eq!(res.value, -2.0);
+        assert_eq!(
+            db.aggregate(at(5), at(6), Statistic::Max).await.unwrap(),
+            None
+        );
+        let ms = |h| at(h).and_utc().timestamp_millis();
+        assert_eq!(
+            storage
+                .aggregate("np_lt", ms(0), ms(4), Statistic::Max)
+                .await
+                .unwrap(),
+            Some(10.0)
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn finds_series() {
+        let (storage, path) = open("find");
+        let labels = Labels::price("LT", RES_HOUR, STAT_RAW);
+        storage.create("np_lt", &labels).await.unwrap();
+        storage
+            .create("np_lt_d", &labels.with(RES_DAY, STAT_AVG))
+            .await
+            .unwrap();
+        storage.series("np_lt_d").save(&d(0, 1.0)).await.unwrap();
+        assert_eq!(
+            storage
+                .query_index(&price_filter("LT", RES_DAY, STAT_AVG))
+                .await
+                .unwrap(),
+            vec!["np_lt_d"]
+        );
+        assert_eq!(
+            storage.query_index(&["zone=LT".to_string()]).await.unwrap(),
+            vec!["np_lt", "np_lt_d"]
+        );
+        let info = storage.info("np_lt_d").await.unwrap();
+        assert_eq!(info.samples, 1);
+        assert_eq!(info.first, Some(at(0).and_utc().timestamp_millis() as u64));
+        assert_eq!(info.labels.get("resolution").unwrap(), "1d");
+        let info = storage.info("np_lt").await.unwrap();
+        assert_eq!((info.samples, info.first), (0, None));
+        assert!(storage.info("none").await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_revisions() {
+        let (storage, path) = open("revisions");
+        let db = storage.series("np_lt");
+        let rev = |h: i64, price: f64, revision: u32, fetched: i64| Revision {
+            at: at(h),
+            price,
+            revision,
+            published: Some(at(fetched - 1)),
+            fetched: at(fetched),
+        };
+        let first = vec![rev(10, 1.0, 1, 0), rev(11, 2.0, 1, 0)];
+        assert_eq!(db.save_revisions(&first).await.unwrap(), 2);
+        let second = vec![rev(10, 1.5, 2, 5), rev(11, 2.0, 1, 5)];
+        assert_eq!(db.save_revisions(&second).await.unwrap(), 1);
+        assert_eq!(db.save_revisions(&second).await.unwrap(), 0);
+        assert_eq!(
+            storage.revisions("np_lt", None, None).await.unwrap(),
+            vec![rev(10, 1.0, 1, 0), rev(10, 1.5, 2, 5), rev(11, 2.0, 1, 0)]
+        );
+        assert_eq!(
+            db.load_revisions(at(11), at(12)).await.unwrap(),
+            vec![rev(11, 2.0, 1, 0)]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
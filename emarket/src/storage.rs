@@ -0,0 +1,140 @@
+use std::{collections::BTreeMap, error::Error, path::PathBuf, str::FromStr};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::{Data, Statistic},
+    revisions::Revision,
+};
+
+/// Backend selected by the `--storage` url
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageUrl {
+    Redis(String),
+    Sqlite(PathBuf),
+    /// nothing is persisted, for tests and demos
+    Memory,
+}
+
+impl FromStr for StorageUrl {
+    type Err = String;
+
+    /// parses `redis://...`, `rediss://...`, `sqlite:///path/to/file.db` or `memory://`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "memory://" {
+            return Ok(StorageUrl::Memory);
+        }
+        if let Some(path) = s.strip_prefix("sqlite://") {
+            if path.is_empty() {
+                return Err(format!("no sqlite file in '{s}'"));
+            }
+            return Ok(StorageUrl::Sqlite(PathBuf::from(path)));
+        }
+        if s.starts_with("redis://") || s.starts_with("rediss://") || s.starts_with("unix://") {
+            return Ok(StorageUrl::Redis(s.to_string()));
+        }
+        Err(format!(
+            "wrong storage '{s}', expected redis://..., sqlite:///path or memory://"
+        ))
+    }
+}
+
+/// picks `--storage`, falls back to the redis url for older configs
+pub fn storage_url(storage: Option<&str>, redis_url: &str) -> Result<StorageUrl, String> {
+    match storage.filter(|s| !s.is_empty()) {
+        Some(s) => s.parse(),
+        None => Ok(StorageUrl::Redis(redis_url.to_string())),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeriesInfo {
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    pub samples: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last: Option<u64>,
+}
+
+/// Read access to named series, used by `importer-ws`
+#[async_trait]
+pub trait SeriesReader: Send + Sync {
+    async fn live(&self) -> Result<String, Box<dyn Error>>;
+    /// points in [from, to), `None` leaves the range open
+    async fn load(
+        &self,
+        ts_name: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<Vec<Data>, Box<dyn Error>>;
+    /// calculates a statistic over [from, to), the default implementation loads all points
+    async fn aggregate(
+        &self,
+        ts_name: &str,
+        from: i64,
+        to: i64,
+        stat: Statistic,
+    ) -> Result<Option<f64>, Box<dyn Error>> {
+        let data = self.load(ts_name, Some(from), Some(to)).await?;
+        Ok(stat.calc(&data))
+    }
+    /// names of the series matching the label filter, sorted
+    async fn query_index(&self, filter: &[String]) -> Result<Vec<String>, Box<dyn Error>>;
+    async fn info(&self, ts_name: &str) -> Result<SeriesInfo, Box<dyn Error>>;
+    /// revision log entries of points in [from, to), sorted by point and fetch time,
+    /// the default is for storages without history
+    async fn revisions(
+        &self,
+        _ts_name: &str,
+        _from: Option<i64>,
+        _to: Option<i64>,
+    ) -> Result<Vec<Revision>, Box<dyn Error>> {
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_url() {
+        assert_eq!(
+            StorageUrl::from_str("redis://localhost:6379").unwrap(),
+            StorageUrl::Redis("redis://localhost:6379".to_string())
+        );
+        assert_eq!(
+            StorageUrl::from_str("sqlite:///data/emarket.db").unwrap(),
+            StorageUrl::Sqlite(PathBuf::from("/data/emarket.db"))
+        );
+        assert_eq!(
+            StorageUrl::from_str("sqlite://emarket.db").unwrap(),
+            StorageUrl::Sqlite(PathBuf::from("emarket.db"))
+        );
+        assert_eq!(
+            StorageUrl::from_str("memory://").unwrap(),
+            StorageUrl::Memory
+        );
+        assert!(StorageUrl::from_str("sqlite://").is_err());
+        assert!(StorageUrl::from_str("postgres://localhost").is_err());
+    }
+
+    #[test]
+    fn fallback_to_redis() {
+        assert_eq!(
+            storage_url(None, "redis://r:6379").unwrap(),
+            StorageUrl::Redis("redis://r:6379".to_string())
+        );
+        assert_eq!(
+            storage_url(Some(""), "redis://r:6379").unwrap(),
+            StorageUrl::Redis("redis://r:6379".to_string())
+        );
+        assert_eq!(
+            storage_url(Some("sqlite:///a.db"), "redis://r:6379").unwrap(),
+            StorageUrl::Sqlite(PathBuf::from("/a.db"))
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::{error::Error, sync::Arc};
+
+use emarket::{
+    data::DBSaver,
+    memory::MemoryStorage,
+    redis_pool::{RedisArgs, RedisPool},
+    redis_reader::RedisReader,
+    series::Labels,
+    sqlite::SqliteStorage,
+    storage::{SeriesReader, StorageUrl},
+};
+
+use crate::redis::{CompactionRule, RedisClient, RetentionOptions};
+
+/// Opened storage for the commands working with any series by name
+pub enum Storage {
+    Redis { pool: RedisPool, args: RedisArgs },
+    Sqlite(SqliteStorage),
+    Memory(MemoryStorage),
+}
+
+impl Storage {
+    pub async fn open(url: &StorageUrl, args: &RedisArgs) -> Result<Storage, Box<dyn Error>> {
+        let res = match url {
+            StorageUrl::Redis(url) => Storage::Redis {
+                pool: RedisPool::new(url, args).await?,
+                args: args.clone(),
+            },
+            StorageUrl::Sqlite(path) => Storage::Sqlite(SqliteStorage::open(path)?),
+            StorageUrl::Memory => Storage::Memory(MemoryStorage::new()),
+        };
+        Ok(res)
+    }
+
+    pub async fn reader(&self) -> Result<Arc<dyn SeriesReader>, Box<dyn Error>> {
+        let res: Arc<dyn SeriesReader> = match self {
+            Storage::Redis { pool, .. } => Arc::new(RedisReader::new(pool.clone()).await?),
+            Storage::Sqlite(storage) => Arc::new(storage.clone()),
+            Storage::Memory(storage) => Arc::new(storage.clone()),
+        };
+        Ok(res)
+    }
+
+    /// storage key of the series
+    pub fn key(&self, name: &str) -> String {
+        match self {
+            Storage::Redis { args, .. } => args.key(name),
+            _ => name.to_string(),
+        }
+    }
+
+    /// series name of the storage key
+    pub fn name<'a>(&self, key: &'a str) -> &'a str {
+        match self {
+            Storage::Redis { args, .. } => args.name(key),
+            _ => key,
+        }
+    }
+
+    /// opens the series for writing, creates it with the labels if missing,
+    /// `retention` is applied by redis only
+    pub async fn series(
+        &self,
+        name: &str,
+        labels: &Labels,
+        retention: RetentionOptions,
+    ) -> Result<Arc<dyn DBSaver + Send + Sync>, Box<dyn Error>> {
+        self.series_with_rules(name, labels, &[], retention).await
+    }
+
+    /// as [`Storage::series`], redis also creates the compaction rules,
+    /// their `dest` are series names
+    pub async fn series_with_rules(
+        &self,
+        name: &str,
+        labels: &Labels,
+        rules: &[CompactionRule],
+        retention: RetentionOptions,
+    ) -> Result<Arc<dyn DBSaver + Send + Sync>, Box<dyn Error>> {
+        let key = self.key(name);
+        let res: Arc<dyn DBSaver + Send + Sync> = match self {
+            Storage::Redis { pool, .. } => {
+                let rules: Vec<CompactionRule> = rules
+                    .iter()
+                    .map(|r| CompactionRule {
+                        dest: self.key(&r.dest),
+                        ..r.clone()
+                    })
+                    .collect();
+                Arc::new(RedisClient::new(pool.clone(), &key, labels, &rules, retention).await?)
+            }
+            Storage::Sqlite(storage) => {
+                storage.create(&key, labels).await?;
+                Arc::new(storage.series(&key))
+            }
+            Storage::Memory(storage) => {
+                storage.create(&key, labels)?;
+                Arc::new(storage.series(&key))
+            }
+        };
+        Ok(res)
+    }
+}
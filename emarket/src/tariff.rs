@@ -0,0 +1,305 @@
+use std::{collections::BTreeSet, error::Error, path::Path};
+
+use chrono::{Datelike, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Timelike, Weekday};
+use chrono_tz::Europe::Vilnius;
+use serde::{Deserialize, Serialize};
+
+/// Value applying from a date until the next one of the list
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dated {
+    pub from: NaiveDate,
+    pub value: f64,
+}
+
+/// value applying on the date
+fn on(list: &[Dated], date: NaiveDate) -> Option<f64> {
+    list.iter()
+        .take_while(|d| d.from <= date)
+        .last()
+        .map(|d| d.value)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Days {
+    #[default]
+    All,
+    /// monday to friday except holidays
+    Workdays,
+    /// saturday, sunday and holidays
+    Weekend,
+}
+
+/// Local hours `[from, to)` of a zone, wraps midnight if `from > to`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hours {
+    #[serde(default)]
+    pub days: Days,
+    pub from: u32,
+    pub to: u32,
+    /// months of the rule, all if empty
+    #[serde(default)]
+    pub months: Vec<u32>,
+}
+
+impl Hours {
+    fn matches(&self, local: NaiveDateTime, holiday: bool) -> bool {
+        let weekend = holiday || matches!(local.weekday(), Weekday::Sat | Weekday::Sun);
+        let day = match self.days {
+            Days::All => true,
+            Days::Workdays => !weekend,
+            Days::Weekend => weekend,
+        };
+        let h = local.hour();
+        let hour = match self.from.cmp(&self.to) {
+            std::cmp::Ordering::Less => h >= self.from && h < self.to,
+            std::cmp::Ordering::Greater => h >= self.from || h < self.to,
+            std::cmp::Ordering::Equal => true,
+        };
+        day && hour && (self.months.is_empty() || self.months.contains(&local.month()))
+    }
+}
+
+/// Time-of-use zone of a grid plan, a zone without hours matches any time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Zone {
+    pub name: String,
+    #[serde(default)]
+    pub hours: Vec<Hours>,
+    /// EUR/kWh without VAT
+    pub fee: Vec<Dated>,
+}
+
+/// Grid distribution plan, the first matching zone applies
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Grid {
+    /// zone hours are in the standard (winter) time all year, as in the ESO plans
+    #[serde(default)]
+    pub standard_time: bool,
+    pub zones: Vec<Zone>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tariff {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    /// VAT in percent
+    pub vat: Vec<Dated>,
+    /// supplier margin, EUR/kWh without VAT
+    #[serde(default)]
+    pub margin: Vec<Dated>,
+    pub grid: Grid,
+    /// public service obligation fee, EUR/kWh without VAT
+    #[serde(default)]
+    pub pso: Vec<Dated>,
+}
+
+/// Retail price in EUR/kWh with its components, VAT is the amount added
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Retail {
+    pub zone: String,
+    pub energy: f64,
+    pub margin: f64,
+    pub grid: f64,
+    pub pso: f64,
+    pub vat: f64,
+    pub total: f64,
+}
+
+/// Tariffs file: `{"holidays": ["2024-12-24"], "tariffs": [...]}`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tariffs {
+    /// days priced as weekends
+    #[serde(default)]
+    pub holidays: BTreeSet<NaiveDate>,
+    #[serde(default)]
+    pub tariffs: Vec<Tariff>,
+}
+
+impl Tariffs {
+    pub fn load(path: &Path) -> Result<Tariffs, Box<dyn Error>> {
+        Ok(Tariffs::from_json(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// parses and checks the tariffs
+    pub fn from_json(txt: &str) -> Result<Tariffs, String> {
+        let mut res: Tariffs = serde_json::from_str(txt).map_err(|e| e.to_string())?;
+        let mut ids = BTreeSet::new();
+        for t in res.tariffs.iter_mut() {
+            if !ids.insert(t.id.clone()) {
+                return Err(format!("duplicate tariff '{}'", t.id));
+            }
+            if t.vat.is_empty() || t.grid.zones.is_empty() {
+                return Err(format!("tariff '{}' has no VAT or grid zones", t.id));
+            }
+            for z in t.grid.zones.iter() {
+                if let Some(h) = z.hours.iter().find(|h| h.from > 23 || h.to > 24) {
+                    return Err(format!(
+                        "tariff '{}' zone '{}': wrong hours {h:?}",
+                        t.id, z.name
+                    ));
+                }
+            }
+            let lists = [&mut t.vat, &mut t.margin, &mut t.pso]
+                .into_iter()
+                .chain(t.grid.zones.iter_mut().map(|z| &mut z.fee));
+            for list in lists {
+                list.sort_by_key(|d| d.from);
+            }
+        }
+        Ok(res)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Plan<'_>> {
+        self.tariffs
+            .iter()
+            .find(|t| t.id.eq_ignore_ascii_case(id))
+            .map(|tariff| Plan {
+                tariff,
+                holidays: &self.holidays,
+            })
+    }
+
+    pub fn ids(&self) -> Vec<&str> {
+        self.tariffs.iter().map(|t| t.id.as_str()).collect()
+    }
+}
+
+/// Tariff with the holidays to price
+#[derive(Debug, Clone, Copy)]
+pub struct Plan<'a> {
+    pub tariff: &'a Tariff,
+    holidays: &'a BTreeSet<NaiveDate>,
+}
+
+impl Plan<'_> {
+    /// retail price of the hour at `at` (UTC) with the spot `energy` price in EUR/kWh
+    pub fn price(&self, at: NaiveDateTime, energy: f64) -> Result<Retail, String> {
+        let local = Vilnius.from_utc_datetime(&at).naive_local();
+        let date = local.date();
+        let zone = self.zone(at)?;
+        let fee = |what: &str, list: &[Dated], required: bool| match on(list, date) {
+            Some(v) => Ok(v),
+            None if !required && list.is_empty() => Ok(0.0),
+            None => Err(format!("no {what} of '{}' for {date}", self.tariff.id)),
+        };
+        let margin = fee("margin", &self.tariff.margin, false)?;
+        let grid = fee("grid fee", &zone.fee, true)?;
+        let pso = fee("PSO fee", &self.tariff.pso, false)?;
+        let vat_rate = fee("VAT", &self.tariff.vat, true)?;
+        let net = energy + margin + grid + pso;
+        let vat = net * vat_rate / 100.0;
+        Ok(Retail {
+            zone: zone.name.clone(),
+            energy,
+            margin,
+            grid,
+            pso,
+            vat,
+            total: net + vat,
+        })
+    }
+
+    /// grid zone of the hour at `at` (UTC)
+    pub fn zone(&self, at: NaiveDateTime) -> Result<&Zone, String> {
+        let local = match self.tariff.grid.standard_time {
+            true => standard_time().from_utc_datetime(&at).naive_local(),
+            false => Vilnius.from_utc_datetime(&at).naive_local(),
+        };
+        let holiday = self.holidays.contains(&local.date());
+        self.tariff
+            .grid
+            .zones
+            .iter()
+            .find(|z| z.hours.is_empty() || z.hours.iter().any(|h| h.matches(local, holiday)))
+            .ok_or_else(|| format!("no grid zone of '{}' for {local}", self.tariff.id))
+    }
+}
+
+/// Lithuanian standard (winter) time
+fn standard_time() -> FixedOffset {
+    FixedOffset::east_opt(2 * 3600).expect("offset")
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::*;
+
+    const TARIFFS: &str = r#"{
+  "holidays": ["2024-06-24"],
+  "tariffs": [{
+    "id": "two",
+    "vat": [{"from": "2024-01-01", "value": 21}, {"from": "2024-07-01", "value": 9}],
+    "margin": [{"from": "2024-01-01", "value": 0.01}],
+    "pso": [{"from": "2024-01-01", "value": 0.005}],
+    "grid": {
+      "standard_time": true,
+      "zones": [
+        {"name": "day", "hours": [{"days": "workdays", "from": 7, "to": 23}], "fee": [{"from": "2024-01-01", "value": 0.1}]},
+        {"name": "night", "fee": [{"from": "2024-01-01", "value": 0.05}]}
+      ]
+    }
+  }]
+}"#;
+
+    fn at(m: u32, d: u32, h: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, m, d)
+            .unwrap()
+            .and_hms_opt(h, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn selects_zones() {
+        let tariffs = Tariffs::from_json(TARIFFS).unwrap();
+        let plan = tariffs.get("TWO").unwrap();
+        let zone = |at| plan.zone(at).unwrap().name.clone();
+        // wednesday, 07:00 and 06:00 EET
+        assert_eq!(zone(at(1, 10, 5)), "day");
+        assert_eq!(zone(at(1, 10, 4)), "night");
+        // summer: 08:00 EEST is 07:00 in the standard time
+        assert_eq!(zone(at(6, 5, 5)), "day");
+        assert_eq!(zone(at(6, 5, 4)), "night");
+        // saturday and a holiday monday
+        assert_eq!(zone(at(1, 13, 10)), "night");
+        assert_eq!(zone(at(6, 24, 10)), "night");
+        assert!(tariffs.get("one").is_none());
+    }
+
+    #[test]
+    fn calculates_price() {
+        let tariffs = Tariffs::from_json(TARIFFS).unwrap();
+        let plan = tariffs.get("two").unwrap();
+        let res = plan.price(at(1, 10, 10), 0.1).unwrap();
+        assert_eq!(res.zone, "day");
+        assert_relative_eq!(res.vat, 0.215 * 0.21);
+        assert_relative_eq!(res.total, 0.215 * 1.21);
+        // new VAT from july
+        let res = plan.price(at(7, 1, 10), 0.1).unwrap();
+        assert_relative_eq!(res.total, 0.215 * 1.09);
+        assert!(plan
+            .price(at(1, 1, 0) - chrono::Duration::hours(3), 0.1)
+            .is_err());
+    }
+
+    #[test]
+    fn checks_tariffs() {
+        let dup = format!(
+            r#"{{"tariffs": [{t}, {t}]}}"#,
+            t = r#"{"id": "a", "vat": [{"from": "2024-01-01", "value": 21}], "grid": {"zones": [{"name": "all", "fee": []}]}}"#
+        );
+        assert!(Tariffs::from_json(&dup).is_err());
+        assert!(Tariffs::from_json(
+            r#"{"tariffs": [{"id": "a", "vat": [], "grid": {"zones": []}}]}"#
+        )
+        .is_err());
+        let example = Tariffs::from_json(include_str!("../tariffs.example.json")).unwrap();
+        assert_eq!(example.ids(), vec!["eso-1", "eso-2", "eso-4"]);
+        for id in example.ids() {
+            assert!(example.get(id).unwrap().price(at(3, 1, 10), 0.1).is_ok());
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::str::FromStr;
+
+use crate::series::Labels;
+
+pub const CURRENCY_EUR: &str = "EUR";
+pub const UNIT_MWH: &str = "MWh";
+pub const UNIT_KWH: &str = "kWh";
+
+/// Unit of the served prices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceUnit {
+    EurMwh,
+    EurKwh,
+    /// euro cents per kWh
+    CtKwh,
+}
+
+impl PriceUnit {
+    /// multiplier converting the prices of a series with `labels` to this unit
+    pub fn factor(self, labels: &Labels) -> Result<f64, String> {
+        if !labels.currency.eq_ignore_ascii_case(CURRENCY_EUR) {
+            return Err(format!(
+                "can't convert {} prices to {self:?}",
+                labels.currency
+            ));
+        }
+        let per_mwh = 1.0 / mwh(&labels.unit)?;
+        Ok(match self {
+            PriceUnit::EurMwh => per_mwh,
+            PriceUnit::EurKwh => per_mwh / 1000.0,
+            PriceUnit::CtKwh => per_mwh / 10.0,
+        })
+    }
+}
+
+impl FromStr for PriceUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "eur_mwh" => Ok(PriceUnit::EurMwh),
+            "eur_kwh" => Ok(PriceUnit::EurKwh),
+            "ct_kwh" => Ok(PriceUnit::CtKwh),
+            _ => Err(format!(
+                "unsupported unit '{s}', expected eur_mwh, eur_kwh or ct_kwh"
+            )),
+        }
+    }
+}
+
+/// energy unit in MWh
+fn mwh(unit: &str) -> Result<f64, String> {
+    match unit.to_lowercase().as_str() {
+        "mwh" => Ok(1.0),
+        "kwh" => Ok(0.001),
+        _ => Err(format!("unsupported energy unit '{unit}'")),
+    }
+}
+
+/// label value of an energy unit: MWH -> MWh
+pub fn normalize_unit(unit: &str) -> Result<&'static str, String> {
+    match unit.to_lowercase().as_str() {
+        "mwh" => Ok(UNIT_MWH),
+        "kwh" => Ok(UNIT_KWH),
+        _ => Err(format!("unsupported energy unit '{unit}'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::series::{RES_HOUR, STAT_RAW};
+
+    use super::*;
+
+    #[test]
+    fn converts_units() {
+        let labels = Labels::price("LT", RES_HOUR, STAT_RAW);
+        let price = 123.4;
+        let conv = |u: &str| price * u.parse::<PriceUnit>().unwrap().factor(&labels).unwrap();
+        assert_relative_eq!(conv("eur_mwh"), 123.4);
+        assert_relative_eq!(conv("EUR_KWH"), 0.1234);
+        assert_relative_eq!(conv("ct_kwh"), 12.34);
+        assert!("usd_mwh".parse::<PriceUnit>().is_err());
+
+        let kwh = labels.with_units("EUR", "kWh");
+        assert_relative_eq!(PriceUnit::EurMwh.factor(&kwh).unwrap(), 1000.0);
+        assert_relative_eq!(PriceUnit::CtKwh.factor(&kwh).unwrap(), 100.0);
+        assert!(PriceUnit::EurKwh
+            .factor(&labels.with_units("PLN", "MWh"))
+            .is_err());
+        assert_eq!(normalize_unit("MWH"), Ok(UNIT_MWH));
+        assert!(normalize_unit("GJ").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use chrono::{DateTime, Datelike, Days, NaiveDateTime, TimeZone};
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
 use chrono_tz::Europe::Vilnius;
 use rand::Rng;
 
@@ -44,6 +44,25 @@ pub fn jitter(d: chrono::Duration) -> chrono::Duration {
     chrono::Duration::milliseconds(ms)
 }
 
+pub fn parse_duration(s: &str) -> Result<std::time::Duration, String> {
+    duration_str::parse(s)
+}
+
+/// parses `2024-01-31`, `2024-01-31T10:00:00Z` or unix millis as UTC time
+pub fn parse_time(s: &str) -> Result<NaiveDateTime, String> {
+    if let Ok(millis) = s.parse::<i64>() {
+        return DateTime::from_timestamp_millis(millis)
+            .map(|v| v.naive_utc())
+            .ok_or_else(|| format!("wrong time '{s}'"));
+    }
+    if let Ok(v) = DateTime::parse_from_rfc3339(s) {
+        return Ok(v.naive_utc());
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|v| v.and_time(NaiveTime::MIN))
+        .map_err(|e| format!("wrong time '{s}': {e}"))
+}
+
 pub fn to_str_or_none(v: Option<i64>) -> String {
     v.map_or_else(|| "none".to_owned(), |v| v.to_string())
 }
@@ -53,7 +72,7 @@ mod tests {
     use chrono::{Duration, NaiveDate, NaiveDateTime};
     use more_asserts::{assert_ge, assert_le};
 
-    use crate::utils::{jitter, time_day_vilnius, time_month_vilnius, to_time};
+    use crate::utils::{jitter, parse_time, time_day_vilnius, time_month_vilnius, to_time};
     #[test]
     fn to_time_0() {
         assert_eq!(
@@ -136,4 +155,16 @@ mod tests {
             .and_hms_opt(h, m, s)
             .unwrap()
     }
+
+    #[test]
+    fn parses_time() {
+        let want = NaiveDate::from_ymd_opt(2024, 1, 31)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        assert_eq!(parse_time("2024-01-31").unwrap(), want);
+        assert_eq!(parse_time("2024-01-31T02:00:00+02:00").unwrap(), want);
+        assert_eq!(parse_time("1706659200000").unwrap(), want);
+        assert!(parse_time("2024-13-01").is_err());
+    }
 }
@@ -0,0 +1,296 @@
+use std::{collections::VecDeque, error::Error, sync::Arc};
+
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::{DBSaver, Data},
+    quarantine::Quarantine,
+};
+
+/// harmonised SDAC min and max clearing prices, EUR/MWh
+pub const SDAC_MIN_PRICE: f64 = -500.0;
+pub const SDAC_MAX_PRICE: f64 = 4000.0;
+/// accepted prices the jump is measured against
+const HISTORY: usize = 24;
+/// stored range before a batch loaded for the history
+const HISTORY_LOOKBACK: i64 = 2;
+
+/// What to do with a point failing a rule, ordered by severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Action {
+    /// log and save the point
+    Warn,
+    /// keep the point out of the DB until it is reviewed
+    Quarantine,
+    /// drop the point
+    Reject,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum Issue {
+    NotFinite,
+    BelowMin {
+        limit: f64,
+    },
+    AboveMax {
+        limit: f64,
+    },
+    /// too far from the average of the recent prices
+    Jump {
+        reference: f64,
+    },
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ValidationArgs {
+    /// Min valid price, the SDAC harmonised min clearing price by default
+    #[arg(long, env, default_value_t = SDAC_MIN_PRICE, allow_hyphen_values = true)]
+    pub price_min: f64,
+    /// Max valid price, the SDAC harmonised max clearing price by default
+    #[arg(long, env, default_value_t = SDAC_MAX_PRICE)]
+    pub price_max: f64,
+    /// Action for prices out of the min and max limits
+    #[arg(long, env, value_enum, default_value_t = Action::Quarantine)]
+    pub price_limit_action: Action,
+    /// Max difference from the average of the last 24 accepted prices
+    #[arg(long, env, default_value_t = 1000.0)]
+    pub price_max_jump: f64,
+    /// Action for prices jumping more than --price-max-jump
+    #[arg(long, env, value_enum, default_value_t = Action::Warn)]
+    pub price_jump_action: Action,
+}
+
+impl Default for ValidationArgs {
+    fn default() -> Self {
+        ValidationArgs {
+            price_min: SDAC_MIN_PRICE,
+            price_max: SDAC_MAX_PRICE,
+            price_limit_action: Action::Quarantine,
+            price_max_jump: 1000.0,
+            price_jump_action: Action::Warn,
+        }
+    }
+}
+
+/// Checks the retrieved points of a series before they are saved
+pub struct Validator {
+    args: ValidationArgs,
+    quarantine: Quarantine,
+    /// stored points of the series, the jump history is seeded from them
+    db: Arc<dyn DBSaver + Send + Sync>,
+}
+
+impl Validator {
+    pub fn new(
+        args: ValidationArgs,
+        quarantine: Quarantine,
+        db: Arc<dyn DBSaver + Send + Sync>,
+    ) -> Validator {
+        Validator {
+            args,
+            quarantine,
+            db,
+        }
+    }
+
+    /// last stored prices before the batch starting at `data[0]`
+    pub async fn history(&self, data: &[Data]) -> VecDeque<f64> {
+        let Some(first) = data.first() else {
+            return VecDeque::new();
+        };
+        let from = first.at - Duration::days(HISTORY_LOOKBACK);
+        match self.db.load(from, first.at).await {
+            Ok(stored) => stored
+                .iter()
+                .rev()
+                .take(HISTORY)
+                .rev()
+                .map(|d| d.price)
+                .collect(),
+            Err(err) => {
+                log::warn!("no price history before {}: {err}", first.at);
+                VecDeque::new()
+            }
+        }
+    }
+
+    /// failed rules of the price with their actions
+    pub fn issues(&self, price: f64, history: &VecDeque<f64>) -> Vec<(Issue, Action)> {
+        let args = &self.args;
+        if !price.is_finite() {
+            return vec![(Issue::NotFinite, Action::Reject)];
+        }
+        let mut res = vec![];
+        if price < args.price_min {
+            res.push((
+                Issue::BelowMin {
+                    limit: args.price_min,
+                },
+                args.price_limit_action,
+            ));
+        }
+        if price > args.price_max {
+            res.push((
+                Issue::AboveMax {
+                    limit: args.price_max,
+                },
+                args.price_limit_action,
+            ));
+        }
+        if !history.is_empty() {
+            let reference = history.iter().sum::<f64>() / history.len() as f64;
+            if (price - reference).abs() > args.price_max_jump {
+                res.push((Issue::Jump { reference }, args.price_jump_action));
+            }
+        }
+        res
+    }
+
+    /// returns the points to save, quarantined and rejected points are left out,
+    /// `data` must be sorted. Jumps are measured against the stored prices before
+    /// the batch and the points kept from it
+    pub async fn filter(&self, data: Vec<Data>) -> Result<Vec<Data>, Box<dyn Error>> {
+        let mut history = self.history(&data).await;
+        self.check(&mut history, data)
+    }
+
+    /// as [`Validator::filter`] with the `history` of the previous batches,
+    /// the kept points are added to it
+    pub fn check(
+        &self,
+        history: &mut VecDeque<f64>,
+        data: Vec<Data>,
+    ) -> Result<Vec<Data>, Box<dyn Error>> {
+        let mut res = Vec::with_capacity(data.len());
+        for d in data {
+            let issues = self.issues(d.price, history);
+            let keep = match issues.iter().map(|(_, a)| *a).max() {
+                None => true,
+                Some(Action::Warn) => {
+                    log::warn!("suspicious {} at {}: {issues:?}", d.price, d.at);
+                    true
+                }
+                Some(Action::Quarantine) => {
+                    let issues = issues.into_iter().map(|(i, _)| i).collect();
+                    !self.quarantine.hold(&d, issues)?
+                }
+                Some(Action::Reject) => {
+                    log::error!("rejected {} at {}: {issues:?}", d.price, d.at);
+                    false
+                }
+            };
+            if keep {
+                if history.len() == HISTORY {
+                    history.pop_front();
+                }
+                history.push_back(d.price);
+                res.push(d);
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate};
+
+    use crate::{
+        memory::MemoryStorage,
+        quarantine::Status,
+        series::{Labels, RES_HOUR, STAT_RAW},
+    };
+
+    use super::*;
+
+    fn d(h: i64, price: f64) -> Data {
+        Data {
+            at: NaiveDate::from_ymd_opt(2024, 1, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                + Duration::hours(h),
+            price,
+        }
+    }
+
+    fn validator(args: ValidationArgs, quarantine: &Quarantine, db: &MemoryStorage) -> Validator {
+        Validator::new(args, quarantine.clone(), Arc::new(db.series("np_lt")))
+    }
+
+    #[tokio::test]
+    async fn filters_points() {
+        let quarantine = Quarantine::open(None).unwrap();
+        let args = ValidationArgs {
+            price_max_jump: 100.0,
+            ..Default::default()
+        };
+        let db = MemoryStorage::new();
+        db.create("np_lt", &Labels::price("LT", RES_HOUR, STAT_RAW))
+            .unwrap();
+        let validator = validator(args, &quarantine, &db);
+        let res = validator
+            .filter(vec![
+                d(0, 10.0),
+                d(1, f64::NAN),
+                d(2, 1e9),
+                d(3, -600.0),
+                d(4, 300.0),
+                d(5, 20.0),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(res, vec![d(0, 10.0), d(4, 300.0), d(5, 20.0)]);
+        let held = quarantine.list(Some(Status::Pending));
+        assert_eq!(held.len(), 2);
+        assert_eq!(
+            held[0].issues,
+            vec![
+                Issue::AboveMax { limit: 4000.0 },
+                Issue::Jump { reference: 10.0 }
+            ]
+        );
+        assert_eq!(
+            held[1].issues,
+            vec![
+                Issue::BelowMin { limit: -500.0 },
+                Issue::Jump { reference: 10.0 }
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn seeds_history_from_stored() {
+        let quarantine = Quarantine::open(None).unwrap();
+        let args = ValidationArgs {
+            price_max_jump: 100.0,
+            price_jump_action: Action::Reject,
+            ..Default::default()
+        };
+        let db = MemoryStorage::new();
+        db.create("np_lt", &Labels::price("LT", RES_HOUR, STAT_RAW))
+            .unwrap();
+        db.add("np_lt", &[d(-60, 900.0), d(-2, 10.0), d(-1, 20.0)])
+            .unwrap();
+        let validator = validator(args, &quarantine, &db);
+        let res = validator
+            .filter(vec![d(0, 300.0), d(1, 15.0)])
+            .await
+            .unwrap();
+        assert_eq!(res, vec![d(1, 15.0)]);
+        // another series starts with its own history
+        let other = MemoryStorage::new();
+        other
+            .create("np_lt", &Labels::price("EE", RES_HOUR, STAT_RAW))
+            .unwrap();
+        let validator = Validator::new(
+            validator.args.clone(),
+            quarantine,
+            Arc::new(other.series("np_lt")),
+        );
+        let res = validator.filter(vec![d(0, 300.0)]).await.unwrap();
+        assert_eq!(res, vec![d(0, 300.0)]);
+    }
+}
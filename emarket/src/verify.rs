@@ -0,0 +1,336 @@
+use std::{collections::BTreeMap, error::Error, path::PathBuf};
+
+use chrono::{Duration, NaiveDateTime};
+use emarket::{
+    data::{average, Aggregator, DBSaver, Data, Loader},
+    fix_missing_hours,
+    series::{Labels, RES_DAY, RES_MONTH, STAT_AVG},
+    utils::{parse_time, to_time},
+    TN_DAY, TN_HOUR, TN_MONTH,
+};
+use serde::Serialize;
+
+use crate::{
+    aggregator::{time_day, time_month, AggregatorByDate},
+    redis::RetentionOptions,
+    store::Storage,
+};
+
+/// days of one source request, as in the import
+const SOURCE_DAYS: i64 = 7;
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+    /// Verify points from: 2024-01-01, RFC 3339 time or unix millis
+    #[arg(long, value_parser = parse_time)]
+    pub from: NaiveDateTime,
+    /// Verify points before this time
+    #[arg(long, value_parser = parse_time)]
+    pub to: NaiveDateTime,
+    /// Recorded EntSOE XML document or a directory of them, EntSOE is queried if not set
+    #[arg(long)]
+    pub source: Option<PathBuf>,
+    /// Save the source values of missing and different points and recalculate wrong averages
+    #[arg(long, default_value_t = false)]
+    pub repair: bool,
+    /// Max difference of equal prices
+    #[arg(long, default_value_t = 1e-6)]
+    pub tolerance: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffKind {
+    /// expected but not stored
+    Missing,
+    /// stored but not expected
+    Extra,
+    Different,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diff {
+    pub kind: DiffKind,
+    pub at: i64,
+    pub stored: Option<f64>,
+    pub expected: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SeriesCheck {
+    pub name: String,
+    /// compared timestamps
+    pub compared: usize,
+    pub diffs: Vec<Diff>,
+    /// points saved or buckets recalculated by the repair
+    pub repaired: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VerifyReport {
+    pub from: i64,
+    pub to: i64,
+    pub source: String,
+    /// no differences were found
+    pub ok: bool,
+    pub series: Vec<SeriesCheck>,
+}
+
+/// diffs the stored points with the expected ones point by point
+pub fn compare(name: &str, stored: &[Data], expected: &[Data], tolerance: f64) -> SeriesCheck {
+    let mut all: BTreeMap<NaiveDateTime, (Option<f64>, Option<f64>)> = BTreeMap::new();
+    for d in stored {
+        all.entry(d.at).or_default().0 = Some(d.price);
+    }
+    for d in expected {
+        all.entry(d.at).or_default().1 = Some(d.price);
+    }
+    let diffs = all
+        .iter()
+        .filter_map(|(at, (stored, expected))| {
+            let kind = match (stored, expected) {
+                (Some(s), Some(e)) if (s - e).abs() <= tolerance => return None,
+                (Some(_), Some(_)) => DiffKind::Different,
+                (None, _) => DiffKind::Missing,
+                (_, None) => DiffKind::Extra,
+            };
+            Some(Diff {
+                kind,
+                at: at.and_utc().timestamp_millis(),
+                stored: *stored,
+                expected: *expected,
+            })
+        })
+        .collect();
+    SeriesCheck {
+        name: name.to_string(),
+        compared: all.len(),
+        diffs,
+        repaired: 0,
+    }
+}
+
+/// Compares the hourly prices of [from, to) with the source and the day and month
+/// averages of the touched buckets with the stored hourly prices, optionally repairs them
+pub async fn verify(
+    storage: &Storage,
+    loader: &dyn Loader,
+    labels: &Labels,
+    args: &VerifyArgs,
+    retention: impl Fn(&str) -> RetentionOptions,
+) -> Result<Vec<SeriesCheck>, Box<dyn Error>> {
+    let (from, to, tolerance) = (args.from, args.to, args.tolerance);
+    let mut source = BTreeMap::new();
+    let mut at = from;
+    while at < to {
+        let next = std::cmp::min(at + Duration::days(SOURCE_DAYS), to);
+        for d in loader.retrieve(at, next).await? {
+            if d.at >= from && d.at < to {
+                source.insert(d.at, d);
+            }
+        }
+        at = next;
+    }
+    let source: Vec<Data> = source.into_values().collect();
+    log::info!("got {} source points", source.len());
+    // compressed curves (A03) leave out repeated prices, the import forward fills them
+    let source = fix_missing_hours(&source);
+
+    let hours = storage.series(TN_HOUR, labels, retention(TN_HOUR)).await?;
+    let mut check = compare(TN_HOUR, &hours.load(from, to).await?, &source, tolerance);
+    if args.repair {
+        let expected: BTreeMap<i64, f64> = source
+            .iter()
+            .map(|d| (d.at.and_utc().timestamp_millis(), d.price))
+            .collect();
+        let fix: Vec<Data> = check
+            .diffs
+            .iter()
+            .filter(|d| d.kind != DiffKind::Extra)
+            .filter_map(|d| {
+                Some(Data {
+                    at: to_time(d.at as u64),
+                    price: *expected.get(&d.at)?,
+                })
+            })
+            .collect();
+        check.repaired = hours.save_bulk(&fix).await?;
+    }
+    let mut res = vec![check];
+
+    // whole months, so days and months are checked over complete buckets
+    let (from, to) = (
+        time_month(from).0,
+        time_month(to - Duration::milliseconds(1)).1,
+    );
+    let hourly = hours.load(from, to).await?;
+    for (name, ts_name, resolution, time_func) in [
+        ("day", TN_DAY, RES_DAY, time_day as fn(_) -> _),
+        ("month", TN_MONTH, RES_MONTH, time_month),
+    ] {
+        let dest = storage
+            .series(
+                ts_name,
+                &labels.with(resolution, STAT_AVG),
+                retention(ts_name),
+            )
+            .await?;
+        let expected = average(&hourly, |t| time_func(t).0);
+        let mut check = compare(ts_name, &dest.load(from, to).await?, &expected, tolerance);
+        if args.repair {
+            let mut aggregator =
+                AggregatorByDate::new(name, Box::new(hours.clone()), Box::new(dest), time_func)
+                    .await?;
+            for d in check.diffs.iter().filter(|d| d.kind != DiffKind::Extra) {
+                aggregator.recompute(to_time(d.at as u64), None).await?;
+                check.repaired += 1;
+            }
+        }
+        res.push(check);
+    }
+    for check in res.iter() {
+        log::info!(
+            "{}: {} compared, {} differences, {} repaired",
+            check.name,
+            check.compared,
+            check.diffs.len(),
+            check.repaired
+        );
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use emarket::{
+        memory::MemoryStorage,
+        series::{RES_HOUR, STAT_RAW},
+    };
+
+    use super::*;
+
+    fn at(h: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 10)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            + Duration::hours(h)
+    }
+
+    fn d(h: i64, price: f64) -> Data {
+        Data { at: at(h), price }
+    }
+
+    struct Source(Vec<Data>);
+
+    #[async_trait::async_trait]
+    impl Loader for Source {
+        async fn live(&self) -> Result<String, Box<dyn Error>> {
+            Ok("ok".to_string())
+        }
+        async fn retrieve(
+            &self,
+            from: NaiveDateTime,
+            to: NaiveDateTime,
+        ) -> Result<Vec<Data>, Box<dyn Error>> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|d| d.at >= from && d.at < to)
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[test]
+    fn compares_points() {
+        let res = compare(
+            "np_lt",
+            &[d(0, 1.0), d(1, 2.0), d(3, 4.0)],
+            &[d(0, 1.0 + 1e-9), d(1, 3.0), d(2, 3.0)],
+            1e-6,
+        );
+        assert_eq!(res.compared, 4);
+        let kinds: Vec<_> = res.diffs.iter().map(|d| d.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![DiffKind::Different, DiffKind::Missing, DiffKind::Extra]
+        );
+        assert_eq!(res.diffs[0].stored, Some(2.0));
+        assert_eq!(res.diffs[0].expected, Some(3.0));
+    }
+
+    #[tokio::test]
+    async fn verifies_and_repairs() {
+        let db = MemoryStorage::new();
+        let storage = Storage::Memory(db.clone());
+        let labels = Labels::price("LT", RES_HOUR, STAT_RAW);
+        let retention = |_: &str| RetentionOptions::default();
+        storage
+            .series(TN_HOUR, &labels, retention(TN_HOUR))
+            .await
+            .unwrap();
+        db.add(TN_HOUR, &[d(0, 1.0), d(1, 5.0), d(3, 7.0)]).unwrap();
+        let source = Source(vec![d(0, 1.0), d(1, 2.0), d(2, 3.0)]);
+
+        let args = |repair| VerifyArgs {
+            from: at(0),
+            to: at(3),
+            source: None,
+            repair,
+            tolerance: 1e-6,
+        };
+        let res = verify(&storage, &source, &labels, &args(false), retention)
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].diffs.len(), 2);
+        // no averages were calculated yet
+        assert!(res[1].diffs.iter().all(|d| d.kind == DiffKind::Missing));
+        assert_eq!(res[2].diffs.len(), 1);
+
+        let res = verify(&storage, &source, &labels, &args(true), retention)
+            .await
+            .unwrap();
+        assert_eq!(res[0].repaired, 2);
+        assert_eq!(res[2].repaired, 1);
+
+        let res = verify(&storage, &source, &labels, &args(false), retention)
+            .await
+            .unwrap();
+        assert!(res.iter().all(|c| c.diffs.is_empty()));
+        let months = db.series(TN_MONTH).load(at(-300), at(300)).await.unwrap();
+        assert_eq!(months.len(), 1);
+        assert_eq!(months[0].price, (1.0 + 2.0 + 3.0 + 7.0) / 4.0);
+    }
+
+    #[tokio::test]
+    async fn verifies_compressed_curve() {
+        let db = MemoryStorage::new();
+        let storage = Storage::Memory(db.clone());
+        let labels = Labels::price("LT", RES_HOUR, STAT_RAW);
+        let retention = |_: &str| RetentionOptions::default();
+        storage
+            .series(TN_HOUR, &labels, retention(TN_HOUR))
+            .await
+            .unwrap();
+        // stored forward filled
+        db.add(TN_HOUR, &[d(0, 1.0), d(1, 1.0), d(2, 1.0), d(3, 2.0)])
+            .unwrap();
+        // an A03 curve has the positions where the price changes only
+        let source = Source(vec![d(0, 1.0), d(3, 2.0)]);
+        let args = VerifyArgs {
+            from: at(0),
+            to: at(4),
+            source: None,
+            repair: false,
+            tolerance: 1e-6,
+        };
+        let res = verify(&storage, &source, &labels, &args, retention)
+            .await
+            .unwrap();
+        assert_eq!(res[0].compared, 4);
+        assert!(res[0].diffs.is_empty());
+    }
+}
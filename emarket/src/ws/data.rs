@@ -1,9 +1,427 @@
-use crate::redis::RedisClient;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono::{DateTime, Duration, NaiveDateTime};
+use emarket::bill::{Cost, Offer, OfferCost};
+use emarket::data::{average, Data};
+use emarket::memory::MemoryStorage;
+use emarket::quality::{self, DayQuality, Quality};
+use emarket::rates::{self, Rates};
+use emarket::revisions::{self, Revision};
+use emarket::schedule::Window;
+use emarket::series::{bucket, price_filter, quality_filter, Labels, RES_HOUR, STAT_RAW};
+use emarket::storage::{SeriesInfo, SeriesReader};
+use emarket::tariff::{Plan, Tariffs};
+use emarket::units::{PriceUnit, CURRENCY_EUR};
+use emarket::utils::{time_day_vilnius, time_month_vilnius};
 use serde::Serialize;
 use thiserror::Error;
 
+/// series of the view built by [`Service::as_of`]
+pub const VIEW_HOUR: &str = "hour";
+pub const VIEW_DAY: &str = "day";
+pub const VIEW_MONTH: &str = "month";
+/// max days of one `/quality` call
+const QUALITY_MAX_DAYS: i64 = 400;
+/// max days of a `/prices` or `/summary` call with `as_of`
+pub const AS_OF_MAX_DAYS: i64 = 400;
+/// how long a resolved series name is used before the labels are queried again
+const SERIES_TTL: std::time::Duration = std::time::Duration::from_secs(300);
+/// how long the loaded rates of a currency are used, ECB publishes once a day
+const RATES_TTL: std::time::Duration = std::time::Duration::from_secs(600);
+
 pub struct Service {
-    pub redis: RedisClient,
+    pub db: Arc<dyn SeriesReader>,
+    /// zone label of the served series
+    pub zone: String,
+    /// series names resolved by label filters and the resolve time
+    series: Mutex<HashMap<String, (String, Instant)>>,
+    /// first timestamps of the series and the time they were read
+    firsts: Mutex<HashMap<String, (Option<u64>, Instant)>>,
+    /// loaded rates by currency and the load time
+    rates: Mutex<HashMap<String, (Arc<Rates>, Instant)>>,
+    /// retail tariffs served by `tariff=<id>`
+    tariffs: Tariffs,
+    /// fixed price offers compared by `/cost`
+    offers: Vec<Offer>,
+}
+
+impl Service {
+    pub fn new(db: Arc<dyn SeriesReader>, zone: &str) -> Service {
+        Service {
+            db,
+            zone: zone.to_string(),
+            series: Mutex::new(HashMap::new()),
+            firsts: Mutex::new(HashMap::new()),
+            rates: Mutex::new(HashMap::new()),
+            tariffs: Tariffs::default(),
+            offers: vec![],
+        }
+    }
+
+    pub fn with_tariffs(self, tariffs: Tariffs) -> Service {
+        Service { tariffs, ..self }
+    }
+
+    pub fn with_offers(self, offers: Vec<Offer>) -> Service {
+        Service { offers, ..self }
+    }
+
+    pub fn offers(&self) -> &[Offer] {
+        &self.offers
+    }
+
+    /// resolves the series name by labels, fails if several series match
+    pub async fn find_series(&self, filter: &[String]) -> Result<String, Box<dyn Error>> {
+        let key = filter.join(" ");
+        if let Some((res, at)) = self.series.lock().map_err(|e| e.to_string())?.get(&key) {
+            if at.elapsed() < SERIES_TTL {
+                return Ok(res.clone());
+            }
+        }
+        let list = self.db.query_index(filter).await?;
+        let res = match list.as_slice() {
+            [res] => res.clone(),
+            [] => return Err(format!("no series for '{key}'").into()),
+            _ => return Err(format!("several series for '{key}': {list:?}").into()),
+        };
+        tracing::debug!(series = res, "resolved");
+        self.series
+            .lock()
+            .map_err(|e| e.to_string())?
+            .insert(key, (res.clone(), Instant::now()));
+        Ok(res)
+    }
+
+    /// drops the cached resolves to `ts_name`, they are queried again on the next call
+    fn forget_series(&self, ts_name: &str) {
+        if let Ok(mut series) = self.series.lock() {
+            series.retain(|_, (name, _)| name != ts_name);
+        }
+    }
+
+    /// lists series matching the filter with their labels and time ranges
+    pub async fn list_series(&self, filter: &[String]) -> Result<Vec<SeriesInfo>, Box<dyn Error>> {
+        let names = self.db.query_index(filter).await?;
+        let mut res = Vec::with_capacity(names.len());
+        for name in names {
+            res.push(self.db.info(&name).await?);
+        }
+        Ok(res)
+    }
+
+    pub async fn load(
+        &self,
+        ts_name: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<Vec<MarketData>, Box<dyn Error>> {
+        let res = self.db.load(ts_name, from, to).await.inspect_err(|_| {
+            // the series may be gone
+            self.forget_series(ts_name)
+        })?;
+        Ok(res.iter().map(MarketData::from).collect())
+    }
+
+    /// revision log of the raw hourly prices in [from, to)
+    pub async fn revisions(&self, from: Option<i64>, to: Option<i64>) -> ApiResult<Vec<Revision>> {
+        let ts_name = self.price_series(RES_HOUR, STAT_RAW).await?;
+        self.db
+            .revisions(&ts_name, from, to)
+            .await
+            .map_err(|e| ApiError::Server(e.to_string()))
+    }
+
+    /// quality flags of the hourly prices in [from, to)
+    pub async fn quality(&self, from: Option<i64>, to: Option<i64>) -> ApiResult<Vec<Data>> {
+        let ts_name = self
+            .find_series(&quality_filter(&self.zone, RES_HOUR))
+            .await
+            .map_err(|e| ApiError::Server(e.to_string()))?;
+        self.db
+            .load(&ts_name, from, to)
+            .await
+            .map_err(|e| ApiError::Server(e.to_string()))
+    }
+
+    /// point counts by quality of the Vilnius days touching [from, to)
+    pub async fn quality_days(&self, from: i64, to: i64) -> ApiResult<Vec<DayQuality>> {
+        let (from, to) = (to_time(from)?, to_time(to)?);
+        if from >= to || to - from > Duration::days(QUALITY_MAX_DAYS) {
+            return Err(ApiError::BadRequest(
+                "wrong range".to_string(),
+                format!("from < to, at most {QUALITY_MAX_DAYS} days"),
+            ));
+        }
+        let mut days = vec![];
+        let mut at = time_day_vilnius(from, 0);
+        while at < to {
+            let next = time_day_vilnius(at, 1);
+            days.push((at, next));
+            at = next;
+        }
+        let (from, to) = (millis(days[0].0), millis(at));
+        let ts_name = self.price_series(RES_HOUR, STAT_RAW).await?;
+        let data = self
+            .db
+            .load(&ts_name, Some(from), Some(to))
+            .await
+            .map_err(|e| ApiError::Server(e.to_string()))?;
+        let flags = self.quality(Some(from), Some(to)).await?;
+        Ok(quality::summarize(&days, &data, &flags, Duration::hours(1)))
+    }
+
+    /// rebuilds hourly prices with their day and month averages as known at `as_of`,
+    /// [from, to) is widened to whole months so the averages are complete.
+    /// `to` defaults to the day-ahead prices of `as_of`, `from` to [`AS_OF_MAX_DAYS`] before `to`
+    pub async fn as_of(
+        &self,
+        as_of: i64,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> ApiResult<MemoryStorage> {
+        let as_of = to_time(as_of)?;
+        let to = match to {
+            Some(v) => to_time(v)?,
+            None => as_of + Duration::days(2),
+        };
+        let from = match from {
+            Some(v) => to_time(v)?,
+            None => to - Duration::days(AS_OF_MAX_DAYS),
+        };
+        if from >= to || to - from > Duration::days(AS_OF_MAX_DAYS) {
+            return Err(ApiError::BadRequest(
+                "wrong range".to_string(),
+                format!("from < to, at most {AS_OF_MAX_DAYS} days with as_of"),
+            ));
+        }
+        let from = millis(time_month_vilnius(from, 0));
+        let to = millis(time_month_vilnius(to - Duration::milliseconds(1), 1));
+        let list = self.revisions(Some(from), Some(to)).await?;
+        let hours = revisions::as_of(&list, as_of);
+        tracing::debug!(revisions = list.len(), hours = hours.len(), "as of view");
+        let res = MemoryStorage::new();
+        let add = |name: &str, data: &[Data]| {
+            res.add(name, data)
+                .map_err(|e| ApiError::Server(e.to_string()))
+        };
+        add(VIEW_DAY, &average(&hours, |at| time_day_vilnius(at, 0)))?;
+        add(VIEW_MONTH, &average(&hours, |at| time_month_vilnius(at, 0)))?;
+        add(VIEW_HOUR, &hours)?;
+        Ok(res)
+    }
+
+    /// converter of the zone prices to `unit` and `currency`, EUR prices are kept if no currency is asked
+    pub async fn converter(
+        &self,
+        unit: Option<PriceUnit>,
+        currency: Option<&str>,
+    ) -> ApiResult<Converter> {
+        let factor = self.unit_factor(unit).await?;
+        Ok(Converter::new(factor, self.rates(currency).await?))
+    }
+
+    /// converter of the zone prices to the retail prices of the tariff in EUR/kWh or ct/kWh of `currency`
+    pub async fn retail(
+        &self,
+        tariff: &str,
+        unit: Option<PriceUnit>,
+        currency: Option<&str>,
+    ) -> ApiResult<RetailConverter<'_>> {
+        let plan = self.tariffs.get(tariff).ok_or_else(|| {
+            ApiError::BadRequest(
+                format!("unknown tariff: {tariff}"),
+                format!("expected one of {:?}", self.tariffs.ids()),
+            )
+        })?;
+        let factor = match unit {
+            None | Some(PriceUnit::EurKwh) => 1.0,
+            Some(PriceUnit::CtKwh) => 100.0,
+            Some(PriceUnit::EurMwh) => {
+                return Err(ApiError::BadRequest(
+                    "wrong unit".to_string(),
+                    "retail prices are per kWh: eur_kwh or ct_kwh".to_string(),
+                ))
+            }
+        };
+        Ok(RetailConverter {
+            plan,
+            spot: self.unit_factor(Some(PriceUnit::EurKwh)).await?,
+            out: Converter::new(factor, self.rates(currency).await?),
+        })
+    }
+
+    /// daily rates of `currency`, none for EUR, the rates are loaded again after [`RATES_TTL`]
+    async fn rates(&self, currency: Option<&str>) -> ApiResult<Option<Arc<Rates>>> {
+        let Some(currency) = currency.filter(|c| !c.eq_ignore_ascii_case(CURRENCY_EUR)) else {
+            return Ok(None);
+        };
+        let key = currency.to_uppercase();
+        let cached = |rates: &Mutex<HashMap<String, (Arc<Rates>, Instant)>>| {
+            rates
+                .lock()
+                .map_err(|e| ApiError::Server(e.to_string()))
+                .map(|m| {
+                    m.get(&key)
+                        .filter(|(_, at)| at.elapsed() < RATES_TTL)
+                        .map(|(r, _)| r.clone())
+                })
+        };
+        if let Some(res) = cached(&self.rates)? {
+            return Ok(Some(res));
+        }
+        let ts_name = self.price_series(RES_HOUR, STAT_RAW).await?;
+        let info = self
+            .db
+            .info(&ts_name)
+            .await
+            .map_err(|e| ApiError::Server(e.to_string()))?;
+        let labels = Labels::try_from(&info.labels).map_err(ApiError::Server)?;
+        if !labels.currency.eq_ignore_ascii_case(CURRENCY_EUR) {
+            return Err(ApiError::BadRequest(
+                format!("wrong currency: {currency}"),
+                format!("can't convert {} prices", labels.currency),
+            ));
+        }
+        let rates_name = self
+            .find_series(&rates::rate_filter(currency))
+            .await
+            .map_err(|e| {
+                ApiError::BadRequest(format!("unknown currency: {currency}"), e.to_string())
+            })?;
+        let rates = self
+            .db
+            .load(&rates_name, None, None)
+            .await
+            .map_err(|e| ApiError::Server(e.to_string()))?;
+        let res = Arc::new(Rates::new(&rates));
+        self.rates
+            .lock()
+            .map_err(|e| ApiError::Server(e.to_string()))?
+            .insert(key, (res.clone(), Instant::now()));
+        Ok(Some(res))
+    }
+
+    /// multiplier converting the zone prices to `unit`, 1 if no unit is asked
+    async fn unit_factor(&self, unit: Option<PriceUnit>) -> ApiResult<f64> {
+        let Some(unit) = unit else {
+            return Ok(1.0);
+        };
+        let ts_name = self.price_series(RES_HOUR, STAT_RAW).await?;
+        let info = self
+            .db
+            .info(&ts_name)
+            .await
+            .map_err(|e| ApiError::Server(e.to_string()))?;
+        let labels = Labels::try_from(&info.labels).map_err(ApiError::Server)?;
+        unit.factor(&labels)
+            .map_err(|e| ApiError::BadRequest("wrong unit".to_string(), e))
+    }
+
+    pub async fn price_series(&self, resolution: &str, statistic: &str) -> ApiResult<String> {
+        self.find_series(&price_filter(&self.zone, resolution, statistic))
+            .await
+            .map_err(|e| ApiError::Server(e.to_string()))
+    }
+
+    /// picks the finest series (`candidates` go from the finest) still keeping data from `from`
+    pub async fn price_series_for(
+        &self,
+        candidates: &[(&str, &str)],
+        from: Option<i64>,
+    ) -> ApiResult<String> {
+        let mut list = Vec::with_capacity(candidates.len());
+        for (resolution, statistic) in candidates {
+            let name = match self
+                .find_series(&price_filter(&self.zone, resolution, statistic))
+                .await
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    tracing::debug!(resolution, statistic, "skip: {e}");
+                    continue;
+                }
+            };
+            let first = self.first(&name).await?;
+            let bucket = bucket(resolution).map_or(0, |v| v.as_millis() as u64);
+            list.push(Tier {
+                name,
+                bucket,
+                first,
+            });
+        }
+        select_series(list, from)
+            .ok_or_else(|| ApiError::Server(format!("no series for {candidates:?}")))
+    }
+
+    /// first timestamp of the series, kept for [`SERIES_TTL`]
+    async fn first(&self, ts_name: &str) -> ApiResult<Option<u64>> {
+        let lock_err = |e: std::sync::PoisonError<_>| ApiError::Server(e.to_string());
+        if let Some((res, at)) = self.firsts.lock().map_err(lock_err)?.get(ts_name) {
+            if at.elapsed() < SERIES_TTL {
+                return Ok(*res);
+            }
+        }
+        let res = self
+            .db
+            .info(ts_name)
+            .await
+            .map_err(|e| {
+                self.forget_series(ts_name);
+                ApiError::Server(e.to_string())
+            })?
+            .first;
+        self.firsts
+            .lock()
+            .map_err(lock_err)?
+            .insert(ts_name.to_string(), (res, Instant::now()));
+        Ok(res)
+    }
+}
+
+/// parses the `unit` query parameter
+pub fn parse_unit(unit: Option<&str>) -> ApiResult<Option<PriceUnit>> {
+    unit.map(|v| {
+        v.parse()
+            .map_err(|e| ApiError::BadRequest(format!("wrong unit: {v}"), e))
+    })
+    .transpose()
+}
+
+/// time of the unix millis, a bad request if out of range
+pub fn to_time(millis: i64) -> ApiResult<NaiveDateTime> {
+    DateTime::from_timestamp_millis(millis)
+        .map(|v| v.naive_utc())
+        .ok_or_else(|| ApiError::BadRequest("wrong time".to_string(), millis.to_string()))
+}
+
+fn millis(at: NaiveDateTime) -> i64 {
+    at.and_utc().timestamp_millis()
+}
+
+/// series of a resolution tier with its bucket in millis
+#[derive(Debug, Clone)]
+struct Tier {
+    name: String,
+    bucket: u64,
+    first: Option<u64>,
+}
+
+/// selects the finest series (`list` goes from the finest) having data from `from` or as old data
+/// as the coarser ones, a coarser series may start up to its bucket earlier
+fn select_series(list: Vec<Tier>, from: Option<i64>) -> Option<String> {
+    let res = list.iter().enumerate().find(|(i, t)| {
+        t.first.is_some_and(|f| {
+            from.is_some_and(|from| f as i64 <= from)
+                || list[i + 1..]
+                    .iter()
+                    .all(|c| c.first.is_none_or(|cf| f <= cf + c.bucket))
+        })
+    });
+    res.map(|(_, t)| t.name.clone())
+        .or_else(|| list.into_iter().next().map(|t| t.name))
 }
 
 #[derive(Debug, Error)]
@@ -29,6 +447,156 @@ pub struct LiveResponse {
 pub struct MarketData {
     pub at: u64,
     pub price: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<Quality>,
+    /// retail price components, `price` is their sum
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub components: Option<Components>,
+}
+
+impl From<&Data> for MarketData {
+    fn from(d: &Data) -> Self {
+        MarketData {
+            at: d.at.and_utc().timestamp_millis() as u64,
+            price: d.price,
+            quality: None,
+            components: None,
+        }
+    }
+}
+
+/// Retail price components, VAT is the amount added
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Components {
+    /// grid zone, not set for averages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    pub energy: f64,
+    pub margin: f64,
+    pub grid: f64,
+    pub pso: f64,
+    pub vat: f64,
+}
+
+/// Calculates the retail prices of a tariff
+pub struct RetailConverter<'a> {
+    plan: Plan<'a>,
+    /// multiplier converting the zone prices to EUR/kWh
+    spot: f64,
+    /// converter of EUR/kWh to the asked unit and currency
+    out: Converter,
+}
+
+impl RetailConverter<'_> {
+    pub fn price(&self, at: NaiveDateTime, price: f64) -> ApiResult<MarketData> {
+        let r = self
+            .plan
+            .price(at, price * self.spot)
+            .map_err(|e| ApiError::BadRequest("wrong tariff".to_string(), e))?;
+        let conv = |v: f64| self.out.convert(at, v);
+        Ok(MarketData {
+            at: millis(at) as u64,
+            price: conv(r.total)?,
+            quality: None,
+            components: Some(Components {
+                zone: Some(r.zone),
+                energy: conv(r.energy)?,
+                margin: conv(r.margin)?,
+                grid: conv(r.grid)?,
+                pso: conv(r.pso)?,
+                vat: conv(r.vat)?,
+            }),
+        })
+    }
+
+    pub fn list(&self, list: &[Data]) -> ApiResult<Vec<MarketData>> {
+        list.iter().map(|d| self.price(d.at, d.price)).collect()
+    }
+}
+
+/// averages of the retail prices and their components by `bucket`
+pub fn average_retail(
+    list: &[MarketData],
+    bucket: impl Fn(NaiveDateTime) -> NaiveDateTime,
+) -> Vec<MarketData> {
+    let mut buckets: BTreeMap<NaiveDateTime, Vec<&MarketData>> = BTreeMap::new();
+    for d in list {
+        buckets
+            .entry(bucket(emarket::utils::to_time(d.at)))
+            .or_default()
+            .push(d);
+    }
+    buckets
+        .into_iter()
+        .map(|(at, list)| {
+            let avg = |f: &dyn Fn(&MarketData) -> f64| {
+                list.iter().map(|d| f(d)).sum::<f64>() / list.len() as f64
+            };
+            let part = |f: fn(&Components) -> f64| {
+                avg(&|d: &MarketData| d.components.as_ref().map_or(0.0, f))
+            };
+            MarketData {
+                at: millis(at) as u64,
+                price: avg(&|d: &MarketData| d.price),
+                quality: None,
+                components: Some(Components {
+                    zone: None,
+                    energy: part(|c| c.energy),
+                    margin: part(|c| c.margin),
+                    grid: part(|c| c.grid),
+                    pso: part(|c| c.pso),
+                    vat: part(|c| c.vat),
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Converts the stored prices to the asked unit and currency
+pub struct Converter {
+    factor: f64,
+    /// rates of the asked currency, none for EUR
+    rates: Option<Arc<Rates>>,
+}
+
+impl Converter {
+    pub fn new(factor: f64, rates: Option<Arc<Rates>>) -> Converter {
+        Converter { factor, rates }
+    }
+
+    /// true if the prices change with the delivery day rate
+    pub fn has_rates(&self) -> bool {
+        self.rates.is_some()
+    }
+
+    /// converts the price at `at`
+    pub fn convert(&self, at: NaiveDateTime, price: f64) -> ApiResult<f64> {
+        let price = price * self.factor;
+        match &self.rates {
+            Some(rates) => rates
+                .convert(at, price)
+                .map_err(|e| ApiError::BadRequest("no currency rate".to_string(), e)),
+            None => Ok(price),
+        }
+    }
+
+    pub fn list(&self, list: &mut [MarketData]) -> ApiResult<()> {
+        if self.factor == 1.0 && self.rates.is_none() {
+            return Ok(());
+        }
+        for d in list.iter_mut() {
+            d.price = self.convert(emarket::utils::to_time(d.at), d.price)?;
+        }
+        Ok(())
+    }
+}
+
+/// sets the quality of the points having a flag
+pub fn with_quality(list: &mut [MarketData], flags: &[Data]) {
+    let flags = quality::by_time(flags);
+    for d in list.iter_mut() {
+        d.quality = flags.get(&emarket::utils::to_time(d.at)).copied();
+    }
 }
 
 #[derive(Serialize)]
@@ -50,9 +618,397 @@ pub struct SummaryData {
     pub last_7_avg: Option<f64>,
 }
 
+/// Spot cost of an uploaded consumption
+#[derive(Serialize)]
+pub struct CostData {
+    pub from: i64,
+    pub to: i64,
+    /// minutes of the consumption intervals
+    pub resolution: i64,
+    pub total: Cost,
+    pub days: Vec<Cost>,
+    pub months: Vec<Cost>,
+    pub offers: Vec<OfferCost>,
+}
+
+/// Cheapest run and the run starting now
+#[derive(Serialize)]
+pub struct WindowData {
+    #[serde(flatten)]
+    pub best: Window,
+    pub now: Window,
+    /// cost of starting now minus the cheapest cost
+    pub savings: f64,
+}
+
+/// Selected slots with their on/off schedule
+#[derive(Serialize)]
+pub struct SlotsData {
+    /// start of the first slot of `schedule`
+    pub from: i64,
+    pub slot_minutes: i64,
+    /// a char per slot from `from`: `1` on, `0` off
+    pub schedule: String,
+    pub runs: Vec<Run>,
+    /// selected slots with their prices
+    pub slots: Vec<MarketData>,
+    pub kwh: f64,
+    pub cost: f64,
+    /// average price of the selected slots
+    pub price: f64,
+}
+
+/// On time [start, end)
+#[derive(Serialize)]
+pub struct Run {
+    pub start: i64,
+    pub end: i64,
+}
+
 #[derive(Serialize)]
 pub struct NowData {
     pub at: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub price: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub components: Option<Components>,
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use chrono::{Duration, NaiveDate};
+    use emarket::series::Labels;
+
+    use super::*;
+
+    const H: u64 = 3_600_000;
+
+    fn tier(name: &str, bucket: u64, first: Option<u64>) -> Tier {
+        Tier {
+            name: name.to_string(),
+            bucket,
+            first,
+        }
+    }
+
+    fn list() -> Vec<Tier> {
+        vec![
+            tier("raw", H, Some(100 * H)),
+            tier("hourly", H, Some(10 * H)),
+            tier("empty", H, None),
+        ]
+    }
+
+    fn ms(h: u64) -> Option<i64> {
+        Some((h * H) as i64)
+    }
+
+    #[test]
+    fn selects_finest() {
+        assert_eq!(select_series(list(), ms(100)), Some("raw".to_string()));
+        assert_eq!(select_series(list(), ms(200)), Some("raw".to_string()));
+    }
+
+    #[test]
+    fn selects_available() {
+        assert_eq!(select_series(list(), ms(50)), Some("hourly".to_string()));
+        assert_eq!(select_series(list(), ms(5)), Some("hourly".to_string()));
+        assert_eq!(select_series(list(), None), Some("hourly".to_string()));
+    }
+
+    #[test]
+    fn selects_coarser_tiers() {
+        let list = || {
+            vec![
+                tier("raw", H, Some(1000 * H)),
+                tier("hourly", H, Some(500 * H)),
+                tier("days", 24 * H, Some(22 * H)),
+                tier("months", 31 * 24 * H, Some(0)),
+            ]
+        };
+        assert_eq!(select_series(list(), ms(600)), Some("hourly".to_string()));
+        assert_eq!(select_series(list(), ms(100)), Some("days".to_string()));
+        // the months start before the days, still within the first month
+        assert_eq!(select_series(list(), ms(10)), Some("days".to_string()));
+        assert_eq!(select_series(list(), None), Some("days".to_string()));
+        let mut full = list();
+        full[0].first = Some(22 * H);
+        assert_eq!(select_series(full.clone(), None), Some("raw".to_string()));
+        assert_eq!(select_series(full, ms(1)), Some("raw".to_string()));
+    }
+
+    #[test]
+    fn selects_empty() {
+        assert_eq!(
+            select_series(vec![tier("empty", H, None)], ms(5)),
+            Some("empty".to_string())
+        );
+        assert_eq!(select_series(vec![], ms(5)), None);
+    }
+
+    #[tokio::test]
+    async fn resolves_one_series() {
+        let storage = MemoryStorage::new();
+        let labels = Labels::price("LT", RES_HOUR, STAT_RAW);
+        storage.create("np_lt", &labels).unwrap();
+        let storage = Arc::new(storage);
+        let srv = Service::new(storage.clone(), "LT");
+        let filter = price_filter("LT", RES_HOUR, STAT_RAW);
+        assert_eq!(srv.find_series(&filter).await.unwrap(), "np_lt");
+        storage.create("np_lt_copy", &labels).unwrap();
+        // cached till forgotten
+        assert_eq!(srv.find_series(&filter).await.unwrap(), "np_lt");
+        srv.forget_series("np_lt");
+        assert!(srv.find_series(&filter).await.is_err());
+        assert!(srv
+            .find_series(&price_filter("EE", RES_HOUR, STAT_RAW))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn builds_as_of_view() {
+        let at = |h: i64| {
+            NaiveDate::from_ymd_opt(2024, 1, 10)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                + Duration::hours(h)
+        };
+        let rev = |h: i64, price: f64, revision: u32, fetched: i64| Revision {
+            at: at(h),
+            price,
+            revision,
+            published: None,
+            fetched: at(fetched),
+        };
+        let storage = MemoryStorage::new();
+        storage
+            .create("np_lt", &Labels::price("LT", RES_HOUR, STAT_RAW))
+            .unwrap();
+        storage
+            .add_revisions(
+                "np_lt",
+                &[rev(10, 1.0, 1, 0), rev(11, 3.0, 1, 0), rev(10, 5.0, 2, 5)],
+            )
+            .unwrap();
+        let srv = Service::new(Arc::new(storage), "LT");
+        let ms = |h| millis(at(h));
+        let load = |view: MemoryStorage, name: &'static str| async move {
+            view.load(name, None, None)
+                .await
+                .unwrap()
+                .iter()
+                .map(|d| d.price)
+                .collect::<Vec<_>>()
+        };
+        let view = srv.as_of(ms(1), Some(ms(0)), Some(ms(24))).await.unwrap();
+        assert_eq!(load(view.clone(), VIEW_HOUR).await, vec![1.0, 3.0]);
+        assert_eq!(load(view, VIEW_MONTH).await, vec![2.0]);
+        let view = srv.as_of(ms(6), None, None).await.unwrap();
+        assert_eq!(load(view.clone(), VIEW_HOUR).await, vec![5.0, 3.0]);
+        assert_eq!(load(view, VIEW_DAY).await, vec![4.0]);
+        assert_eq!(srv.revisions(None, None).await.unwrap().len(), 3);
+        // the range is bounded, a december range ends before january
+        let view = srv.as_of(ms(6), None, Some(ms(-31 * 24))).await.unwrap();
+        assert!(load(view, VIEW_HOUR).await.is_empty());
+        assert!(srv
+            .as_of(ms(6), Some(ms(-401 * 24)), Some(ms(24)))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn summarizes_quality() {
+        // 2024-01-10 00:00 in Vilnius
+        let at = |h: i64| {
+            NaiveDate::from_ymd_opt(2024, 1, 9)
+                .unwrap()
+                .and_hms_opt(22, 0, 0)
+                .unwrap()
+                + Duration::hours(h)
+        };
+        let d = |h: i64, price: f64| Data { at: at(h), price };
+        let storage = MemoryStorage::new();
+        storage
+            .create("np_lt", &Labels::price("LT", RES_HOUR, STAT_RAW))
+            .unwrap();
+        storage
+            .create("np_lt_q", &Labels::quality("LT", RES_HOUR))
+            .unwrap();
+        storage
+            .add("np_lt", &[d(0, 1.0), d(1, 1.0), d(25, 2.0)])
+            .unwrap();
+        storage
+            .add(
+                "np_lt_q",
+                &[
+                    d(0, Quality::Original.code()),
+                    d(1, Quality::ForwardFilled.code()),
+                ],
+            )
+            .unwrap();
+        let srv = Service::new(Arc::new(storage), "LT");
+        let res = srv
+            .quality_days(millis(at(5)), millis(at(26)))
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].at, at(0));
+        assert_eq!((res[0].original, res[0].filled, res[0].missing), (1, 1, 22));
+        assert_eq!((res[1].points, res[1].unflagged), (1, 1));
+        assert!(srv
+            .quality_days(millis(at(5)), millis(at(5)))
+            .await
+            .is_err());
+
+        let mut list: Vec<MarketData> = [d(0, 1.0), d(1, 1.0), d(2, 1.0)]
+            .iter()
+            .map(MarketData::from)
+            .collect();
+        with_quality(&mut list, &srv.quality(None, None).await.unwrap());
+        let res: Vec<_> = list.iter().map(|d| d.quality).collect();
+        assert_eq!(
+            res,
+            vec![Some(Quality::Original), Some(Quality::ForwardFilled), None]
+        );
+    }
+
+    #[tokio::test]
+    async fn converts_units() {
+        let storage = MemoryStorage::new();
+        storage
+            .create(
+                "np_lt",
+                &Labels::price("LT", RES_HOUR, STAT_RAW).with_units("EUR", "MWh"),
+            )
+            .unwrap();
+        let srv = Service::new(Arc::new(storage), "LT");
+        let at = to_time(0).unwrap();
+        let conv = srv.converter(None, None).await.unwrap();
+        assert!(!conv.has_rates());
+        assert_eq!(conv.convert(at, 120.0).unwrap(), 120.0);
+        let unit = parse_unit(Some("ct_kwh")).unwrap();
+        let conv = srv.converter(unit, Some("eur")).await.unwrap();
+        assert_eq!(conv.convert(at, 120.0).unwrap(), 12.0);
+        assert!(parse_unit(Some("usd")).is_err());
+        let mut list = vec![MarketData::from(&Data { at, price: 120.0 })];
+        let conv = srv.converter(parse_unit(Some("eur_kwh")).unwrap(), None);
+        conv.await.unwrap().list(&mut list).unwrap();
+        assert_eq!(list[0].price, 0.12);
+    }
+
+    #[tokio::test]
+    async fn converts_currency() {
+        let day = |d: u32, h: u32| {
+            NaiveDate::from_ymd_opt(2024, 1, d)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        let storage = MemoryStorage::new();
+        storage
+            .create("np_lt", &Labels::price("LT", RES_HOUR, STAT_RAW))
+            .unwrap();
+        storage.create("ecb_pln", &rates::labels("PLN")).unwrap();
+        // friday and monday rates
+        storage
+            .add(
+                "ecb_pln",
+                &[
+                    Data {
+                        at: day(5, 0),
+                        price: 4.0,
+                    },
+                    Data {
+                        at: day(8, 0),
+                        price: 5.0,
+                    },
+                ],
+            )
+            .unwrap();
+        let srv = Service::new(Arc::new(storage), "LT");
+        assert!(srv.converter(None, Some("SEK")).await.is_err());
+        let conv = srv
+            .converter(parse_unit(Some("eur_kwh")).unwrap(), Some("pln"))
+            .await
+            .unwrap();
+        assert!(conv.has_rates());
+        // sunday 23:00 UTC is monday in Vilnius
+        let mut list: Vec<MarketData> = [
+            Data {
+                at: day(7, 21),
+                price: 100.0,
+            },
+            Data {
+                at: day(7, 23),
+                price: 100.0,
+            },
+        ]
+        .iter()
+        .map(MarketData::from)
+        .collect();
+        conv.list(&mut list).unwrap();
+        let res: Vec<_> = list.iter().map(|d| d.price).collect();
+        assert_eq!(res, vec![0.4, 0.5]);
+        assert!(conv.convert(day(4, 0), 1.0).is_err());
+    }
+
+    #[tokio::test]
+    async fn calculates_retail() {
+        let at = |h: u32| {
+            NaiveDate::from_ymd_opt(2024, 1, 10)
+                .unwrap()
+                .and_hms_opt(h, 0, 0)
+                .unwrap()
+        };
+        let storage = MemoryStorage::new();
+        storage
+            .create("np_lt", &Labels::price("LT", RES_HOUR, STAT_RAW))
+            .unwrap();
+        let tariffs = Tariffs::from_json(
+            r#"{"tariffs": [{
+                "id": "two",
+                "vat": [{"from": "2024-01-01", "value": 10}],
+                "margin": [{"from": "2024-01-01", "value": 0.01}],
+                "grid": {"zones": [
+                    {"name": "day", "hours": [{"days": "workdays", "from": 7, "to": 23}], "fee": [{"from": "2024-01-01", "value": 0.1}]},
+                    {"name": "night", "fee": [{"from": "2024-01-01", "value": 0.05}]}
+                ]}
+            }]}"#,
+        )
+        .unwrap();
+        let srv = Service::new(Arc::new(storage), "LT").with_tariffs(tariffs);
+        assert!(srv.retail("one", None, None).await.is_err());
+        let mwh = parse_unit(Some("eur_mwh")).unwrap();
+        assert!(srv.retail("two", mwh, None).await.is_err());
+        let ct = parse_unit(Some("ct_kwh")).unwrap();
+        let retail = srv.retail("two", ct, None).await.unwrap();
+        // 12:00 and 02:00 in Vilnius
+        let list = retail
+            .list(&[
+                Data {
+                    at: at(10),
+                    price: 90.0,
+                },
+                Data {
+                    at: at(0),
+                    price: 40.0,
+                },
+            ])
+            .unwrap();
+        let c = list[0].components.clone().unwrap();
+        assert_eq!(c.zone.as_deref(), Some("day"));
+        assert_relative_eq!(c.energy, 9.0);
+        assert_relative_eq!(c.vat, 2.0);
+        assert_relative_eq!(list[0].price, 22.0);
+        assert_relative_eq!(list[1].price, 11.0);
+        let avg = average_retail(&list, |at| time_day_vilnius(at, 0));
+        assert_eq!(avg.len(), 1);
+        assert_relative_eq!(avg[0].price, 16.5);
+        let c = avg[0].components.clone().unwrap();
+        assert_eq!(c.zone, None);
+        assert_relative_eq!(c.grid, 7.5);
+    }
 }
@@ -0,0 +1,138 @@
+use std::{error::Error, f64::consts::PI};
+
+use chrono::{Datelike, Duration, NaiveDateTime, TimeZone, Timelike, Weekday};
+use chrono_tz::Europe::Vilnius;
+use emarket::{
+    data::{average, Data},
+    memory::MemoryStorage,
+    quality,
+    revisions::Revision,
+    series::{Labels, RES_DAY, RES_HOUR, RES_MONTH, STAT_AVG, STAT_RAW},
+    utils::{time_day_vilnius, time_month_vilnius},
+    TN_DAY, TN_HOUR, TN_MONTH, TN_QUALITY,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// fills the storage with synthetic hourly prices for `days` back from `now` and the next day,
+/// plus the day and month averages, the revision log and the quality flags,
+/// returns the number of hourly points
+pub fn fill(
+    storage: &MemoryStorage,
+    zone: &str,
+    now: NaiveDateTime,
+    days: i64,
+) -> Result<usize, Box<dyn Error>> {
+    let hours = generate(time_day_vilnius(now, -days), time_day_vilnius(now, 2), 1);
+    let labels = Labels::price(zone, RES_HOUR, STAT_RAW);
+    storage.create(TN_HOUR, &labels)?;
+    storage.add(TN_HOUR, &hours)?;
+    let revisions = revisions(&hours);
+    storage.add_revisions(TN_HOUR, &revisions)?;
+    storage.create(TN_QUALITY, &Labels::quality(zone, RES_HOUR))?;
+    storage.add(TN_QUALITY, &quality::flags(&hours, &revisions))?;
+    storage.create(TN_DAY, &labels.with(RES_DAY, STAT_AVG))?;
+    storage.add(TN_DAY, &average(&hours, |at| time_day_vilnius(at, 0)))?;
+    storage.create(TN_MONTH, &labels.with(RES_MONTH, STAT_AVG))?;
+    storage.add(TN_MONTH, &average(&hours, |at| time_month_vilnius(at, 0)))?;
+    log::info!("demo data: {} hours from {}", hours.len(), hours[0].at);
+    Ok(hours.len())
+}
+
+/// hourly prices in [from, to), the same seed gives the same curve
+fn generate(from: NaiveDateTime, to: NaiveDateTime, seed: u64) -> Vec<Data> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut res = vec![];
+    let mut at = from;
+    while at < to {
+        let noise = rng.gen_range(-1.0..1.0) * 12.0;
+        let spike = if rng.gen_bool(0.01) {
+            rng.gen_range(100.0..300.0)
+        } else {
+            0.0
+        };
+        let price = price(at) + noise + spike;
+        res.push(Data {
+            at,
+            price: (price * 100.0).round() / 100.0,
+        });
+        at += Duration::hours(1);
+    }
+    res
+}
+
+/// price shape in EUR/MWh: expensive winters, morning and evening peaks,
+/// cheap nights and weekends, solar dips at summer noons
+fn price(at: NaiveDateTime) -> f64 {
+    let local = Vilnius.from_utc_datetime(&at);
+    let h = local.hour() as f64;
+    let year = 2.0 * PI * local.ordinal0() as f64 / 365.0;
+    let season = 90.0 + 40.0 * (year - 0.25).cos();
+    let peak = |center: f64, width: f64| (-(h - center).powi(2) / width).exp();
+    let solar = 70.0 * (year - 1.4).sin().max(0.0);
+    let daily = 35.0 * peak(8.0, 4.0) + 45.0 * peak(19.0, 5.0)
+        - 25.0 * peak(3.0, 6.0)
+        - solar * peak(13.0, 6.0);
+    let week = match local.weekday() {
+        Weekday::Sat | Weekday::Sun => 0.8,
+        _ => 1.0,
+    };
+    (season + daily) * week
+}
+
+/// day-ahead prices as if fetched at 13:00 the day before
+fn revisions(data: &[Data]) -> Vec<Revision> {
+    data.iter()
+        .map(|d| Revision {
+            at: d.at,
+            price: d.price,
+            revision: 1,
+            published: None,
+            fetched: time_day_vilnius(d.at, 0) - Duration::hours(11),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use emarket::{data::DBSaver, series::price_filter, storage::SeriesReader};
+
+    use super::*;
+
+    fn at(day: u32, h: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(h, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn generates_hours() {
+        let res = generate(at(1, 0), at(3, 0), 1);
+        assert_eq!(res.len(), 48);
+        assert_eq!(res[1].at, at(1, 1));
+        assert_eq!(generate(at(1, 0), at(3, 0), 1), res);
+        assert!(res.iter().all(|d| d.price > -100.0 && d.price < 600.0));
+    }
+
+    #[tokio::test]
+    async fn fills_storage() {
+        let storage = MemoryStorage::new();
+        let n = fill(&storage, "LT", at(15, 10), 10).unwrap();
+        assert_eq!(n, 12 * 24);
+        assert_eq!(
+            storage
+                .query_index(&price_filter("LT", RES_DAY, STAT_AVG))
+                .await
+                .unwrap(),
+            vec![TN_DAY]
+        );
+        assert_eq!(storage.info(TN_DAY).await.unwrap().samples, 12);
+        assert_eq!(storage.info(TN_MONTH).await.unwrap().samples, 1);
+        let last = storage.series(TN_HOUR).get_last_time().await.unwrap();
+        assert_eq!(last, Some(at(16, 21)));
+        let list = storage.revisions(TN_HOUR, None, None).await.unwrap();
+        assert_eq!(list.len(), n);
+        assert_eq!(list[0].fetched, at(4, 11));
+    }
+}
@@ -0,0 +1,429 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{self, Query, State},
+    Json,
+};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use emarket::{
+    data::Data,
+    schedule::{self, Constraints, Slot, SLOT_MINUTES},
+    series::{RES_HOUR, STAT_RAW},
+    units::PriceUnit,
+    utils::{parse_duration, to_str_or_none},
+};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use crate::data::{ApiError, ApiResult, MarketData, Run, Service, SlotsData, WindowData};
+
+/// default search range if no deadline is set, the day-ahead prices end earlier
+const DEFAULT_RANGE_HOURS: i64 = 48;
+/// max search range
+const MAX_RANGE_DAYS: i64 = 7;
+/// max slots of a run, the slots of the max range
+const MAX_SLOTS: usize = (MAX_RANGE_DAYS * 24 * 60 / SLOT_MINUTES) as usize;
+
+#[derive(Deserialize)]
+pub struct WindowParams {
+    /// run time as `2h` or `90m`, a multiple of 15 minutes, the profile length if not set
+    duration: Option<String>,
+    /// earliest start, now if not set
+    from: Option<i64>,
+    /// deadline to finish the run by
+    to: Option<i64>,
+    /// kW per 15 minute slot of the run: `2,2,0.5`
+    profile: Option<String>,
+    /// kW of a flat profile, 1 if not set
+    power: Option<f64>,
+    /// ECB currency code, e.g. PLN, EUR if not set
+    currency: Option<String>,
+    /// prices the run by the retail prices of the tariff
+    tariff: Option<String>,
+}
+
+/// cheapest start of a run of `duration` within [from, to] compared with starting at `from`
+pub async fn window(
+    State(srv_wrap): State<Arc<RwLock<Service>>>,
+    Query(params): Query<WindowParams>,
+) -> ApiResult<extract::Json<WindowData>> {
+    tracing::debug!("cheapest window handler");
+    let srv = srv_wrap.read().await;
+    tracing::debug!(
+        from = to_str_or_none(params.from),
+        to = to_str_or_none(params.to),
+        duration = params.duration,
+        "params",
+    );
+    let profile = parse_profile(
+        params.duration.as_deref(),
+        params.profile.as_deref(),
+        params.power,
+    )?;
+    let (from, to) = range(params.from, params.to)?;
+    let slots = slot_prices(
+        &srv,
+        from,
+        to,
+        params.tariff.as_deref(),
+        params.currency.as_deref(),
+    )
+    .await?;
+    let (now, best) = schedule::cheapest_window(&slots, &profile).ok_or_else(|| {
+        ApiError::BadRequest(
+            "no prices for the run".to_string(),
+            format!("{} slots, {} needed", slots.len(), profile.len()),
+        )
+    })?;
+    Ok(Json(WindowData {
+        savings: now.cost - best.cost,
+        best,
+        now,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct SlotsParams {
+    /// total run time as `4h`, a multiple of 15 minutes
+    duration: String,
+    /// window start, now if not set
+    from: Option<i64>,
+    /// window end
+    to: Option<i64>,
+    /// min length of a run
+    min_block: Option<String>,
+    /// max off time between two runs
+    max_gap: Option<String>,
+    /// min run time of every local day fully within the window
+    min_per_day: Option<String>,
+    /// kW while on, 1 if not set
+    power: Option<f64>,
+    /// ECB currency code, e.g. PLN, EUR if not set
+    currency: Option<String>,
+    /// prices the run by the retail prices of the tariff
+    tariff: Option<String>,
+}
+
+/// cheapest slots of `duration` within [from, to) meeting the run constraints
+pub async fn slots(
+    State(srv_wrap): State<Arc<RwLock<Service>>>,
+    Query(params): Query<SlotsParams>,
+) -> ApiResult<extract::Json<SlotsData>> {
+    tracing::debug!("cheapest slots handler");
+    let srv = srv_wrap.read().await;
+    tracing::debug!(
+        from = to_str_or_none(params.from),
+        to = to_str_or_none(params.to),
+        duration = params.duration,
+        "params",
+    );
+    let optional =
+        |what: &str, v: &Option<String>| v.as_deref().map(|v| slot_count(what, v)).transpose();
+    let constraints = Constraints {
+        count: slot_count("duration", &params.duration)?,
+        min_block: optional("min_block", &params.min_block)?.unwrap_or(0),
+        max_gap: optional("max_gap", &params.max_gap)?,
+        min_per_day: optional("min_per_day", &params.min_per_day)?.unwrap_or(0),
+    };
+    let power = params.power.unwrap_or(1.0);
+    if !power.is_finite() || power <= 0.0 {
+        return Err(ApiError::BadRequest(
+            format!("wrong power: {power}"),
+            power.to_string(),
+        ));
+    }
+    let (from, to) = range(params.from, params.to)?;
+    let slots = slot_prices(
+        &srv,
+        from,
+        to,
+        params.tariff.as_deref(),
+        params.currency.as_deref(),
+    )
+    .await?;
+    let selected = schedule::cheapest_slots(&slots, &constraints).map_err(|e| {
+        ApiError::BadRequest(format!("no schedule: {e}"), format!("{constraints:?}"))
+    })?;
+    let kwh = power * SLOT_MINUTES as f64 / 60.0;
+    let chosen: Vec<MarketData> = slots
+        .iter()
+        .zip(&selected)
+        .filter(|(_, on)| **on)
+        .map(|(s, _)| {
+            MarketData::from(&Data {
+                at: s.at,
+                price: s.price,
+            })
+        })
+        .collect();
+    let total = kwh * chosen.len() as f64;
+    let cost = chosen.iter().map(|d| d.price * kwh).sum::<f64>();
+    let ms = |at: NaiveDateTime| at.and_utc().timestamp_millis();
+    Ok(Json(SlotsData {
+        from: slots.first().map_or(ms(from), |s| ms(s.at)),
+        slot_minutes: SLOT_MINUTES,
+        schedule: selected
+            .iter()
+            .map(|on| if *on { '1' } else { '0' })
+            .collect(),
+        runs: schedule::runs(&slots, &selected)
+            .into_iter()
+            .map(|(start, end)| Run {
+                start: ms(start),
+                end: ms(end),
+            })
+            .collect(),
+        slots: chosen,
+        kwh: total,
+        cost,
+        price: if total > 0.0 { cost / total } else { 0.0 },
+    }))
+}
+
+/// kW per slot of the run
+fn parse_profile(
+    duration: Option<&str>,
+    profile: Option<&str>,
+    power: Option<f64>,
+) -> ApiResult<Vec<f64>> {
+    let wrong = |what: &str, e: String| ApiError::BadRequest(format!("wrong {what}: {e}"), e);
+    let slots = duration.map(|v| slot_count("duration", v)).transpose()?;
+    if slots == Some(0) {
+        return Err(wrong("duration", "0".to_string()));
+    }
+    let too_long = |len: usize| format!("{len} slots, max {MAX_SLOTS}");
+    if let Some(len) = slots.filter(|v| *v > MAX_SLOTS) {
+        return Err(wrong("duration", too_long(len)));
+    }
+    let res = match profile {
+        Some(v) => v
+            .split(',')
+            .map(|kw| match kw.trim().parse::<f64>() {
+                Ok(kw) if kw.is_finite() && kw >= 0.0 => Ok(kw),
+                _ => Err(wrong("profile", format!("'{kw}' is not kW"))),
+            })
+            .collect::<ApiResult<Vec<_>>>()?,
+        None => {
+            let power = power.unwrap_or(1.0);
+            if !power.is_finite() || power <= 0.0 {
+                return Err(wrong("power", power.to_string()));
+            }
+            let slots = slots.ok_or_else(|| wrong("duration", "not set".to_string()))?;
+            vec![power; slots]
+        }
+    };
+    if res.len() > MAX_SLOTS {
+        return Err(wrong("profile", too_long(res.len())));
+    }
+    if slots.is_some_and(|v| v != res.len()) {
+        return Err(wrong(
+            "profile",
+            format!("{} slots do not match the duration", res.len()),
+        ));
+    }
+    Ok(res)
+}
+
+/// slots of a duration as `2h` or `90m`
+fn slot_count(what: &str, v: &str) -> ApiResult<usize> {
+    let wrong = |e: String| ApiError::BadRequest(format!("wrong {what}: {e}"), e);
+    let d = parse_duration(v).map_err(wrong)?;
+    if d.as_secs() % (SLOT_MINUTES as u64 * 60) != 0 {
+        return Err(wrong(format!(
+            "{v} is not a multiple of {SLOT_MINUTES} minutes"
+        )));
+    }
+    Ok((d.as_secs() / 60 / SLOT_MINUTES as u64) as usize)
+}
+
+/// [from, to) of the search, from now if not set, `from` is rounded up to a slot start
+/// so the run never starts in the past
+fn range(from: Option<i64>, to: Option<i64>) -> ApiResult<(NaiveDateTime, NaiveDateTime)> {
+    let time = |v: i64| {
+        DateTime::from_timestamp_millis(v)
+            .map(|v| v.naive_utc())
+            .ok_or_else(|| ApiError::BadRequest("wrong time".to_string(), v.to_string()))
+    };
+    let from = match from {
+        Some(v) => time(v)?,
+        None => Utc::now().naive_utc(),
+    };
+    let from = match schedule::slot_start(from) {
+        start if start < from => start + schedule::slot(),
+        start => start,
+    };
+    let to = match to {
+        Some(v) => time(v)?,
+        None => from + Duration::hours(DEFAULT_RANGE_HOURS),
+    };
+    if to <= from {
+        return Err(ApiError::BadRequest(
+            "wrong range".to_string(),
+            format!("{from} - {to}"),
+        ));
+    }
+    if to - from > Duration::days(MAX_RANGE_DAYS) {
+        return Err(ApiError::BadRequest(
+            format!("wrong range: more than {MAX_RANGE_DAYS} days"),
+            format!("{from} - {to}"),
+        ));
+    }
+    Ok((from, to))
+}
+
+/// slot prices in EUR/kWh or in `currency`, the retail ones if a tariff is set,
+/// from the slot containing `from`
+pub async fn slot_prices(
+    srv: &Service,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    tariff: Option<&str>,
+    currency: Option<&str>,
+) -> ApiResult<Vec<Slot>> {
+    let ms = |at: NaiveDateTime| at.and_utc().timestamp_millis();
+    let ts_name = srv.price_series(RES_HOUR, STAT_RAW).await?;
+    let prices = srv
+        .db
+        .load(&ts_name, Some(ms(from - Duration::hours(1))), Some(ms(to)))
+        .await
+        .map_err(|e| ApiError::Server(e.to_string()))?;
+    let prices = match tariff {
+        Some(tariff) => srv
+            .retail(tariff, Some(PriceUnit::EurKwh), currency)
+            .await?
+            .list(&prices)?
+            .iter()
+            .map(|d| Data {
+                at: emarket::utils::to_time(d.at),
+                price: d.price,
+            })
+            .collect(),
+        None => {
+            let conv = srv.converter(Some(PriceUnit::EurKwh), currency).await?;
+            prices
+                .iter()
+                .map(|d| {
+                    Ok(Data {
+                        at: d.at,
+                        price: conv.convert(d.at, d.price)?,
+                    })
+                })
+                .collect::<ApiResult<Vec<_>>>()?
+        }
+    };
+    Ok(schedule::slots(&prices, from, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use emarket::{memory::MemoryStorage, series::Labels};
+
+    use super::*;
+
+    #[test]
+    fn parses_profile() {
+        assert_eq!(parse_profile(Some("1h"), None, None).unwrap(), vec![1.0; 4]);
+        assert_eq!(
+            parse_profile(Some("30m"), None, Some(2.0)).unwrap(),
+            vec![2.0; 2]
+        );
+        assert_eq!(
+            parse_profile(None, Some("2, 0.5"), None).unwrap(),
+            vec![2.0, 0.5]
+        );
+        assert!(parse_profile(Some("30m"), Some("1"), None).is_err());
+        assert!(parse_profile(Some("20m"), None, None).is_err());
+        assert!(parse_profile(None, None, None).is_err());
+        assert!(parse_profile(None, Some("1,x"), None).is_err());
+        assert!(parse_profile(Some("1h"), None, Some(-1.0)).is_err());
+        assert!(parse_profile(Some("0m"), None, None).is_err());
+        assert_eq!(slot_count("max_gap", "0m").unwrap(), 0);
+        assert_eq!(slot_count("min_block", "1h30m").unwrap(), 6);
+        assert!(parse_profile(Some("169h"), None, None).is_err());
+        let profile = vec!["1"; MAX_SLOTS + 1].join(",");
+        assert!(parse_profile(None, Some(&profile), None).is_err());
+    }
+
+    fn at(h: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 10)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            + Duration::hours(h)
+    }
+
+    fn ms(at: NaiveDateTime) -> i64 {
+        at.and_utc().timestamp_millis()
+    }
+
+    /// hourly prices of two days, cheap at 05:00
+    fn service() -> Arc<RwLock<Service>> {
+        let storage = MemoryStorage::new();
+        storage
+            .create("np_lt", &Labels::price("LT", RES_HOUR, STAT_RAW))
+            .unwrap();
+        let prices: Vec<Data> = (0..48)
+            .map(|h| Data {
+                at: at(h),
+                price: if h == 5 { 10.0 } else { 100.0 },
+            })
+            .collect();
+        storage.add("np_lt", &prices).unwrap();
+        Arc::new(RwLock::new(Service::new(Arc::new(storage), "LT")))
+    }
+
+    #[tokio::test]
+    async fn finds_window() {
+        let srv = service();
+        let call = |from: NaiveDateTime, to: NaiveDateTime, duration: &str| {
+            window(
+                State(srv.clone()),
+                Query(WindowParams {
+                    duration: Some(duration.to_string()),
+                    from: Some(ms(from)),
+                    to: Some(ms(to)),
+                    profile: None,
+                    power: None,
+                    currency: None,
+                    tariff: None,
+                }),
+            )
+        };
+        // from is rounded up to the next slot
+        let res = call(at(0) + Duration::minutes(5), at(24), "1h")
+            .await
+            .unwrap();
+        assert_eq!(res.now.start, at(0) + Duration::minutes(15));
+        assert_eq!(res.best.start, at(5));
+        assert!((res.savings - 0.09).abs() < 1e-9);
+        assert!(call(at(0), at(0) + Duration::days(8), "1h").await.is_err());
+        assert!(call(at(24), at(0), "1h").await.is_err());
+        assert!(call(at(40), at(60), "10h").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_huge_constraints() {
+        let srv = service();
+        let call = |duration: &str, huge: &str| {
+            let huge = Some(huge.to_string());
+            slots(
+                State(srv.clone()),
+                Query(SlotsParams {
+                    duration: duration.to_string(),
+                    from: Some(ms(at(0))),
+                    to: Some(ms(at(24))),
+                    min_block: huge.clone(),
+                    max_gap: huge.clone(),
+                    min_per_day: huge,
+                    power: None,
+                    currency: None,
+                    tariff: None,
+                }),
+            )
+        };
+        assert!(call("2h", "100000h").await.is_err());
+        let res = call("2h", "1h").await.unwrap();
+        assert!((res.kwh - 2.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{self, Query, State},
+    http::{header::CONTENT_TYPE, HeaderMap},
+    Json,
+};
+use chrono::{Duration, NaiveDateTime};
+use emarket::{
+    bill,
+    data::Data,
+    series::{RES_HOUR, STAT_RAW},
+    units::PriceUnit,
+    utils::{time_day_vilnius, time_month_vilnius},
+};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use crate::data::{ApiError, ApiResult, CostData, Service};
+
+/// max days of one upload
+const COST_MAX_DAYS: i64 = 400;
+
+#[derive(Deserialize)]
+pub struct CostParams {
+    /// `end` if the CSV times are the interval ends, `start` by default
+    time: Option<String>,
+}
+
+/// spot cost of the uploaded consumption by day and month compared with the fixed price offers,
+/// the body is a smart-meter CSV export or JSON with the `application/json` content type
+pub async fn handler(
+    State(srv_wrap): State<Arc<RwLock<Service>>>,
+    Query(params): Query<CostParams>,
+    headers: HeaderMap,
+    body: String,
+) -> ApiResult<extract::Json<CostData>> {
+    tracing::debug!("cost handler");
+    let srv = srv_wrap.read().await;
+    let interval_end = match params.time.as_deref() {
+        None | Some("start") => false,
+        Some("end") => true,
+        Some(v) => {
+            return Err(ApiError::BadRequest(
+                format!("wrong time: {v}, expected start or end"),
+                v.to_string(),
+            ))
+        }
+    };
+    let json = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("json"));
+    let wrong = |e: String| ApiError::BadRequest(format!("wrong consumption: {e}"), e);
+    let mut usage = match json {
+        true => bill::parse_json(&body),
+        false => bill::parse_csv(&body),
+    }
+    .map_err(wrong)?;
+    let step = bill::prepare(&mut usage, interval_end).map_err(wrong)?;
+    let from = usage[0].at;
+    let to = usage[usage.len() - 1].at + step;
+    tracing::debug!(len = usage.len(), %from, %to, step = step.num_minutes(), "consumption");
+    if to - from > Duration::days(COST_MAX_DAYS) {
+        return Err(wrong(format!("more than {COST_MAX_DAYS} days")));
+    }
+
+    let ms = |at: NaiveDateTime| at.and_utc().timestamp_millis();
+    let ts_name = srv.price_series(RES_HOUR, STAT_RAW).await?;
+    let prices = srv
+        .db
+        .load(&ts_name, Some(ms(from - Duration::hours(1))), Some(ms(to)))
+        .await
+        .map_err(|e| ApiError::Server(e.to_string()))?;
+    let conv = srv.converter(Some(PriceUnit::EurKwh), None).await?;
+    let prices = prices
+        .iter()
+        .map(|d| {
+            Ok(Data {
+                at: d.at,
+                price: conv.convert(d.at, d.price)?,
+            })
+        })
+        .collect::<ApiResult<Vec<_>>>()?;
+    let costs = bill::spot_costs(&usage, &prices)
+        .map_err(|e| ApiError::BadRequest(format!("no spot prices: {e}"), e))?;
+
+    let months = bill::sum(&costs, |at| time_month_vilnius(at, 0));
+    let total = bill::sum(&costs, |_| from).remove(0);
+    let res = CostData {
+        from: ms(from),
+        to: ms(to),
+        resolution: step.num_minutes(),
+        offers: bill::compare(srv.offers(), &total, bill::covered_months(from, to)),
+        days: bill::sum(&costs, |at| time_day_vilnius(at, 0)),
+        months,
+        total,
+    };
+    Ok(Json(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+    use chrono::NaiveDate;
+    use emarket::{bill::Offer, memory::MemoryStorage, series::Labels};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn calculates_cost() {
+        let at = |h: i64| {
+            NaiveDate::from_ymd_opt(2024, 1, 10)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                + Duration::hours(h)
+        };
+        let storage = MemoryStorage::new();
+        storage
+            .create("np_lt", &Labels::price("LT", RES_HOUR, STAT_RAW))
+            .unwrap();
+        let prices: Vec<Data> = (0..48)
+            .map(|h| Data {
+                at: at(h),
+                price: 100.0,
+            })
+            .collect();
+        storage.add("np_lt", &prices).unwrap();
+        let offer = |id: &str, monthly_fee| Offer {
+            id: id.to_string(),
+            name: String::new(),
+            price: 0.2,
+            monthly_fee,
+        };
+        let offers = vec![offer("fixed", 0.0), offer("fee", 7.44)];
+        let srv = Service::new(Arc::new(storage), "LT").with_offers(offers);
+        let srv = Arc::new(RwLock::new(srv));
+        let call = |body: &str, json: bool| {
+            let mut headers = HeaderMap::new();
+            if json {
+                headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            }
+            handler(
+                State(srv.clone()),
+                Query(CostParams { time: None }),
+                headers,
+                body.to_string(),
+            )
+        };
+        // 02:00 and 03:00 in Vilnius
+        let res = call(
+            "Data;Laikas;kWh\n2024-01-10;02:00;1\n2024-01-10;03:00;2\n",
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.resolution, 60);
+        assert_eq!(res.days.len(), 1);
+        assert!((res.total.cost - 0.3).abs() < 1e-9);
+        assert!((res.offers[0].difference - 0.3).abs() < 1e-9);
+        // 2 of the 744 hours of january
+        assert!((res.offers[1].difference - 0.32).abs() < 1e-9);
+        let json = format!(
+            r#"[{{"at": {}, "kwh": 1}}]"#,
+            at(47).and_utc().timestamp_millis()
+        );
+        assert!(call(&json, true).await.is_ok());
+        let json = format!(
+            r#"[{{"at": {}, "kwh": 1}}]"#,
+            at(48).and_utc().timestamp_millis()
+        );
+        assert!(call(&json, true).await.is_err());
+        assert!(call("", false).await.is_err());
+    }
+}
@@ -12,7 +12,7 @@ pub async fn handler(
     State(srv_wrap): State<Arc<RwLock<Service>>>,
 ) -> ApiResult<extract::Json<LiveResponse>> {
     let srv = srv_wrap.read().await;
-    let res = srv.redis.live().await;
+    let res = srv.db.live().await;
     let (r_res, st) = match res {
         Ok(_) => ("ok".to_string(), true),
         Err(err) => (err.to_string(), false),
@@ -4,4 +4,8 @@ pub mod error;
 pub mod summary;
 pub mod prices;
 pub mod now;
-
+pub mod series;
+pub mod revisions;
+pub mod quality;
+pub mod cost;
+pub mod cheapest;
@@ -1,38 +1,71 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::{self, State},
+    extract::{self, Query, State},
     Json,
 };
 use chrono::{Duration, NaiveDateTime, Timelike, Utc};
-use emarket::TN_HOUR;
+use emarket::series::{RES_HOUR, STAT_RAW};
+use serde::Deserialize;
 use tokio::sync::RwLock;
 
 use tracing::instrument;
 
 use crate::{
-    data::{ApiResult, MarketData, NowData, Service},
+    data::{parse_unit, ApiResult, MarketData, NowData, Service},
     handlers::summary::{get_list},
 };
 
+#[derive(Deserialize, Debug)]
+pub struct NowParams {
+    /// eur_mwh, eur_kwh or ct_kwh, the stored unit if not set
+    unit: Option<String>,
+    /// ECB currency code, e.g. PLN, EUR if not set
+    currency: Option<String>,
+    /// returns the retail price of the tariff per kWh with its components
+    tariff: Option<String>,
+}
+
 #[instrument(skip(srv_wrap))]
 pub async fn handler(
     State(srv_wrap): State<Arc<RwLock<Service>>>,
+    Query(params): Query<NowParams>,
 ) -> ApiResult<extract::Json<NowData>> {
     tracing::debug!("now handler");
 
     let srv = srv_wrap.read().await;
+    let unit = parse_unit(params.unit.as_deref())?;
+    let retail = match params.tariff.as_deref() {
+        Some(tariff) => Some(srv.retail(tariff, unit, params.currency.as_deref()).await?),
+        None => None,
+    };
+    let conv = srv.converter(unit, params.currency.as_deref()).await?;
 
     let now = Utc::now().naive_utc();
 
     let from = hour_start(now);
     let to = from + Duration::minutes(50); // make range from start of an hour to 50 minutes later
 
-    let list = get_list(&srv.redis, TN_HOUR, from, to).await?;
+    let ts_name = srv.price_series(RES_HOUR, STAT_RAW).await?;
+    let list = get_list(srv.db.as_ref(), &ts_name, from, to).await?;
 
     let v = get_best_value(&list, now);
     match v {
-        Ok(v) => {return Ok(Json(v));},
+        Ok(v) => {
+            let at = emarket::utils::to_time(v.at as u64);
+            if let (Some(retail), Some(price)) = (&retail, v.price) {
+                let res = retail.price(at, price)?;
+                return Ok(Json(NowData {
+                    price: Some(res.price),
+                    components: res.components,
+                    ..v
+                }));
+            }
+            return Ok(Json(NowData {
+                price: v.price.map(|p| conv.convert(at, p)).transpose()?,
+                ..v
+            }));
+        }
         Err(e) => {
             return Err(crate::data::ApiError::Server(format!(
                 "Error getting best value for now: {}",
@@ -50,6 +83,7 @@ fn get_best_value(list: &[MarketData], now: NaiveDateTime) -> anyhow::Result<Now
     let mut res = NowData {
         at: list[0].at as i64,
         price: Some(list[0].price),
+        components: None,
     };
     for item in list {
         if item.at <= timestamp_millis {
@@ -4,11 +4,23 @@ use axum::{
     extract::{self, Query, State},
     Json,
 };
-use emarket::{utils::to_str_or_none, TN_DAY, TN_HOUR, TN_MONTH};
+use chrono::{Duration, NaiveDateTime, Utc};
+use emarket::{
+    data::{average, Data},
+    series::{RES_DAY, RES_HOUR, RES_MONTH, STAT_AVG, STAT_RAW},
+    storage::SeriesReader,
+    utils::{time_day_vilnius, time_month_vilnius, to_str_or_none},
+};
 use serde::Deserialize;
 use tokio::sync::RwLock;
 
-use crate::data::{ApiError, ApiResult, MarketData, Service};
+use crate::data::{
+    average_retail, parse_unit, to_time, with_quality, ApiError, ApiResult, Converter, MarketData,
+    RetailConverter, Service, VIEW_DAY, VIEW_HOUR, VIEW_MONTH,
+};
+
+/// max days of a `/prices` call with `tariff`, as the retail prices are built from the hours
+const RETAIL_MAX_DAYS: i64 = 400;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TimeRange {
@@ -36,6 +48,17 @@ pub struct PricesParams {
     time_range: Option<String>,
     from: Option<i64>,
     to: Option<i64>,
+    /// returns the prices as known at this time
+    as_of: Option<i64>,
+    /// adds the quality flag to the hourly prices
+    #[serde(default)]
+    quality: bool,
+    /// eur_mwh, eur_kwh or ct_kwh, the stored unit if not set
+    unit: Option<String>,
+    /// ECB currency code, e.g. PLN, EUR if not set
+    currency: Option<String>,
+    /// returns the retail prices of the tariff per kWh with their components
+    tariff: Option<String>,
 }
 
 pub async fn handler(
@@ -48,30 +71,192 @@ pub async fn handler(
         from = to_str_or_none(params.from),
         to = to_str_or_none(params.to),
         time_range = &params.time_range,
+        as_of = to_str_or_none(params.as_of),
         "params",
     );
 
-    let table_name = get_table_name(params.time_range)?;
+    if params.quality
+        && (params.as_of.is_some()
+            || parse_time_range(params.time_range.clone())? != TimeRange::Hourly)
+    {
+        return Err(ApiError::BadRequest(
+            "wrong quality".to_string(),
+            "flags are kept for the current hourly prices only".to_string(),
+        ));
+    }
+    let unit = parse_unit(params.unit.as_deref())?;
+    if let Some(tariff) = params.tariff.as_deref() {
+        let retail = srv.retail(tariff, unit, params.currency.as_deref()).await?;
+        let res = retail_prices(&srv, &retail, &params).await?;
+        return Ok(Json(res));
+    }
+    let conv = srv.converter(unit, params.currency.as_deref()).await?;
+    let time_range = parse_time_range(params.time_range.clone())?;
+    if let Some(as_of) = params.as_of {
+        let view = srv.as_of(as_of, params.from, params.to).await?;
+        if time_range == TimeRange::Monthly && conv.has_rates() {
+            let res = months_from_days(&view, VIEW_DAY, params.from, params.to, &conv).await?;
+            return Ok(Json(res));
+        }
+        let ts_name = match time_range {
+            TimeRange::Hourly => VIEW_HOUR,
+            TimeRange::Daily => VIEW_DAY,
+            TimeRange::Monthly => VIEW_MONTH,
+        };
+        let res = view
+            .load(ts_name, params.from, params.to)
+            .await
+            .map_err(|e| ApiError::Server(e.to_string()))?;
+        tracing::debug!(len = res.len(), "loaded as of");
+        let mut res: Vec<MarketData> = res.iter().map(MarketData::from).collect();
+        conv.list(&mut res)?;
+        return Ok(Json(res));
+    }
+    if time_range == TimeRange::Monthly && conv.has_rates() {
+        let tn_day = srv.price_series(RES_DAY, STAT_AVG).await?;
+        let res = months_from_days(srv.db.as_ref(), &tn_day, params.from, params.to, &conv).await?;
+        return Ok(Json(res));
+    }
+    let candidates = get_series(params.time_range)?;
+    let table_name = srv.price_series_for(candidates, params.from).await?;
     tracing::debug!(table_name, "will use");
-    let res = srv
-        .redis
-        .load(table_name, params.from, params.to)
+    let mut res = srv
+        .load(&table_name, params.from, params.to)
         .await
         .map_err(|e| ApiError::Server(e.to_string()))?;
     tracing::debug!(len = res.len(), "loaded");
+    if params.quality {
+        with_quality(&mut res, &srv.quality(params.from, params.to).await?);
+    }
+    conv.list(&mut res)?;
     Ok(Json(res))
 }
 
-fn get_table_name(data: Option<String>) -> Result<&'static str, ApiError> {
-    let time_range = match data {
-        Some(s) => TimeRange::from_str(&s)
-            .map_err(|e| ApiError::BadRequest(format!("wrong time_range: {}", s).to_string(), e))?,
-        None => TimeRange::Monthly,
+/// retail prices from the hourly prices, the daily and monthly ones are their averages
+/// as the grid fee depends on the hour. `to` defaults to the day-ahead prices,
+/// `from` to [`RETAIL_MAX_DAYS`] before `to`
+async fn retail_prices(
+    srv: &Service,
+    retail: &RetailConverter<'_>,
+    params: &PricesParams,
+) -> ApiResult<Vec<MarketData>> {
+    let time_range = parse_time_range(params.time_range.clone())?;
+    let bucket = |at: NaiveDateTime, shift: i32| match time_range {
+        TimeRange::Hourly => at,
+        TimeRange::Daily => time_day_vilnius(at, shift as i64),
+        TimeRange::Monthly => time_month_vilnius(at, shift),
+    };
+    let ms = |at: NaiveDateTime| at.and_utc().timestamp_millis();
+    let to = match params.to {
+        Some(v) => to_time(v)?,
+        None => Utc::now().naive_utc() + Duration::days(2),
+    };
+    let from = match params.from {
+        Some(v) => to_time(v)?,
+        None => to - Duration::days(RETAIL_MAX_DAYS),
+    };
+    if from >= to || to - from > Duration::days(RETAIL_MAX_DAYS) {
+        return Err(ApiError::BadRequest(
+            "wrong range".to_string(),
+            format!("from < to, at most {RETAIL_MAX_DAYS} days with tariff"),
+        ));
+    }
+    let (from, to) = (ms(from), ms(to));
+    // the hours of the days or months starting in [from, to)
+    let (hours_from, hours_to) = match time_range {
+        TimeRange::Hourly => (from, to),
+        _ => (
+            ms(bucket(to_time(from)?, 0)),
+            ms(bucket(to_time(to - 1)?, 1)),
+        ),
+    };
+    let (hours_from, hours_to) = (Some(hours_from), Some(hours_to));
+    let hours = match params.as_of {
+        Some(as_of) => {
+            srv.as_of(as_of, hours_from, hours_to)
+                .await?
+                .load(VIEW_HOUR, hours_from, hours_to)
+                .await
+        }
+        None => {
+            let ts_name = srv.price_series(RES_HOUR, STAT_RAW).await?;
+            srv.db.load(&ts_name, hours_from, hours_to).await
+        }
+    }
+    .map_err(|e| ApiError::Server(e.to_string()))?;
+    tracing::debug!(len = hours.len(), "loaded hours");
+    let mut res = retail.list(&hours)?;
+    if time_range == TimeRange::Hourly {
+        if params.quality {
+            with_quality(&mut res, &srv.quality(hours_from, hours_to).await?);
+        }
+        return Ok(res);
+    }
+    let res = average_retail(&res, |at| bucket(at, 0))
+        .into_iter()
+        .filter(|d| (from..to).contains(&(d.at as i64)))
+        .collect();
+    Ok(res)
+}
+
+/// monthly prices as the means of the converted day averages,
+/// as the rate changes every day within a month
+async fn months_from_days(
+    db: &dyn SeriesReader,
+    tn_day: &str,
+    from: Option<i64>,
+    to: Option<i64>,
+    conv: &Converter,
+) -> ApiResult<Vec<MarketData>> {
+    let month = |v: i64, months: i32| {
+        Ok::<_, ApiError>(
+            time_month_vilnius(to_time(v)?, months)
+                .and_utc()
+                .timestamp_millis(),
+        )
     };
-    let res = match time_range {
-        TimeRange::Hourly => TN_HOUR,
-        TimeRange::Daily => TN_DAY,
-        TimeRange::Monthly => TN_MONTH,
+    let days = db
+        .load(
+            tn_day,
+            from.map(|v| month(v, 0)).transpose()?,
+            to.map(|v| month(v.saturating_sub(1), 1)).transpose()?,
+        )
+        .await
+        .map_err(|e| ApiError::Server(e.to_string()))?;
+    let mut converted = Vec::with_capacity(days.len());
+    for d in days {
+        converted.push(Data {
+            price: conv.convert(d.at, d.price)?,
+            at: d.at,
+        });
+    }
+    let res = average(&converted, |at| time_month_vilnius(at, 0))
+        .iter()
+        .map(MarketData::from)
+        .filter(|d| from.is_none_or(|v| d.at as i64 >= v) && to.is_none_or(|v| (d.at as i64) < v))
+        .collect();
+    Ok(res)
+}
+
+fn parse_time_range(data: Option<String>) -> Result<TimeRange, ApiError> {
+    match data {
+        Some(s) => TimeRange::from_str(&s)
+            .map_err(|e| ApiError::BadRequest(format!("wrong time_range: {}", s).to_string(), e)),
+        None => Ok(TimeRange::Monthly),
+    }
+}
+
+/// returns resolution and statistic labels of the series starting from the finest one
+fn get_series(data: Option<String>) -> Result<&'static [(&'static str, &'static str)], ApiError> {
+    let res: &'static [(&str, &str)] = match parse_time_range(data)? {
+        TimeRange::Hourly => &[
+            (RES_HOUR, STAT_RAW),
+            (RES_HOUR, STAT_AVG),
+            (RES_DAY, STAT_AVG),
+            (RES_MONTH, STAT_AVG),
+        ],
+        TimeRange::Daily => &[(RES_DAY, STAT_AVG), (RES_MONTH, STAT_AVG)],
+        TimeRange::Monthly => &[(RES_MONTH, STAT_AVG)],
     };
     Ok(res)
 }
@@ -96,6 +281,156 @@ mod tests {
         assert_eq!(TimeRange::from_str("moNthly"), Ok(TimeRange::Monthly));
     }
 
+    #[test]
+    fn test_series_labels() {
+        assert_eq!(get_series(None).unwrap(), &[(RES_MONTH, STAT_AVG)]);
+        assert_eq!(
+            get_series(Some("hourly".to_string())).unwrap(),
+            &[
+                (RES_HOUR, STAT_RAW),
+                (RES_HOUR, STAT_AVG),
+                (RES_DAY, STAT_AVG),
+                (RES_MONTH, STAT_AVG)
+            ]
+        );
+        assert_eq!(
+            get_series(Some("daily".to_string())).unwrap(),
+            &[(RES_DAY, STAT_AVG), (RES_MONTH, STAT_AVG)]
+        );
+        assert!(get_series(Some("weekly".to_string())).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_months_from_days() {
+        use chrono::NaiveDate;
+        use emarket::{memory::MemoryStorage, rates::Rates};
+
+        let at = |m: u32, d: u32| {
+            NaiveDate::from_ymd_opt(2024, m, d)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                - chrono::Duration::hours(2)
+        };
+        let point = |at, price| Data { at, price };
+        let db = MemoryStorage::new();
+        db.create(
+            "np_lt_d",
+            &emarket::series::Labels::price("LT", RES_DAY, STAT_AVG),
+        )
+        .unwrap();
+        db.add(
+            "np_lt_d",
+            &[
+                point(at(1, 31), 10.0),
+                point(at(2, 1), 10.0),
+                point(at(2, 2), 20.0),
+            ],
+        )
+        .unwrap();
+        let rates = Rates::new(&[
+            point(at(1, 31) + chrono::Duration::hours(2), 2.0),
+            point(at(2, 2) + chrono::Duration::hours(2), 4.0),
+        ]);
+        let conv = Converter::new(1.0, Some(Arc::new(rates)));
+        let ms = |v: chrono::NaiveDateTime| v.and_utc().timestamp_millis();
+        let res = months_from_days(&db, "np_lt_d", Some(ms(at(2, 1))), None, &conv)
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].at as i64, ms(at(2, 1)));
+        assert_eq!(res[0].price, 50.0);
+        // [from, to) as the EUR months
+        let res = months_from_days(&db, "np_lt_d", None, Some(ms(at(2, 1))), &conv)
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].at as i64, ms(at(1, 1)));
+        let res = months_from_days(&db, "np_lt_d", None, Some(ms(at(2, 2))), &conv)
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[1].price, 50.0);
+        assert!(
+            months_from_days(&db, "np_lt_d", Some(i64::MAX), None, &conv)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retail_prices_range() {
+        use chrono::NaiveDate;
+        use emarket::{memory::MemoryStorage, series::Labels, tariff::Tariffs};
+
+        // Vilnius midnights
+        let day = |d: u32| {
+            NaiveDate::from_ymd_opt(2024, 1, d)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                - Duration::hours(2)
+        };
+        let storage = MemoryStorage::new();
+        storage
+            .create("np_lt", &Labels::price("LT", RES_HOUR, STAT_RAW))
+            .unwrap();
+        let hours: Vec<Data> = (0..72)
+            .map(|h| Data {
+                at: day(10) + Duration::hours(h),
+                price: 100.0,
+            })
+            .collect();
+        storage.add("np_lt", &hours).unwrap();
+        let tariffs = Tariffs::from_json(
+            r#"{"tariffs": [{
+                "id": "one",
+                "vat": [{"from": "2024-01-01", "value": 0}],
+                "grid": {"zones": [{"name": "all", "fee": [{"from": "2024-01-01", "value": 0.1}]}]}
+            }]}"#,
+        )
+        .unwrap();
+        let srv = Service::new(Arc::new(storage), "LT").with_tariffs(tariffs);
+        let retail = srv.retail("one", None, None).await.unwrap();
+        let ms = |v: NaiveDateTime| v.and_utc().timestamp_millis();
+        let params = |time_range: &str, from: Option<i64>, to: Option<i64>| PricesParams {
+            time_range: Some(time_range.to_string()),
+            from,
+            to,
+            as_of: None,
+            quality: false,
+            unit: None,
+            currency: None,
+            tariff: Some("one".to_string()),
+        };
+        let call = |p: PricesParams| {
+            let (srv, retail) = (&srv, &retail);
+            async move { retail_prices(srv, retail, &p).await }
+        };
+        // [from, to) as without a tariff
+        let res = call(params("daily", Some(ms(day(10))), Some(ms(day(12)))))
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[1].at as i64, ms(day(11)));
+        assert!((res[0].price - 0.2).abs() < 1e-9);
+        let res = call(params("hourly", Some(ms(day(10))), Some(ms(day(11)))))
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 24);
+        // a day starting before `to` is whole
+        let to = day(11) + Duration::hours(1);
+        let res = call(params("daily", Some(ms(day(10))), Some(ms(to))))
+            .await
+            .unwrap();
+        assert_eq!(res.len(), 2);
+        assert!(call(params("daily", None, None)).await.is_ok());
+        let from = day(12) - Duration::days(RETAIL_MAX_DAYS + 1);
+        assert!(call(params("daily", Some(ms(from)), Some(ms(day(12)))))
+            .await
+            .is_err());
+    }
+
     #[test]
     fn test_invalid_time_ranges() {
         assert!(TimeRange::from_str("weekly").is_err());
@@ -0,0 +1,30 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{self, Query, State},
+    Json,
+};
+use emarket::quality::DayQuality;
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use crate::data::{ApiResult, Service};
+
+#[derive(Deserialize)]
+pub struct QualityParams {
+    from: i64,
+    to: i64,
+}
+
+/// counts the original, filled, revised and missing hourly prices of the days in [from, to)
+pub async fn handler(
+    State(srv_wrap): State<Arc<RwLock<Service>>>,
+    Query(params): Query<QualityParams>,
+) -> ApiResult<extract::Json<Vec<DayQuality>>> {
+    tracing::debug!("quality handler");
+    let srv = srv_wrap.read().await;
+    tracing::debug!(from = params.from, to = params.to, "params");
+    let res = srv.quality_days(params.from, params.to).await?;
+    tracing::debug!(len = res.len(), "days");
+    Ok(Json(res))
+}
@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{self, Query, State},
+    Json,
+};
+use emarket::{revisions::Revision, utils::to_str_or_none};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use crate::data::{ApiResult, Service};
+
+#[derive(Deserialize)]
+pub struct RevisionsParams {
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+/// lists the known values of the hourly prices in [from, to) with their revisions
+pub async fn handler(
+    State(srv_wrap): State<Arc<RwLock<Service>>>,
+    Query(params): Query<RevisionsParams>,
+) -> ApiResult<extract::Json<Vec<Revision>>> {
+    tracing::debug!("revisions handler");
+    let srv = srv_wrap.read().await;
+    tracing::debug!(
+        from = to_str_or_none(params.from),
+        to = to_str_or_none(params.to),
+        "params",
+    );
+    let res = srv.revisions(params.from, params.to).await?;
+    tracing::debug!(len = res.len(), "loaded");
+    Ok(Json(res))
+}
@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{self, Query, State},
+    Json,
+};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+use tracing::instrument;
+
+use emarket::storage::SeriesInfo;
+
+use crate::data::{ApiError, ApiResult, Service};
+
+#[derive(Deserialize, Debug)]
+pub struct SeriesParams {
+    zone: Option<String>,
+    measure: Option<String>,
+}
+
+#[instrument(skip(srv_wrap))]
+pub async fn handler(
+    State(srv_wrap): State<Arc<RwLock<Service>>>,
+    Query(params): Query<SeriesParams>,
+) -> ApiResult<extract::Json<Vec<SeriesInfo>>> {
+    tracing::debug!("series handler");
+    let srv = srv_wrap.read().await;
+    let mut filter = vec![format!(
+        "zone={}",
+        params.zone.as_deref().unwrap_or(&srv.zone)
+    )];
+    if let Some(measure) = params.measure {
+        filter.push(format!("measure={measure}"));
+    }
+    let res = srv
+        .list_series(&filter)
+        .await
+        .map_err(|e| ApiError::Server(e.to_string()))?;
+    tracing::debug!(len = res.len(), "loaded");
+    Ok(Json(res))
+}
@@ -5,17 +5,31 @@ use axum::{
     Json,
 };
 use chrono::{NaiveDateTime, Utc};
+use emarket::data::Statistic;
+use emarket::series::{RES_DAY, RES_HOUR, RES_MONTH, STAT_AVG, STAT_RAW};
+use emarket::storage::SeriesReader;
 use emarket::utils::{time_day_vilnius, time_month_vilnius, to_str_or_none, to_time};
 use serde::Deserialize;
 use tokio::sync::RwLock;
 
-use crate::data::{ApiError, ApiResult, MarketData, Service, SummaryData};
+use crate::data::{
+    parse_unit, ApiError, ApiResult, Converter, MarketData, RetailConverter, Service, SummaryData,
+    VIEW_DAY, VIEW_HOUR, VIEW_MONTH,
+};
 
 use tracing::instrument;
 
 #[derive(Deserialize)]
 pub struct SummaryParams {
     at: Option<i64>,
+    /// calculates the summary from the prices known at this time
+    as_of: Option<i64>,
+    /// eur_mwh, eur_kwh or ct_kwh, the stored unit if not set
+    unit: Option<String>,
+    /// ECB currency code, e.g. PLN, EUR if not set
+    currency: Option<String>,
+    /// averages the retail prices of the tariff per kWh
+    tariff: Option<String>,
 }
 
 #[instrument(skip(srv_wrap, params))]
@@ -25,26 +39,166 @@ pub async fn handler(
 ) -> ApiResult<extract::Json<SummaryData>> {
     tracing::debug!("summary handler");
     let srv = srv_wrap.read().await;
-    tracing::debug!(at = to_str_or_none(params.at));
+    tracing::debug!(
+        at = to_str_or_none(params.at),
+        as_of = to_str_or_none(params.as_of)
+    );
 
+    let unit = parse_unit(params.unit.as_deref())?;
     let at = match params.at {
         Some(a) => a,
         None => Utc::now().timestamp_millis(),
     };
+    if let Some(tariff) = params.tariff.as_deref() {
+        let retail = srv.retail(tariff, unit, params.currency.as_deref()).await?;
+        return Ok(Json(retail_summary(&srv, &retail, at, params.as_of).await?));
+    }
+    let conv = srv.converter(unit, params.currency.as_deref()).await?;
+
+    let (db, tn_month, tn_day): (Arc<dyn SeriesReader>, String, String) = match params.as_of {
+        Some(as_of) => {
+            let from = std::cmp::min(month(at, -1), day(at, -29));
+            let to = std::cmp::max(month(at, 1), day(at, 3));
+            let view = srv
+                .as_of(
+                    as_of,
+                    Some(from.and_utc().timestamp_millis()),
+                    Some(to.and_utc().timestamp_millis()),
+                )
+                .await?;
+            (Arc::new(view), VIEW_MONTH.to_string(), VIEW_DAY.to_string())
+        }
+        None => (
+            srv.db.clone(),
+            srv.price_series(RES_MONTH, STAT_AVG).await?,
+            srv.price_series(RES_DAY, STAT_AVG).await?,
+        ),
+    };
+    let (tn_month, tn_day) = (tn_month.as_str(), tn_day.as_str());
+    let db = db.as_ref();
 
+    let conv = &conv;
+    let value = |v: Option<f64>, from: NaiveDateTime| v.map(|v| conv.convert(from, v)).transpose();
     let res = SummaryData {
         at,
-        current_month_avg: get_value(&srv.redis, "np_lt_m", month(at, 0), month(at, 1)).await?,
-        previous_month_avg: get_value(&srv.redis, "np_lt_m", month(at, -1), month(at, 0)).await?,
-        today_avg: get_value(&srv.redis, "np_lt_d", day(at, 0), day(at, 1)).await?,
-        tomorrow_avg: get_value_full(&srv.redis, "np_lt_d", day(at, 1), day(at, 3), 2).await?,
-        yesterday_avg: get_value(&srv.redis, "np_lt_d", day(at, -1), day(at, 0)).await?,
-        last_30d_avg: get_avg(&srv.redis, "np_lt_d", day(at, -29), day(at, 1)).await?,
-        last_7_avg: get_avg(&srv.redis, "np_lt_d", day(at, -6), day(at, 1)).await?,
+        current_month_avg: get_month(db, tn_month, tn_day, month(at, 0), month(at, 1), conv)
+            .await?,
+        previous_month_avg: get_month(db, tn_month, tn_day, month(at, -1), month(at, 0), conv)
+            .await?,
+        today_avg: value(
+            get_value(db, tn_day, day(at, 0), day(at, 1)).await?,
+            day(at, 0),
+        )?,
+        tomorrow_avg: value(
+            get_value_full(db, tn_day, day(at, 1), day(at, 3), 2).await?,
+            day(at, 1),
+        )?,
+        yesterday_avg: value(
+            get_value(db, tn_day, day(at, -1), day(at, 0)).await?,
+            day(at, -1),
+        )?,
+        last_30d_avg: get_converted_avg(db, tn_day, day(at, -29), day(at, 1), conv).await?,
+        last_7_avg: get_converted_avg(db, tn_day, day(at, -6), day(at, 1), conv).await?,
     };
     Ok(Json(res))
 }
 
+/// summary of the hourly retail prices, the grid fee depends on the hour
+async fn retail_summary(
+    srv: &Service,
+    retail: &RetailConverter<'_>,
+    at: i64,
+    as_of: Option<i64>,
+) -> ApiResult<SummaryData> {
+    let from = std::cmp::min(month(at, -1), day(at, -29));
+    let to = std::cmp::max(month(at, 1), day(at, 3));
+    let (from_ms, to_ms) = (
+        Some(from.and_utc().timestamp_millis()),
+        Some(to.and_utc().timestamp_millis()),
+    );
+    let hours = match as_of {
+        Some(as_of) => {
+            let view = srv.as_of(as_of, from_ms, to_ms).await?;
+            view.load(VIEW_HOUR, from_ms, to_ms).await
+        }
+        None => {
+            let ts_name = srv.price_series(RES_HOUR, STAT_RAW).await?;
+            srv.db.load(&ts_name, from_ms, to_ms).await
+        }
+    }
+    .map_err(|e| ApiError::Server(e.to_string()))?;
+    let list = retail.list(&hours)?;
+    // `full` requires all hours of the range
+    let avg = |from: NaiveDateTime, to: NaiveDateTime, full: bool| {
+        let (from, to) = (
+            from.and_utc().timestamp_millis() as u64,
+            to.and_utc().timestamp_millis() as u64,
+        );
+        let prices: Vec<f64> = list
+            .iter()
+            .filter(|d| d.at >= from && d.at < to)
+            .map(|d| d.price)
+            .collect();
+        let hours = (to - from) / 3_600_000;
+        if prices.is_empty() || (full && (prices.len() as u64) < hours) {
+            return None;
+        }
+        Some(prices.iter().sum::<f64>() / prices.len() as f64)
+    };
+    Ok(SummaryData {
+        at,
+        current_month_avg: avg(month(at, 0), month(at, 1), false),
+        previous_month_avg: avg(month(at, -1), month(at, 0), false),
+        today_avg: avg(day(at, 0), day(at, 1), false),
+        tomorrow_avg: avg(day(at, 1), day(at, 2), true),
+        yesterday_avg: avg(day(at, -1), day(at, 0), false),
+        last_30d_avg: avg(day(at, -29), day(at, 1), false),
+        last_7_avg: avg(day(at, -6), day(at, 1), false),
+    })
+}
+
+/// month average, the mean of the converted day averages if the rate changes by day
+async fn get_month(
+    db: &dyn SeriesReader,
+    tn_month: &str,
+    tn_day: &str,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    conv: &Converter,
+) -> ApiResult<Option<f64>> {
+    if conv.has_rates() {
+        return get_converted_avg(db, tn_day, from, to, conv).await;
+    }
+    get_value(db, tn_month, from, to)
+        .await?
+        .map(|v| conv.convert(from, v))
+        .transpose()
+}
+
+/// average of the days, each day converted with its own rate
+async fn get_converted_avg(
+    db: &dyn SeriesReader,
+    tn_day: &str,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    conv: &Converter,
+) -> ApiResult<Option<f64>> {
+    if !conv.has_rates() {
+        return get_avg(db, tn_day, from, to)
+            .await?
+            .map(|v| conv.convert(from, v))
+            .transpose();
+    }
+    let mut list = get_list(db, tn_day, from, to).await?;
+    conv.list(&mut list)?;
+    if list.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        list.iter().map(|d| d.price).sum::<f64>() / list.len() as f64,
+    ))
+}
+
 fn month(at: i64, months: i32) -> NaiveDateTime {
     time_month_vilnius(to_time(at as u64), months)
 }
@@ -54,23 +208,23 @@ fn day(at: i64, days: i64) -> NaiveDateTime {
 }
 
 pub async fn get_value(
-    redis: &crate::redis::RedisClient,
+    db: &dyn SeriesReader,
     ts_name: &str,
     from: NaiveDateTime,
     to: NaiveDateTime,
 ) -> anyhow::Result<Option<f64>> {
-    get_value_full(redis, ts_name, from, to, 1).await
+    get_value_full(db, ts_name, from, to, 1).await
 }
 
-#[instrument(skip(redis, ts_name, from, to, min_items))]
+#[instrument(skip(db, ts_name, from, to, min_items))]
 async fn get_value_full(
-    redis: &crate::redis::RedisClient,
+    db: &dyn SeriesReader,
     ts_name: &str,
     from: NaiveDateTime,
     to: NaiveDateTime,
     min_items: usize,
 ) -> anyhow::Result<Option<f64>> {
-    let res = redis
+    let res = db
         .load(
             ts_name,
             Some(from.and_utc().timestamp_millis()),
@@ -86,14 +240,14 @@ async fn get_value_full(
     Ok(Some(res[0].price))
 }
 
-#[instrument(skip(redis, ts_name, from, to))]
+#[instrument(skip(db, ts_name, from, to))]
 pub async fn get_list(
-    redis: &crate::redis::RedisClient,
+    db: &dyn SeriesReader,
     ts_name: &str,
     from: NaiveDateTime,
     to: NaiveDateTime,
 ) -> anyhow::Result<Vec<MarketData>> {
-    let res = redis
+    let res = db
         .load(
             ts_name,
             Some(from.and_utc().timestamp_millis()),
@@ -102,28 +256,25 @@ pub async fn get_list(
         .await
         .map_err(|e| ApiError::Server(e.to_string()))?;
     tracing::debug!("{} len res {} - {}-{}", ts_name, res.len(), from, to);
-    Ok(res)
+    Ok(res.iter().map(MarketData::from).collect())
 }
 
-#[instrument(skip(redis, ts_name, from, to))]
+#[instrument(skip(db, ts_name, from, to))]
 async fn get_avg(
-    redis: &crate::redis::RedisClient,
+    db: &dyn SeriesReader,
     ts_name: &str,
     from: NaiveDateTime,
     to: NaiveDateTime,
 ) -> anyhow::Result<Option<f64>> {
-    let res = redis
-        .load(
+    let res = db
+        .aggregate(
             ts_name,
-            Some(from.and_utc().timestamp_millis()),
-            Some(to.and_utc().timestamp_millis()),
+            from.and_utc().timestamp_millis(),
+            to.and_utc().timestamp_millis(),
+            Statistic::Avg,
         )
         .await
         .map_err(|e| ApiError::Server(e.to_string()))?;
-    log::debug!("{} len res {} - {}-{}", ts_name, res.len(), from, to);
-    if res.is_empty() {
-        return Ok(None);
-    }
-    let sum = res.iter().map(|v| v.price).sum::<f64>();
-    Ok(Some(sum / res.len() as f64))
+    log::debug!("{} avg {:?} - {}-{}", ts_name, res, from, to);
+    Ok(res)
 }
@@ -1,16 +1,24 @@
 mod data;
+mod demo;
 mod handlers;
 mod metrics;
 mod otel;
-mod redis;
 
 use axum::extract::DefaultBodyLimit;
-use axum::routing::get;
+use axum::routing::{get, post};
 use axum::{middleware, Router};
-use clap::{command, Parser};
+use chrono::Utc;
+use clap::Parser;
 use data::Service;
-use deadpool_redis::Runtime;
+use emarket::bill;
+use emarket::memory::MemoryStorage;
+use emarket::redis_pool::{RedisArgs, RedisPool};
+use emarket::redis_reader::RedisReader;
+use emarket::sqlite::SqliteStorage;
+use emarket::storage::{storage_url, SeriesReader, StorageUrl};
+use emarket::tariff::Tariffs;
 use metrics::Metrics;
+use std::path::PathBuf;
 use std::process;
 use std::time::Duration;
 use std::{error::Error, sync::Arc};
@@ -23,7 +31,6 @@ use tower_http::trace::TraceLayer;
 
 use tokio::signal::unix::{signal, SignalKind};
 
-use crate::redis::RedisClient;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
@@ -36,8 +43,30 @@ struct Args {
     /// Redis url
     #[arg(long, short, env, default_value = "")]
     redis_url: String,
+    /// Storage url: redis://host:port, sqlite:///path/to/file.db or memory://, defaults to the redis url
+    #[arg(long, env)]
+    storage: Option<String>,
+    #[command(flatten)]
+    redis: RedisArgs,
+    /// Serve synthetic prices from memory, no storage is needed
+    #[arg(long, env, default_value_t = false)]
+    demo: bool,
+    /// Zone label of the served series
+    #[arg(long, env, default_value = "LT")]
+    zone: String,
+    /// Retail tariffs JSON file served by `tariff=<id>`, see tariffs.example.json
+    #[arg(long, env)]
+    tariffs: Option<PathBuf>,
+    /// Fixed price offers JSON file compared by `/cost`: [{"id": "fixed", "price": 0.15, "monthly_fee": 1}]
+    #[arg(long, env)]
+    offers: Option<PathBuf>,
 }
 
+/// days of the demo history, enough for the monthly and 30 day summaries
+const DEMO_DAYS: i64 = 400;
+/// max body of a `/cost` upload, a year of 15 minute consumption
+const COST_BODY_LIMIT: usize = 8 * 1024 * 1024;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let _guard = TracerGuard;
@@ -64,7 +93,15 @@ async fn main_int(args: Args) -> Result<(), Box<dyn Error>> {
     tracing::info!("Starting Importer service");
     tracing::info!(version = env!("CARGO_APP_VERSION"));
     tracing::info!(port = args.port);
-    tracing::info!(url = args.redis_url, "redis");
+    let storage = if args.demo {
+        StorageUrl::Memory
+    } else {
+        storage_url(args.storage.as_deref(), &args.redis_url).unwrap_or_else(|err| {
+            log::error!("{err}");
+            process::exit(1)
+        })
+    };
+    tracing::info!(storage = ?storage);
 
     let cancel_token = CancellationToken::new();
 
@@ -83,19 +120,51 @@ async fn main_int(args: Args) -> Result<(), Box<dyn Error>> {
         log::debug!("expected drop tx_close");
     });
 
-    let pool = deadpool_redis::Config::from_url(&args.redis_url)
-        .create_pool(Some(Runtime::Tokio1))
-        .unwrap_or_else(|err| {
-            log::error!("redis poll init: {err}");
+    let db: Arc<dyn SeriesReader> = match storage {
+        StorageUrl::Redis(url) => {
+            let pool = RedisPool::new(&url, &args.redis)
+                .await
+                .unwrap_or_else(|err| {
+                    log::error!("redis poll init: {err}");
+                    process::exit(1)
+                });
+            Arc::new(RedisReader::new(pool).await.unwrap_or_else(|err| {
+                log::error!("redis client init: {err}");
+                process::exit(1)
+            }))
+        }
+        StorageUrl::Sqlite(path) => Arc::new(SqliteStorage::open(&path).unwrap_or_else(|err| {
+            log::error!("sqlite init: {err}");
             process::exit(1)
-        });
-
-    let db = RedisClient::new(pool).await.unwrap_or_else(|err| {
-        log::error!("redis client init: {err}");
-        process::exit(1)
-    });
-
-    let srv = Arc::new(RwLock::new(Service { redis: db }));
+        })),
+        StorageUrl::Memory => {
+            let storage = MemoryStorage::new();
+            if args.demo {
+                demo::fill(&storage, &args.zone, Utc::now().naive_utc(), DEMO_DAYS)?;
+            } else {
+                tracing::warn!("memory storage is empty");
+            }
+            Arc::new(storage)
+        }
+    };
+
+    let tariffs = match &args.tariffs {
+        Some(path) => Tariffs::load(path).map_err(|e| format!("tariffs {}: {e}", path.display()))?,
+        None => Tariffs::default(),
+    };
+    tracing::info!(tariffs = ?tariffs.ids());
+    let offers = match &args.offers {
+        Some(path) => {
+            bill::load_offers(path).map_err(|e| format!("offers {}: {e}", path.display()))?
+        }
+        None => vec![],
+    };
+    tracing::info!(offers = offers.len());
+    let srv = Arc::new(RwLock::new(
+        Service::new(db, &args.zone)
+            .with_tariffs(tariffs)
+            .with_offers(offers),
+    ));
 
     let helper_router = axum::Router::new()
         .route("/live", get(handlers::live::handler))
@@ -106,6 +175,15 @@ async fn main_int(args: Args) -> Result<(), Box<dyn Error>> {
         .route("/summary", get(handlers::summary::handler))
         .route("/prices", get(handlers::prices::handler))
         .route("/np/now", get(handlers::now::handler))
+        .route("/series", get(handlers::series::handler))
+        .route("/revisions", get(handlers::revisions::handler))
+        .route("/quality", get(handlers::quality::handler))
+        .route("/cheapest/window", get(handlers::cheapest::window))
+        .route("/cheapest/slots", get(handlers::cheapest::slots))
+        .route(
+            "/cost",
+            post(handlers::cost::handler).layer(DefaultBodyLimit::max(COST_BODY_LIMIT)),
+        )
         .with_state(srv.clone())
         .layer(middleware::from_fn(move |req, next| {
             let mc = metrics.clone();